@@ -0,0 +1,103 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use xml_c14n::{canonicalize_xml, CanonicalizationOptions, CanonicalizerContext};
+
+/// Roughly the size of a SAML attribute statement
+const SMALL_DOCUMENT: &str = r#"<saml:AttributeStatement xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion">
+  <saml:Attribute Name="mail" NameFormat="urn:oasis:names:tc:SAML:2.0:attrname-format:basic">
+    <saml:AttributeValue>someone@example.com</saml:AttributeValue>
+  </saml:Attribute>
+  <saml:Attribute Name="eduPersonAffiliation">
+    <saml:AttributeValue>member</saml:AttributeValue>
+    <saml:AttributeValue>staff</saml:AttributeValue>
+  </saml:Attribute>
+</saml:AttributeStatement>"#;
+
+fn small_documents(c: &mut Criterion) {
+    c.bench_function("canonicalize small document", |b| {
+        b.iter(|| {
+            canonicalize_xml(
+                black_box(SMALL_DOCUMENT),
+                CanonicalizationOptions::default(),
+            )
+        })
+    });
+}
+
+/// Many documents with the same names, each too large for the free functions to reuse a parser context
+fn similar_documents(c: &mut Criterion) {
+    let documents: Vec<String> = (0..100)
+        .map(|i| {
+            let statement = SMALL_DOCUMENT.replace("someone", &format!("user{i}"));
+            format!("<batch>{}</batch>", statement.repeat(10))
+        })
+        .collect();
+
+    c.bench_function("canonicalize similar documents separately", |b| {
+        b.iter(|| {
+            for document in &documents {
+                canonicalize_xml(black_box(document), CanonicalizationOptions::default()).unwrap();
+            }
+        })
+    });
+
+    c.bench_function(
+        "canonicalize similar documents with a shared context",
+        |b| {
+            b.iter(|| {
+                let mut context = CanonicalizerContext::new();
+                for document in &documents {
+                    context
+                        .canonicalize(black_box(document), CanonicalizationOptions::default())
+                        .unwrap();
+                }
+            })
+        },
+    );
+}
+
+/// Small documents canonicalized in a loop on several threads at once, as in a signing server
+fn concurrent_documents(c: &mut Criterion) {
+    const THREADS: usize = 4;
+    const DOCUMENTS_PER_THREAD: usize = 250;
+
+    let run = |canonicalize: fn(&str) -> String| {
+        std::thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(move || {
+                    for _ in 0..DOCUMENTS_PER_THREAD {
+                        black_box(canonicalize(black_box(SMALL_DOCUMENT)));
+                    }
+                });
+            }
+        })
+    };
+
+    c.bench_function("canonicalize concurrently with free functions", |b| {
+        b.iter(|| {
+            run(|document| canonicalize_xml(document, CanonicalizationOptions::default()).unwrap())
+        })
+    });
+
+    c.bench_function(
+        "canonicalize concurrently with thread-local contexts",
+        |b| {
+            b.iter(|| {
+                run(|document| {
+                    CanonicalizerContext::with_thread_local(|context| {
+                        context
+                            .canonicalize(document, CanonicalizationOptions::default())
+                            .unwrap()
+                    })
+                })
+            })
+        },
+    );
+}
+
+criterion_group!(
+    benches,
+    small_documents,
+    similar_documents,
+    concurrent_documents
+);
+criterion_main!(benches);
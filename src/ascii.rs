@@ -0,0 +1,202 @@
+//! Checking or making canonical output ASCII-only, for transports that can't carry anything else
+
+use crate::{canonicalize_xml, CanonicalizationError, CanonicalizationOptions};
+
+/// What [canonicalize_ascii] does with characters outside ASCII
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum NonAsciiHandling {
+    /// Fail with [CanonicalizationError::NonAsciiCharacter] at the first one, so the output is exactly the canonical form
+    Reject,
+    /// Replace the ones in text and attribute values with character references like `&#xE9;`
+    ///
+    /// **This is a transport encoding, not canonicalization**: the result isn't canonical XML, and its bytes (and so any
+    /// digest or signature over them) differ from the canonical form's. Canonicalizing it again gives back the canonical
+    /// form, so a verifier has to do that first. Character references mean nothing in names, comments and processing
+    /// instructions, so characters there still fail with [CanonicalizationError::NonAsciiCharacter].
+    EscapeAsCharacterReferences,
+}
+
+/// Parse specified XML document, canonicalize it, and make sure the result is ASCII-only, as `handling` says
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_ascii, CanonicalizationError, CanonicalizationOptions, NonAsciiHandling};
+///
+/// let document = "<a title='café'>naïve</a>";
+///
+/// let rejected = canonicalize_ascii(document, CanonicalizationOptions::default(), NonAsciiHandling::Reject);
+/// assert!(matches!(rejected, Err(CanonicalizationError::NonAsciiCharacter { character: 'é', .. })));
+///
+/// let escaped = canonicalize_ascii(
+///     document,
+///     CanonicalizationOptions::default(),
+///     NonAsciiHandling::EscapeAsCharacterReferences,
+/// )
+/// .unwrap();
+/// assert_eq!(escaped, r#"<a title="caf&#xE9;">na&#xEF;ve</a>"#);
+/// ```
+pub fn canonicalize_ascii(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+    handling: NonAsciiHandling,
+) -> Result<String, CanonicalizationError> {
+    let canonical = canonicalize_xml(document, options)?;
+    match handling {
+        NonAsciiHandling::Reject => {
+            check_ascii(&canonical, 0)?;
+            Ok(canonical)
+        }
+        NonAsciiHandling::EscapeAsCharacterReferences => escape_non_ascii(&canonical),
+    }
+}
+
+/// Fail at the first character of `piece` outside ASCII, `piece` starting at byte `offset` of the canonical form
+fn check_ascii(piece: &str, offset: usize) -> Result<(), CanonicalizationError> {
+    match piece
+        .char_indices()
+        .find(|(_, character)| !character.is_ascii())
+    {
+        Some((position, character)) => Err(CanonicalizationError::NonAsciiCharacter {
+            character,
+            position: offset + position,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// See [NonAsciiHandling::EscapeAsCharacterReferences]
+fn escape_non_ascii(canonical: &str) -> Result<String, CanonicalizationError> {
+    let mut escaped = String::with_capacity(canonical.len() + canonical.len() / 8);
+    let escape = |escaped: &mut String, text: &str| {
+        for character in text.chars() {
+            if character.is_ascii() {
+                escaped.push(character);
+            } else {
+                escaped.push_str(&format!("&#x{:X};", u32::from(character)));
+            }
+        }
+    };
+
+    let mut pos = 0;
+    while pos < canonical.len() {
+        let rest = &canonical[pos..];
+        let markup_end = if rest.starts_with("<!--") {
+            Some(rest.find("-->").map_or(rest.len(), |end| end + 3))
+        } else if rest.starts_with("<?") {
+            Some(rest.find("?>").map_or(rest.len(), |end| end + 2))
+        } else {
+            None
+        };
+
+        if let Some(end) = markup_end {
+            check_ascii(&rest[..end], pos)?;
+            escaped.push_str(&rest[..end]);
+            pos += end;
+        } else if rest.starts_with('<') {
+            // a start or end tag, whose attribute values never contain `"` (or `<`, but may contain `>`)
+            let mut in_value = false;
+            let end = rest
+                .find(|character| {
+                    in_value ^= character == '"';
+                    character == '>' && !in_value
+                })
+                .map_or(rest.len(), |end| end + 1);
+
+            // between the quotes are attribute values, outside of them names
+            let mut offset = pos;
+            for (index, piece) in rest[..end].split('"').enumerate() {
+                if index > 0 {
+                    escaped.push('"');
+                }
+                if index % 2 == 1 {
+                    escape(&mut escaped, piece);
+                } else {
+                    check_ascii(piece, offset)?;
+                    escaped.push_str(piece);
+                }
+                offset += piece.len() + 1;
+            }
+            pos += end;
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            escape(&mut escaped, &rest[..end]);
+            pos += end;
+        }
+    }
+
+    Ok(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "<a title='café &gt; thé'>naïve <b>€</b><!-- ok --><?pi ok?></a>";
+
+    #[test]
+    fn reject() {
+        assert_eq!(
+            canonicalize_ascii(INPUT, Default::default(), NonAsciiHandling::Reject),
+            Err(CanonicalizationError::NonAsciiCharacter {
+                character: 'é',
+                position: 13
+            })
+        );
+        assert_eq!(
+            canonicalize_ascii(
+                "<a b='c'>d</a>",
+                Default::default(),
+                NonAsciiHandling::Reject
+            )
+            .unwrap(),
+            r#"<a b="c">d</a>"#
+        );
+    }
+
+    #[test]
+    fn escape_as_character_references() {
+        let options = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
+        let escaped = canonicalize_ascii(
+            INPUT,
+            options.clone(),
+            NonAsciiHandling::EscapeAsCharacterReferences,
+        )
+        .unwrap();
+        assert_eq!(
+            escaped,
+            r#"<a title="caf&#xE9; > th&#xE9;">na&#xEF;ve <b>&#x20AC;</b><!-- ok --><?pi ok?></a>"#
+        );
+        assert!(escaped.is_ascii());
+
+        // only a transport encoding: canonicalizing again undoes it
+        assert_eq!(
+            crate::canonicalize_xml(&escaped, options.clone()).unwrap(),
+            crate::canonicalize_xml(INPUT, options.clone()).unwrap()
+        );
+
+        // character references would change the meaning of names, comments and processing instructions
+        for (input, character, position) in [
+            ("<é/>", 'é', 1),
+            ("<a b='é' ë='1'/>", 'ë', 10),
+            ("<a>é<!-- ë --></a>", 'ë', 10),
+            ("<a><?pi ë?></a>", 'ë', 8),
+        ] {
+            assert_eq!(
+                canonicalize_ascii(
+                    input,
+                    options.clone(),
+                    NonAsciiHandling::EscapeAsCharacterReferences
+                ),
+                Err(CanonicalizationError::NonAsciiCharacter {
+                    character,
+                    position
+                }),
+                "{input}"
+            );
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Memoizing canonicalization results, enabled by the `cache` feature
+
+use crate::{canonicalize_xml, CanonicalizationError, CanonicalizationOptions};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+type CanonicalizeFn = fn(&str, CanonicalizationOptions) -> Result<String, CanonicalizationError>;
+
+struct Entry {
+    document: String,
+    options: CanonicalizationOptions,
+    canonical: String,
+    /// Value of [CachedCanonicalizer::clock] when last used
+    last_used: u64,
+}
+
+/// Canonicalizes documents, remembering the results for the most recently used (document, options) pairs
+///
+/// Useful when the same documents come in over and over, e.g. retried requests.
+/// Only successful results are cached, errors are recomputed every time.
+/// Entries are keyed by a hash of the document and options, but the document is compared in full before a cached
+/// result is returned, so hash collisions can't return the wrong canonical form.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{CachedCanonicalizer, CanonicalizationOptions};
+///
+/// let mut canonicalizer = CachedCanonicalizer::new(100);
+///
+/// let first = canonicalizer.canonicalize("<a/>", CanonicalizationOptions::default()).unwrap();
+/// // not parsed again
+/// let second = canonicalizer.canonicalize("<a/>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(first, "<a></a>");
+/// assert_eq!(first, second);
+/// ```
+pub struct CachedCanonicalizer {
+    capacity: usize,
+    entries: HashMap<u64, Entry>,
+    /// Incremented on every lookup, to find the least recently used entry
+    clock: u64,
+    canonicalize: CanonicalizeFn,
+}
+
+impl CachedCanonicalizer {
+    /// Create a cache holding up to `capacity` results (none at all if 0)
+    pub fn new(capacity: usize) -> Self {
+        Self::with_canonicalize(capacity, |document, options| {
+            canonicalize_xml(document, options)
+        })
+    }
+
+    fn with_canonicalize(capacity: usize, canonicalize: CanonicalizeFn) -> Self {
+        CachedCanonicalizer {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+            canonicalize,
+        }
+    }
+
+    /// Same as [canonicalize_xml], but returns the cached result if this document was canonicalized with these options recently
+    pub fn canonicalize(
+        &mut self,
+        document: impl AsRef<str>,
+        options: CanonicalizationOptions,
+    ) -> Result<String, CanonicalizationError> {
+        let document = document.as_ref();
+        self.clock += 1;
+
+        let mut hasher = DefaultHasher::new();
+        (document, &options).hash(&mut hasher);
+        let key = hasher.finish();
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if entry.document == document && entry.options == options {
+                entry.last_used = self.clock;
+                return Ok(entry.canonical.clone());
+            }
+        }
+
+        let canonical = (self.canonicalize)(document, options.clone())?;
+        if self.capacity == 0 {
+            return Ok(canonical);
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let least_recently_used = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(&key, _)| key);
+            if let Some(evicted) = least_recently_used {
+                self.entries.remove(&evicted);
+            }
+        }
+        self.entries.insert(
+            key,
+            Entry {
+                document: document.to_owned(),
+                options,
+                canonical: canonical.clone(),
+                last_used: self.clock,
+            },
+        );
+
+        Ok(canonical)
+    }
+
+    /// Number of cached results
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Forget all cached results
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CanonicalizationMode;
+    use std::cell::Cell;
+
+    thread_local! {
+        static PARSES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn counting_canonicalize(
+        document: &str,
+        options: CanonicalizationOptions,
+    ) -> Result<String, CanonicalizationError> {
+        PARSES.with(|parses| parses.set(parses.get() + 1));
+        canonicalize_xml(document, options)
+    }
+
+    fn parses() -> usize {
+        PARSES.with(Cell::get)
+    }
+
+    #[test]
+    fn second_call_is_cached() {
+        let mut canonicalizer = CachedCanonicalizer::with_canonicalize(2, counting_canonicalize);
+        let inclusive = CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_1,
+            ..Default::default()
+        };
+
+        let input = r#"<a xmlns:x="urn:x"/>"#;
+        let first = canonicalizer.canonicalize(input, Default::default());
+        let second = canonicalizer.canonicalize(input, Default::default());
+        assert_eq!(first, Ok("<a></a>".to_owned()));
+        assert_eq!(first, second);
+        assert_eq!(parses(), 1);
+
+        // different options are a different entry
+        assert_eq!(
+            canonicalizer.canonicalize(input, inclusive.clone()),
+            Ok(r#"<a xmlns:x="urn:x"></a>"#.to_owned())
+        );
+        assert_eq!(parses(), 2);
+        assert_eq!(canonicalizer.len(), 2);
+
+        // errors aren't cached
+        assert!(canonicalizer
+            .canonicalize("<a>", Default::default())
+            .is_err());
+        assert!(canonicalizer
+            .canonicalize("<a>", Default::default())
+            .is_err());
+        assert_eq!(parses(), 4);
+        assert_eq!(canonicalizer.len(), 2);
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let mut canonicalizer = CachedCanonicalizer::with_canonicalize(2, counting_canonicalize);
+
+        canonicalizer
+            .canonicalize("<a/>", Default::default())
+            .unwrap();
+        canonicalizer
+            .canonicalize("<b/>", Default::default())
+            .unwrap();
+        // a is now more recently used than b
+        canonicalizer
+            .canonicalize("<a/>", Default::default())
+            .unwrap();
+        canonicalizer
+            .canonicalize("<c/>", Default::default())
+            .unwrap();
+        assert_eq!(parses(), 3);
+
+        canonicalizer
+            .canonicalize("<a/>", Default::default())
+            .unwrap();
+        assert_eq!(parses(), 3);
+        canonicalizer
+            .canonicalize("<b/>", Default::default())
+            .unwrap();
+        assert_eq!(parses(), 4);
+        assert_eq!(canonicalizer.len(), 2);
+
+        canonicalizer.clear();
+        assert!(canonicalizer.is_empty());
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut canonicalizer = CachedCanonicalizer::with_canonicalize(0, counting_canonicalize);
+        canonicalizer
+            .canonicalize("<a/>", Default::default())
+            .unwrap();
+        canonicalizer
+            .canonicalize("<a/>", Default::default())
+            .unwrap();
+        assert_eq!(parses(), 2);
+        assert!(canonicalizer.is_empty());
+    }
+}
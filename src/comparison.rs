@@ -0,0 +1,119 @@
+//! Canonical-like forms for comparing documents that differ in ways that don't matter to their readers
+
+use crate::document::Document;
+use crate::{tree, CanonicalizationError, CanonicalizationOptions};
+
+/// How [canonicalize_for_comparison] deviates from canonical XML before canonicalizing
+///
+/// Everything is off by default, which gives the canonical form (wrapped in a [ComparisonForm]).
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct ComparisonOptions {
+    /// If true, remove text nodes that contain only whitespace from elements that also have child elements, like the
+    /// indentation of pretty-printed documents
+    ///
+    /// Text-only elements like `<a> </a>` keep their whitespace, and so does everything under `xml:space="preserve"`.
+    /// CDATA sections are never removed.
+    pub strip_insignificant_whitespace: bool,
+}
+
+/// The output of [canonicalize_for_comparison]
+///
+/// **This is not canonical XML**, only compare it with other forms produced with the same options, and never hash or sign
+/// it. It's a separate type so that it can't be passed where canonical output is expected by mistake.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct ComparisonForm(String);
+
+impl ComparisonForm {
+    /// The form as a string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The form as a string, consuming it
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+/// Parse specified XML document and canonicalize it after the changes `comparison` asks for, for diffing documents
+/// that only differ in e.g. pretty-printing
+///
+/// For comparison only: the result is not canonical XML and not what a verifier computes, so it's not for signing.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_for_comparison, CanonicalizationOptions, ComparisonOptions};
+///
+/// let comparison = ComparisonOptions {
+///     strip_insignificant_whitespace: true,
+/// };
+/// let compact = canonicalize_for_comparison("<a><b>1</b></a>", CanonicalizationOptions::default(), comparison.clone()).unwrap();
+/// let indented = canonicalize_for_comparison("<a>\n  <b>1</b>\n</a>", CanonicalizationOptions::default(), comparison).unwrap();
+///
+/// assert_eq!(compact, indented);
+/// assert_eq!(indented.as_str(), "<a><b>1</b></a>");
+/// ```
+pub fn canonicalize_for_comparison(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+    comparison: ComparisonOptions,
+) -> Result<ComparisonForm, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let parsed = Document::read_for(document.as_bytes(), None, &options)?;
+
+    if comparison.strip_insignificant_whitespace {
+        // SAFETY: the document is valid, and only this function is looking at it
+        unsafe { tree::remove_whitespace_between_elements(parsed.root_element()) };
+    }
+    parsed.canonicalize(options).map(ComparisonForm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonicalize_xml;
+
+    const STRIP: ComparisonOptions = ComparisonOptions {
+        strip_insignificant_whitespace: true,
+    };
+
+    #[test]
+    fn strip_insignificant_whitespace() {
+        let input = "<a>\n  <b> </b>\n  <c>\t<d/>text <e/>\r\n</c>\n  <f xml:space='preserve'>\n    <g/>\n  </f><![CDATA[ ]]></a>";
+
+        assert_eq!(
+            canonicalize_for_comparison(input, Default::default(), STRIP)
+                .unwrap()
+                .as_str(),
+            "<a><b> </b><c><d></d>text <e></e></c><f xml:space=\"preserve\">\n    <g></g>\n  </f> </a>"
+        );
+    }
+
+    #[test]
+    fn off_by_default() {
+        let input = "<a>\n  <b/>\n</a>";
+        for (keep_comments, mode) in [
+            (false, crate::CanonicalizationMode::Canonical1_0),
+            (true, Default::default()),
+        ] {
+            let options = CanonicalizationOptions {
+                mode,
+                keep_comments,
+                ..Default::default()
+            };
+            assert_eq!(
+                canonicalize_for_comparison(input, options.clone(), Default::default())
+                    .unwrap()
+                    .into_string(),
+                canonicalize_xml(input, options).unwrap()
+            );
+        }
+
+        assert!(matches!(
+            canonicalize_for_comparison("<a>", Default::default(), STRIP),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+}
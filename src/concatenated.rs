@@ -0,0 +1,164 @@
+//! Splitting streams of concatenated XML documents
+
+/// Whitespace as defined by XML
+fn is_xml_whitespace(char: char) -> bool {
+    matches!(char, ' ' | '\t' | '\r' | '\n')
+}
+
+/// What a piece of markup does to the element nesting
+enum Markup {
+    StartTag,
+    EmptyElementTag,
+    EndTag,
+    /// Comments, PIs, CDATA sections, the XML and document type declarations
+    Other,
+}
+
+/// Find where the markup at the start of `rest` (which starts with `<`) ends, and what kind of markup it is
+///
+/// Returns [None] if the markup isn't terminated.
+fn markup_end(rest: &str) -> Option<(usize, Markup)> {
+    let after = |delimiter: &str, skip: usize| {
+        rest[skip..]
+            .find(delimiter)
+            .map(|offset| skip + offset + delimiter.len())
+    };
+
+    if rest.starts_with("<!--") {
+        after("-->", 4).map(|end| (end, Markup::Other))
+    } else if rest.starts_with("<![CDATA[") {
+        after("]]>", 9).map(|end| (end, Markup::Other))
+    } else if rest.starts_with("<?") {
+        after("?>", 2).map(|end| (end, Markup::Other))
+    } else if rest.starts_with("</") {
+        after(">", 2).map(|end| (end, Markup::EndTag))
+    } else if rest.starts_with("<!") {
+        // document type declaration, which may have an internal subset with `>` in it
+        tag_end(rest, true).map(|end| (end, Markup::Other))
+    } else {
+        let end = tag_end(rest, false)?;
+        let kind = if rest[..end].ends_with("/>") {
+            Markup::EmptyElementTag
+        } else {
+            Markup::StartTag
+        };
+        Some((end, kind))
+    }
+}
+
+/// Find the `>` ending the tag at the start of `rest`, skipping quoted strings (and `[...]` if `brackets`)
+fn tag_end(rest: &str, brackets: bool) -> Option<usize> {
+    let mut quote = None;
+    let mut bracket_depth = 0usize;
+
+    for (index, char) in rest.char_indices().skip(1) {
+        match (quote, char) {
+            (Some(open), _) if char == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(char),
+            (None, '[') if brackets => bracket_depth += 1,
+            (None, ']') if brackets => bracket_depth = bracket_depth.saturating_sub(1),
+            (None, '>') if bracket_depth == 0 => return Some(index + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split a stream of concatenated XML documents into the individual documents
+///
+/// A document ends once its document element is closed, but keeps any comments and PIs after it.
+/// The next document starts at the next XML declaration, document type declaration or start tag.
+/// Whitespace between documents is dropped. If the input is malformed such that a boundary can't be found,
+/// everything from there on is returned as the last document, so that parsing it reports the problem.
+pub(crate) fn split_documents(input: &str) -> Vec<&str> {
+    let mut documents = vec![];
+    let mut document_start = 0;
+    let mut depth = 0usize;
+    let mut seen_root = false;
+    let mut position = 0;
+
+    while let Some(offset) = input[position..].find('<') {
+        let markup_start = position + offset;
+        let rest = &input[markup_start..];
+
+        let is_xml_declaration = rest
+            .strip_prefix("<?xml")
+            .is_some_and(|after| after.starts_with(is_xml_whitespace) || after.starts_with("?>"));
+        let is_element =
+            !(rest.starts_with("</") || rest.starts_with("<!") || rest.starts_with("<?"));
+        let begins_document = is_xml_declaration || rest.starts_with("<!DOCTYPE") || is_element;
+        if seen_root && depth == 0 && begins_document {
+            documents.push(&input[document_start..markup_start]);
+            document_start = markup_start;
+            seen_root = false;
+        }
+
+        let Some((length, kind)) = markup_end(rest) else {
+            break;
+        };
+
+        match kind {
+            Markup::StartTag => {
+                depth += 1;
+                seen_root = true;
+            }
+            Markup::EmptyElementTag => seen_root = true,
+            Markup::EndTag => depth = depth.saturating_sub(1),
+            Markup::Other => {}
+        }
+
+        position = markup_start + length;
+    }
+    documents.push(&input[document_start..]);
+
+    documents
+        .into_iter()
+        .map(|document| document.trim_matches(is_xml_whitespace))
+        .filter(|document| !document.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boundaries() {
+        let input = r#"<?xml version="1.0"?>
+<a x='1>2'><b/><![CDATA[</a><c>]]><!-- </a> --></a>
+<!-- trailing comment of a -->
+
+<?xml version="1.0"?><!DOCTYPE d [<!ELEMENT d ANY>]><d/><?pi of d?>
+<e>
+  <f></f>
+</e>
+
+"#;
+
+        assert_eq!(
+            split_documents(input),
+            [
+                "<?xml version=\"1.0\"?>\n<a x='1>2'><b/><![CDATA[</a><c>]]><!-- </a> --></a>\n<!-- trailing comment of a -->",
+                "<?xml version=\"1.0\"?><!DOCTYPE d [<!ELEMENT d ANY>]><d/><?pi of d?>",
+                "<e>\n  <f></f>\n</e>",
+            ]
+        );
+    }
+
+    #[test]
+    fn single_and_empty() {
+        assert_eq!(split_documents("<a/>"), ["<a/>"]);
+        assert_eq!(split_documents(" \n "), [] as [&str; 0]);
+        assert_eq!(split_documents(""), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn unterminated() {
+        assert_eq!(
+            split_documents("<a/><b><!-- <c/>"),
+            ["<a/>", "<b><!-- <c/>"]
+        );
+        assert_eq!(split_documents("<a/><b x='>"), ["<a/>", "<b x='>"]);
+    }
+}
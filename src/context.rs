@@ -0,0 +1,309 @@
+//! Reusable state for canonicalizing many documents
+
+use crate::document::{Document, ReusedParser};
+use crate::{CanonicalizationError, CanonicalizationOptions};
+use std::cell::RefCell;
+
+thread_local! {
+    static THREAD_CONTEXT: RefCell<CanonicalizerContext> = RefCell::new(CanonicalizerContext::new());
+}
+
+/// State kept between calls, for canonicalizing many documents with minimal per-call overhead, e.g. in a signing server
+///
+/// Every document is parsed with the same libxml2 parser context, regardless of its size
+/// (the free functions only reuse one for small documents). Parsing is done with the same protections as everywhere
+/// else in this crate: entities are never substituted, and external resources are never fetched over the network.
+///
+/// The context's string dictionary is shared by all documents parsed with it, so element and attribute names that
+/// recur across documents (as in homogeneous feeds) are only allocated once. Documents hold a reference to the
+/// dictionary, so they stay valid after the context is dropped. To keep memory bounded, the context is replaced
+/// once its dictionary holds many thousands of distinct names.
+///
+/// A context is confined to the thread that created it, as is the libxml2 parser context inside it, so it's neither
+/// `Send` nor `Sync`. For one context per thread, e.g. in a thread pool, use [CanonicalizerContext::with_thread_local].
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>(_: T) {}
+///
+/// assert_send(xml_c14n::CanonicalizerContext::new());
+/// ```
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{CanonicalizationOptions, CanonicalizerContext};
+///
+/// let mut context = CanonicalizerContext::new();
+///
+/// for (document, expected) in [("<a/>", "<a></a>"), ("<b/>", "<b></b>")] {
+///     let canonicalized = context.canonicalize(document, CanonicalizationOptions::default()).unwrap();
+///     assert_eq!(canonicalized, expected);
+/// }
+/// ```
+#[derive(Default)]
+pub struct CanonicalizerContext {
+    parser: ReusedParser,
+}
+
+impl CanonicalizerContext {
+    /// Create a context. The libxml2 parser context is only allocated when the first document is parsed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `f` with the current thread's context, which is created on first use and freed when the thread exits
+    ///
+    /// If the thread's context is already in use (i.e. when called from within `f`), a fresh context is used instead.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use xml_c14n::{CanonicalizationOptions, CanonicalizerContext};
+    ///
+    /// let handles: Vec<_> = (0..4)
+    ///     .map(|i| {
+    ///         std::thread::spawn(move || {
+    ///             CanonicalizerContext::with_thread_local(|context| {
+    ///                 context.canonicalize(format!("<a{i}/>"), CanonicalizationOptions::default())
+    ///             })
+    ///         })
+    ///     })
+    ///     .collect();
+    ///
+    /// for (i, handle) in handles.into_iter().enumerate() {
+    ///     assert_eq!(handle.join().unwrap().unwrap(), format!("<a{i}></a{i}>"));
+    /// }
+    /// ```
+    pub fn with_thread_local<R>(f: impl FnOnce(&mut CanonicalizerContext) -> R) -> R {
+        let mut f = Some(f);
+        let reused = THREAD_CONTEXT.try_with(|context| {
+            let mut context = context.try_borrow_mut().ok()?;
+            f.take().map(|f| f(&mut context))
+        });
+        match (reused, f) {
+            (Ok(Some(result)), _) => result,
+            // being destroyed or already borrowed
+            (_, Some(f)) => f(&mut CanonicalizerContext::new()),
+            (_, None) => unreachable!("f is only taken when it's run"),
+        }
+    }
+
+    /// Parse specified XML document and canonicalize it, like [canonicalize_xml](crate::canonicalize_xml)
+    pub fn canonicalize(
+        &mut self,
+        document: impl AsRef<str>,
+        options: CanonicalizationOptions,
+    ) -> Result<String, CanonicalizationError> {
+        let document = document.as_ref();
+        options.check_input_size(document.len())?;
+
+        Document::read_with(
+            document.as_bytes(),
+            None,
+            options.parse_options(),
+            Some(&mut self.parser),
+        )?
+        .coalesced_for(&options)
+        .canonicalize(options)
+    }
+}
+
+/// Canonicalizes documents with options fixed when it's created, reusing a [CanonicalizerContext] for all of them
+///
+/// The object-oriented counterpart of [canonicalize_xml](crate::canonicalize_xml): configure it once, e.g. when a signing
+/// server starts, and call [canonicalize](Self::canonicalize) for every document. The options are checked with
+/// [CanonicalizationOptions::validate] up front, so a bad configuration fails at startup rather than on the first
+/// document. The parser context is reused as described for [CanonicalizerContext], with the same protections against
+/// entity substitution and network access.
+///
+/// Like the context, a canonicalizer is confined to the thread that created it (neither `Send` nor `Sync`), and
+/// documents are canonicalized one at a time. For a thread pool, create one per thread, e.g. in a `thread_local!`.
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>(_: T) {}
+///
+/// assert_send(xml_c14n::Canonicalizer::new(Default::default()).unwrap());
+/// ```
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{CanonicalizationOptions, Canonicalizer};
+///
+/// let canonicalizer = Canonicalizer::new(CanonicalizationOptions {
+///     keep_comments: true,
+///     ..Default::default()
+/// })
+/// .unwrap();
+///
+/// assert_eq!(canonicalizer.canonicalize("<a><!-- b --></a>").unwrap(), "<a><!-- b --></a>");
+/// assert_eq!(canonicalizer.canonicalize("<c/>").unwrap(), "<c></c>");
+/// ```
+pub struct Canonicalizer {
+    options: CanonicalizationOptions,
+    context: RefCell<CanonicalizerContext>,
+}
+
+impl Canonicalizer {
+    /// Create a canonicalizer, failing like [CanonicalizationOptions::validate] if the options don't make sense together
+    pub fn new(options: CanonicalizationOptions) -> Result<Self, CanonicalizationError> {
+        options.validate()?;
+        Ok(Canonicalizer {
+            options,
+            context: RefCell::new(CanonicalizerContext::new()),
+        })
+    }
+
+    /// The options documents are canonicalized with
+    pub fn options(&self) -> &CanonicalizationOptions {
+        &self.options
+    }
+
+    /// Parse specified XML document and canonicalize it, like [canonicalize_xml](crate::canonicalize_xml) with the
+    /// canonicalizer's options
+    pub fn canonicalize(&self, document: impl AsRef<str>) -> Result<String, CanonicalizationError> {
+        self.context
+            .borrow_mut()
+            .canonicalize(document, self.options.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonicalize_xml;
+
+    #[test]
+    fn same_as_canonicalize_xml() {
+        let large = format!("<a>{}</a>", "<b x='1'/>".repeat(10_000));
+        let inputs = [
+            "<a><!-- c --></a>",
+            "<a>",
+            "",
+            &large,
+            r#"<a xmlns:x="urn:x"/>"#,
+        ];
+
+        let mut context = CanonicalizerContext::new();
+        for input in inputs {
+            for keep_comments in [true, false] {
+                let options = CanonicalizationOptions {
+                    keep_comments,
+                    ..Default::default()
+                };
+                assert_eq!(
+                    context.canonicalize(input, options.clone()),
+                    canonicalize_xml(input, options)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shared_dictionary() {
+        let mut context = CanonicalizerContext::new();
+        let first =
+            Document::read_with(b"<a><b/></a>", None, 0, Some(&mut context.parser)).unwrap();
+        let second =
+            Document::read_with(b"<a><c/></a>", None, 0, Some(&mut context.parser)).unwrap();
+
+        unsafe {
+            let dict = (*(*first.root_element()).doc).dict;
+            assert!(!dict.is_null());
+            assert_eq!((*(*second.root_element()).doc).dict, dict);
+            // the name of both roots is the same interned string
+            assert_eq!((*first.root_element()).name, (*second.root_element()).name);
+        }
+
+        drop(context);
+        drop(first);
+        assert_eq!(
+            second.canonicalize(Default::default()).unwrap(),
+            "<a><c></c></a>"
+        );
+    }
+
+    #[test]
+    fn thread_local_context() {
+        let parser = |context: &mut CanonicalizerContext| context.parser.context();
+
+        CanonicalizerContext::with_thread_local(|context| {
+            context.canonicalize("<a/>", Default::default()).unwrap();
+        });
+        let first = CanonicalizerContext::with_thread_local(parser);
+        assert!(first.is_some());
+        assert_eq!(CanonicalizerContext::with_thread_local(parser), first);
+
+        // reentrant use gets a fresh context
+        CanonicalizerContext::with_thread_local(|outer| {
+            let inner = CanonicalizerContext::with_thread_local(|inner| {
+                assert!(inner.parser.context().is_none());
+                inner.canonicalize("<b/>", Default::default()).unwrap()
+            });
+            assert_eq!(inner, "<b></b>");
+            assert_eq!(outer.parser.context(), first);
+        });
+
+        let other_thread = std::thread::spawn(move || {
+            CanonicalizerContext::with_thread_local(|context| {
+                context.canonicalize("<a/>", Default::default()).unwrap();
+                context.parser.context().map(|context| context as usize)
+            })
+        });
+        assert_ne!(
+            other_thread.join().unwrap(),
+            first.map(|context| context as usize)
+        );
+    }
+
+    #[test]
+    fn canonicalizer() {
+        let options = CanonicalizationOptions {
+            mode: crate::CanonicalizationMode::Canonical1_1,
+            keep_comments: true,
+            max_input_bytes: Some(100),
+            ..Default::default()
+        };
+        let canonicalizer = Canonicalizer::new(options.clone()).unwrap();
+        assert_eq!(canonicalizer.options(), &options);
+
+        for input in [
+            "<a xmlns:x='urn:x'><!-- c --><x:b/></a>",
+            "<a>",
+            &"<b/>".repeat(30),
+        ] {
+            assert_eq!(
+                canonicalizer.canonicalize(input),
+                canonicalize_xml(input, options.clone())
+            );
+        }
+        let parser = canonicalizer.context.borrow().parser.context();
+        assert!(parser.is_some());
+        canonicalizer.canonicalize("<c/>").unwrap();
+        assert_eq!(canonicalizer.context.borrow().parser.context(), parser);
+
+        assert!(matches!(
+            Canonicalizer::new(CanonicalizationOptions {
+                inclusive_ns_prefixes: vec!["ds".to_owned()],
+                ..options
+            }),
+            Err(CanonicalizationError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn max_input_bytes() {
+        let mut context = CanonicalizerContext::new();
+        let canonicalized = context.canonicalize(
+            "<abc/>",
+            CanonicalizationOptions {
+                max_input_bytes: Some(2),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            canonicalized,
+            Err(CanonicalizationError::InputTooLarge { size: 6, limit: 2 })
+        );
+    }
+}
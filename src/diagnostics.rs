@@ -0,0 +1,196 @@
+//! Collecting warnings and errors reported by libxml2 as structured records
+
+use libxml::bindings::{
+    __xmlStructuredError, __xmlStructuredErrorContext, xmlError, xmlErrorLevel_XML_ERR_ERROR,
+    xmlErrorLevel_XML_ERR_FATAL, xmlErrorLevel_XML_ERR_WARNING, xmlErrorPtr,
+    xmlSetStructuredErrorFunc, xmlStructuredErrorFunc,
+};
+use std::ffi::{c_int, c_void, CStr};
+
+/// Severity of a [Diagnostic]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum DiagnosticLevel {
+    /// Something suspicious that libxml2 recovered from
+    Warning,
+    /// A recoverable error
+    Error,
+    /// An error that stopped processing
+    Fatal,
+}
+
+/// A warning or error reported by libxml2
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Diagnostic {
+    pub level: DiagnosticLevel,
+    /// libxml2 error code, one of the `xmlParserErrors` values, or 0 for the messages of libxslt, which have none
+    pub code: i32,
+    /// libxml2 error domain, one of the `xmlErrorDomain` values, e.g. 1 for the parser or 19 for C14N
+    pub domain: i32,
+    /// Message as reported by libxml2
+    pub message: String,
+    /// Line the problem occurred on, if known
+    pub line: Option<u32>,
+    /// Column the problem occurred on, if known
+    pub column: Option<u32>,
+}
+
+impl Diagnostic {
+    /// # Safety
+    ///
+    /// `error` must point to a valid error
+    pub(crate) unsafe fn from_xml_error(error: *const xmlError) -> Self {
+        let error = &*error;
+
+        #[allow(non_upper_case_globals)]
+        let level = match error.level {
+            xmlErrorLevel_XML_ERR_WARNING => DiagnosticLevel::Warning,
+            xmlErrorLevel_XML_ERR_ERROR => DiagnosticLevel::Error,
+            xmlErrorLevel_XML_ERR_FATAL => DiagnosticLevel::Fatal,
+            // XML_ERR_NONE is never actually reported
+            _ => DiagnosticLevel::Warning,
+        };
+
+        let message = if error.message.is_null() {
+            String::new()
+        } else {
+            // libxml2 messages come with a trailing newline
+            CStr::from_ptr(error.message)
+                .to_string_lossy()
+                .trim_end()
+                .to_owned()
+        };
+
+        Diagnostic {
+            level,
+            code: error.code,
+            domain: error.domain,
+            message,
+            line: positive(error.line),
+            // for parser errors, libxml2 stores the column in int2
+            column: positive(error.int2),
+        }
+    }
+}
+
+fn positive(value: c_int) -> Option<u32> {
+    u32::try_from(value).ok().filter(|&value| value > 0)
+}
+
+/// Run `f`, collecting everything libxml2 reports on the current thread in the meantime
+///
+/// Whatever structured error handler was installed before still gets everything reported in the meantime, so that nested
+/// collections (e.g. of canonicalization errors, within [canonicalize_xml_with_diagnostics](crate::canonicalize_xml_with_diagnostics))
+/// don't hide anything from the outer ones. It's restored afterwards, even if `f` panics.
+pub(crate) fn collect_diagnostics<T>(f: impl FnOnce() -> T) -> (T, Vec<Diagnostic>) {
+    // SAFETY: these point to the current thread's libxml2 globals
+    let mut collector = unsafe {
+        Collector {
+            diagnostics: Vec::new(),
+            previous_handler: *__xmlStructuredError(),
+            previous_context: *__xmlStructuredErrorContext(),
+        }
+    };
+
+    let result = {
+        let _guard = HandlerGuard::install(&mut collector);
+        f()
+    };
+
+    (result, collector.diagnostics)
+}
+
+/// What the installed handler collects into
+struct Collector {
+    diagnostics: Vec<Diagnostic>,
+    previous_handler: xmlStructuredErrorFunc,
+    previous_context: *mut c_void,
+}
+
+/// Restores the previous structured error handler on drop
+struct HandlerGuard {
+    previous_handler: xmlStructuredErrorFunc,
+    previous_context: *mut c_void,
+}
+
+impl HandlerGuard {
+    fn install(collector: &mut Collector) -> Self {
+        let guard = HandlerGuard {
+            previous_handler: collector.previous_handler,
+            previous_context: collector.previous_context,
+        };
+        // SAFETY: the collector outlives the guard, which uninstalls the handler
+        unsafe {
+            xmlSetStructuredErrorFunc(
+                collector as *mut Collector as *mut c_void,
+                Some(collect_diagnostic),
+            );
+        }
+        guard
+    }
+}
+
+impl Drop for HandlerGuard {
+    fn drop(&mut self) {
+        unsafe {
+            xmlSetStructuredErrorFunc(self.previous_context, self.previous_handler);
+        }
+    }
+}
+
+unsafe extern "C" fn collect_diagnostic(user_data: *mut c_void, error: xmlErrorPtr) {
+    if error.is_null() {
+        return;
+    }
+    let collector = &mut *(user_data as *mut Collector);
+    collector
+        .diagnostics
+        .push(Diagnostic::from_xml_error(error));
+
+    if let Some(previous_handler) = collector.previous_handler {
+        previous_handler(collector.previous_context, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{canonicalize_xml, canonicalize_xml_with_diagnostics, CanonicalizationOptions};
+
+    unsafe extern "C" fn count_errors(user_data: *mut c_void, _error: xmlErrorPtr) {
+        *(user_data as *mut usize) += 1;
+    }
+
+    #[test]
+    fn host_handler_is_restored() {
+        let mut reported = 0usize;
+        let context = &mut reported as *mut usize as *mut c_void;
+
+        unsafe {
+            let original_handler = *__xmlStructuredError();
+            let original_context = *__xmlStructuredErrorContext();
+            xmlSetStructuredErrorFunc(context, Some(count_errors));
+
+            let options = CanonicalizationOptions {
+                keep_comments_under: Some("//a".to_owned()),
+                ..Default::default()
+            };
+            assert!(canonicalize_xml("<a/>", options).is_ok());
+            assert!(canonicalize_xml("<a>", Default::default()).is_err());
+            let (_, diagnostics) = canonicalize_xml_with_diagnostics(
+                r#"<a xml:space="sometimes"/>"#,
+                Default::default(),
+            )
+            .unwrap();
+            assert!(!diagnostics.is_empty());
+
+            let installed = (*__xmlStructuredError()).map(|handler| handler as usize);
+            let sentinel: unsafe extern "C" fn(*mut c_void, xmlErrorPtr) = count_errors;
+            assert_eq!(installed, Some(sentinel as usize));
+            assert_eq!(*__xmlStructuredErrorContext(), context);
+            xmlSetStructuredErrorFunc(original_context, original_handler);
+        }
+
+        // errors reported while the crate's handler was installed still reached the host's handler
+        assert!(reported >= 2);
+    }
+}
@@ -0,0 +1,335 @@
+//! Comparing documents by their canonical forms, for tests and debugging
+
+use crate::escape::unescape_attribute_value;
+use crate::tokens::{comment_ranges, start_tags, tag_positions};
+use crate::{
+    canonicalize_xml, first_difference, CanonicalizationError, CanonicalizationMode,
+    CanonicalizationOptions,
+};
+use std::collections::BTreeSet;
+
+/// Where the canonical forms of two documents first differ, see [canonical_diff]
+///
+/// The fragments are pieces of canonical output, cut before every start and end tag so that each fits on a line in a
+/// test failure. **They are not canonical XML themselves**, never hash or sign them.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct CanonicalDifference {
+    /// Index of the first differing fragment
+    pub index: usize,
+    /// The fragment of the left document, or [None] if its canonical form ended before
+    pub left: Option<String>,
+    /// The fragment of the right document, or [None] if its canonical form ended before
+    pub right: Option<String>,
+}
+
+/// Why a document's canonical form isn't what another implementation produced, see [explain_mismatch]
+///
+/// Namespace declarations are `(prefix, URI)` pairs, with an empty prefix for the default namespace, each listed once no
+/// matter how many elements it's rendered on.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct MismatchReport {
+    /// Byte position of the first difference, the length of the shorter string if one is a prefix of the other
+    pub position: usize,
+    /// The fragment of our canonical form (cut like [CanonicalDifference]'s) the difference is in, or [None] if it ended
+    /// before
+    pub actual_fragment: Option<String>,
+    /// The fragment of the expected string the difference is in, or [None] if it ended before
+    pub expected_fragment: Option<String>,
+    /// Whether the expected string is canonical XML at all, i.e. its own canonical form
+    ///
+    /// If it isn't, the other implementation got the serialization wrong (e.g. wrote `<a/>` or single quotes), and the
+    /// rest of the report compares against the canonical form of the expected string instead.
+    pub expected_is_canonical: bool,
+    /// Namespace declarations in our canonical form but not in the expected one, e.g. because we used inclusive
+    /// canonicalization and they used exclusive
+    pub declarations_only_in_actual: Vec<(String, String)>,
+    /// Namespace declarations in the expected canonical form but not in ours, e.g. missing
+    /// [inclusive_ns_prefixes](CanonicalizationOptions::inclusive_ns_prefixes)
+    pub declarations_only_in_expected: Vec<(String, String)>,
+    /// Number of comments in our canonical form
+    pub actual_comments: usize,
+    /// Number of comments in the expected canonical form, which differs from `actual_comments` if only one side kept them
+    pub expected_comments: usize,
+}
+
+/// Whether two documents have the same canonical form with the given options
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonical_equal, CanonicalizationOptions};
+///
+/// assert!(canonical_equal("<a b='1' c='2'/>", r#"<a c="2" b="1"></a>"#, CanonicalizationOptions::default()).unwrap());
+/// ```
+pub fn canonical_equal(
+    left: impl AsRef<str>,
+    right: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<bool, CanonicalizationError> {
+    Ok(canonicalize_xml(left, options.clone())? == canonicalize_xml(right, options)?)
+}
+
+/// Canonicalize two documents and find the first fragment (start tag, end tag, and the content up to the next tag) in
+/// which they differ, or [None] if they're [canonical_equal]
+///
+/// Meant for comparing output against fixtures in tests, where a byte diff of two long lines is unreadable.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonical_diff, CanonicalizationOptions};
+///
+/// let difference = canonical_diff("<a><b/><c>1</c></a>", "<a><b/><c>2</c></a>", CanonicalizationOptions::default())
+///     .unwrap()
+///     .unwrap();
+///
+/// // fragments are `<a>`, `<b>`, `</b>`, `<c>1`, ...
+/// assert_eq!(difference.index, 3);
+/// assert_eq!(difference.left.as_deref(), Some("<c>1"));
+/// assert_eq!(difference.right.as_deref(), Some("<c>2"));
+/// ```
+pub fn canonical_diff(
+    left: impl AsRef<str>,
+    right: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<Option<CanonicalDifference>, CanonicalizationError> {
+    let left = canonicalize_xml(left, options.clone())?;
+    let right = canonicalize_xml(right, options)?;
+    if left == right {
+        return Ok(None);
+    }
+
+    let left_fragments = fragments(&left);
+    let right_fragments = fragments(&right);
+    let index = left_fragments
+        .iter()
+        .zip(&right_fragments)
+        .position(|(left, right)| left != right)
+        .unwrap_or(left_fragments.len().min(right_fragments.len()));
+
+    Ok(Some(CanonicalDifference {
+        index,
+        left: left_fragments
+            .get(index)
+            .map(|&fragment| fragment.to_owned()),
+        right: right_fragments
+            .get(index)
+            .map(|&fragment| fragment.to_owned()),
+    }))
+}
+
+/// Canonicalize specified XML document and explain how the result differs from `expected`, the canonical form another
+/// implementation produced for it, or return [None] if it doesn't
+///
+/// The "why won't my signature validate against their tool" check: the report combines the first differing byte with
+/// the usual suspects, namespace declarations (exclusive or inclusive canonicalization, inclusive prefixes) and comments
+/// (a `WithComments` algorithm on one side only). `expected` has to be well-formed XML, as the canonical form of a
+/// document or of an element is, otherwise this fails with [CanonicalizationError::Parse].
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{explain_mismatch, CanonicalizationOptions};
+///
+/// let document = r#"<a xmlns:x="urn:x"><!-- note --><b/></a>"#;
+/// let theirs = r#"<a xmlns:x="urn:x"><!-- note --><b></b></a>"#;
+///
+/// let report = explain_mismatch(document, CanonicalizationOptions::default(), theirs).unwrap().unwrap();
+/// assert_eq!(report.position, 2);
+/// assert_eq!(report.declarations_only_in_expected, [("x".to_owned(), "urn:x".to_owned())]);
+/// assert_eq!((report.actual_comments, report.expected_comments), (0, 1));
+/// ```
+pub fn explain_mismatch(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+    expected: &str,
+) -> Result<Option<MismatchReport>, CanonicalizationError> {
+    let actual = canonicalize_xml(document, options)?;
+    let Some(position) = first_difference(&actual, expected) else {
+        return Ok(None);
+    };
+
+    // canonical output is a fixed point of (any mode of) canonicalization, keeping whatever comments there are
+    let expected_canonical = canonicalize_xml(
+        expected,
+        CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_0,
+            keep_comments: true,
+            ..Default::default()
+        },
+    )?;
+    let actual_declarations = declarations(&actual);
+    let expected_declarations = declarations(&expected_canonical);
+
+    Ok(Some(MismatchReport {
+        position,
+        actual_fragment: fragment_at(&actual, position),
+        expected_fragment: fragment_at(expected, position),
+        expected_is_canonical: expected_canonical == expected,
+        declarations_only_in_actual: actual_declarations
+            .difference(&expected_declarations)
+            .cloned()
+            .collect(),
+        declarations_only_in_expected: expected_declarations
+            .difference(&actual_declarations)
+            .cloned()
+            .collect(),
+        actual_comments: comment_ranges(&actual).len(),
+        expected_comments: comment_ranges(&expected_canonical).len(),
+    }))
+}
+
+/// Distinct namespace declarations in canonical output
+fn declarations(canonical: &str) -> BTreeSet<(String, String)> {
+    start_tags(canonical)
+        .iter()
+        .flat_map(|tag| &tag.attributes)
+        .filter_map(|attribute| {
+            let prefix = attribute.declared_prefix()?;
+            Some((
+                prefix.to_owned(),
+                unescape_attribute_value(attribute.value(canonical)),
+            ))
+        })
+        .collect()
+}
+
+/// The fragment (as cut by [fragments]) of `text` containing byte `position`, cutting `text` only before `<` if it isn't
+/// canonical
+fn fragment_at(text: &str, position: usize) -> Option<String> {
+    if position >= text.len() {
+        return None;
+    }
+    let start = text[..=position].rfind('<').unwrap_or(0);
+    let end = text[position + 1..]
+        .find('<')
+        .map_or(text.len(), |end| position + 1 + end);
+    Some(text[start..end].to_owned())
+}
+
+/// Cut canonical output before every tag
+fn fragments(canonical: &str) -> Vec<&str> {
+    let mut fragments = vec![];
+    let mut start = 0;
+    for position in tag_positions(canonical) {
+        if position > start {
+            fragments.push(&canonical[start..position]);
+        }
+        start = position;
+    }
+    if start < canonical.len() {
+        fragments.push(&canonical[start..]);
+    }
+    fragments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fragments_cover_output() {
+        let canonical = "<!-- c -->\n<a x=\"<\">text<b></b><?pi <c>?>tail</a>";
+        assert_eq!(
+            fragments(canonical),
+            [
+                "<!-- c -->\n",
+                "<a x=\"<\">text",
+                "<b>",
+                "</b><?pi <c>?>tail",
+                "</a>"
+            ]
+        );
+        assert_eq!(fragments(canonical).concat(), canonical);
+    }
+
+    #[test]
+    fn differences() {
+        let options = CanonicalizationOptions::default();
+
+        assert_eq!(
+            canonical_diff("<a b='1'/>", r#"<a   b="1"></a>"#, options.clone()),
+            Ok(None)
+        );
+
+        // an extra element
+        let difference = canonical_diff("<a><b/></a>", "<a><b/><c/></a>", options.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(difference.index, 3);
+        assert_eq!(difference.left.as_deref(), Some("</a>"));
+        assert_eq!(difference.right.as_deref(), Some("<c>"));
+
+        let difference = canonical_diff(
+            "<a>x</a>",
+            "<a>x<!-- c --></a>",
+            CanonicalizationOptions {
+                keep_comments: true,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(difference.index, 0);
+        assert_eq!(difference.right.as_deref(), Some("<a>x<!-- c -->"));
+
+        assert!(canonical_diff("<a>", "<a/>", options).is_err());
+    }
+
+    #[test]
+    fn mismatch_report() {
+        let options = CanonicalizationOptions::default();
+        let document = r#"<a xmlns="urn:a" xmlns:x="urn:x"><b x:c="1"/><!-- c --></a>"#;
+
+        let ours = canonicalize_xml(document, options.clone()).unwrap();
+        assert_eq!(explain_mismatch(document, options.clone(), &ours), Ok(None));
+
+        // inclusive canonicalization with comments on their side
+        let theirs = r#"<a xmlns="urn:a" xmlns:x="urn:x"><b x:c="1"></b><!-- c --></a>"#;
+        assert_eq!(
+            explain_mismatch(document, options.clone(), theirs).unwrap(),
+            Some(MismatchReport {
+                position: 16,
+                actual_fragment: Some(r#"<a xmlns="urn:a">"#.to_owned()),
+                expected_fragment: Some(r#"<a xmlns="urn:a" xmlns:x="urn:x">"#.to_owned()),
+                expected_is_canonical: true,
+                declarations_only_in_actual: vec![],
+                declarations_only_in_expected: vec![],
+                actual_comments: 0,
+                expected_comments: 1,
+            })
+        );
+
+        // not canonical XML, and a different URI
+        let report = explain_mismatch(
+            document,
+            options.clone(),
+            "<a xmlns='urn:a'><b xmlns:x='urn:y' x:c='1'/></a>",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(report.position, 9);
+        assert!(!report.expected_is_canonical);
+        assert_eq!(
+            report.declarations_only_in_actual,
+            [("x".to_owned(), "urn:x".to_owned())]
+        );
+        assert_eq!(
+            report.declarations_only_in_expected,
+            [("x".to_owned(), "urn:y".to_owned())]
+        );
+
+        // one is a prefix of the other
+        let report = explain_mismatch("<a>x</a>", options.clone(), "<a>x</a>\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.position, 8);
+        assert_eq!(report.actual_fragment, None);
+        assert_eq!(report.expected_fragment.as_deref(), Some("</a>\n"));
+
+        assert!(matches!(
+            explain_mismatch(document, options, "<a>"),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+}
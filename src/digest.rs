@@ -0,0 +1,381 @@
+//! Digest computation and verification, enabled by the `digest` feature
+
+use crate::escape::xml_attribute_value;
+use crate::{canonicalize_xml, CanonicalizationError, CanonicalizationOptions};
+use ::digest::core_api::BlockSizeUser;
+use ::digest::Digest;
+use subtle::ConstantTimeEq;
+
+/// A hash function with an XML Signature algorithm identifier, for [digest_reference]
+///
+/// Implemented for the SHA-2 functions of the `sha2` crate. Implement it for others by giving their URI, as in
+/// `<ds:DigestMethod Algorithm="...">`.
+pub trait DigestAlgorithm: Digest {
+    /// Algorithm identifier, e.g. `http://www.w3.org/2001/04/xmlenc#sha256`
+    const ALGORITHM_URI: &'static str;
+}
+
+impl DigestAlgorithm for sha2::Sha224 {
+    const ALGORITHM_URI: &'static str = "http://www.w3.org/2001/04/xmldsig-more#sha224";
+}
+
+impl DigestAlgorithm for sha2::Sha256 {
+    const ALGORITHM_URI: &'static str = "http://www.w3.org/2001/04/xmlenc#sha256";
+}
+
+impl DigestAlgorithm for sha2::Sha384 {
+    const ALGORITHM_URI: &'static str = "http://www.w3.org/2001/04/xmldsig-more#sha384";
+}
+
+impl DigestAlgorithm for sha2::Sha512 {
+    const ALGORITHM_URI: &'static str = "http://www.w3.org/2001/04/xmlenc#sha512";
+}
+
+/// The digest of a canonicalized document, with the algorithm that computed it, see [digest_reference]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DigestReference {
+    /// Algorithm identifier, for `<ds:DigestMethod Algorithm="...">`
+    pub algorithm_uri: &'static str,
+    /// Base64 encoded digest, for `<ds:DigestValue>`
+    pub value: String,
+}
+
+impl DigestReference {
+    /// `<ds:DigestMethod>` and `<ds:DigestValue>` elements, to put into a `<ds:Reference>` that declares the `ds` prefix
+    pub fn to_xml(&self) -> String {
+        format!(
+            r#"<ds:DigestMethod Algorithm="{}"></ds:DigestMethod><ds:DigestValue>{}</ds:DigestValue>"#,
+            xml_attribute_value(self.algorithm_uri),
+            self.value
+        )
+    }
+}
+
+/// Canonicalize the specified XML document and hash it with `D`, returning the base64 digest paired with `D`'s algorithm URI
+///
+/// Keeps the digest and the `<DigestMethod>` that claims to have computed it from getting mixed up.
+///
+/// Example:
+///
+/// ```
+/// use sha2::Sha256;
+/// use xml_c14n::{digest_reference, CanonicalizationOptions};
+///
+/// let reference = digest_reference::<Sha256>("<hi/>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(reference.algorithm_uri, "http://www.w3.org/2001/04/xmlenc#sha256");
+/// assert_eq!(reference.value, "LmxCu/SWMOSOCVm9Czi3J9GIyDlLFsjhbRv/CmI9uM8=");
+/// assert_eq!(
+///     reference.to_xml(),
+///     r#"<ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"></ds:DigestMethod><ds:DigestValue>LmxCu/SWMOSOCVm9Czi3J9GIyDlLFsjhbRv/CmI9uM8=</ds:DigestValue>"#
+/// );
+/// ```
+pub fn digest_reference<D: DigestAlgorithm>(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<DigestReference, CanonicalizationError> {
+    let canonical = canonicalize_xml(document, options)?;
+    Ok(DigestReference {
+        algorithm_uri: D::ALGORITHM_URI,
+        value: base64(&D::digest(canonical.as_bytes())),
+    })
+}
+
+/// Standard base64 with padding, as XML Signature uses for `<DigestValue>`
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(
+                    ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Canonicalize the specified XML document, hash it with `D`, and check whether the hash equals `expected`
+///
+/// The comparison takes constant time, so it doesn't leak how much of the digest matched.
+/// `expected` is the raw digest, so a base64 `<DigestValue>` must be decoded first.
+///
+/// Example:
+///
+/// ```
+/// use sha2::{Digest, Sha256};
+/// use xml_c14n::{verify_digest, CanonicalizationOptions};
+///
+/// let expected = Sha256::digest("<hi></hi>");
+///
+/// assert!(verify_digest::<Sha256>("<hi/>", CanonicalizationOptions::default(), &expected).unwrap());
+/// assert!(!verify_digest::<Sha256>("<bye/>", CanonicalizationOptions::default(), &expected).unwrap());
+/// ```
+pub fn verify_digest<D: Digest>(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+    expected: &[u8],
+) -> Result<bool, CanonicalizationError> {
+    let document = document.as_ref();
+    let canonical = canonicalize_xml(document, options)?;
+    let actual = D::digest(canonical.as_bytes());
+    Ok(bool::from(actual.as_slice().ct_eq(expected)))
+}
+
+/// Canonicalize the specified XML document and compute the HMAC of the canonical bytes with `key`, using the hash function `D`
+///
+/// Returns the raw MAC, e.g. to base64 encode it for `<ds:SignatureValue>` with
+/// `http://www.w3.org/2001/04/xmldsig-more#hmac-sha256`. Keys longer than `D`'s block size are hashed first, as HMAC
+/// specifies.
+///
+/// Example:
+///
+/// ```
+/// use sha2::Sha256;
+/// use xml_c14n::{canonicalize_hmac, CanonicalizationOptions};
+///
+/// let mac = canonicalize_hmac::<Sha256>("<hi/>", CanonicalizationOptions::default(), b"secret").unwrap();
+///
+/// let hex: String = mac.iter().map(|byte| format!("{byte:02x}")).collect();
+/// assert_eq!(hex, "469c600a2ced243faea297b9f9abbd4a6005f1c7b11d3b656fac34f92261368a");
+/// ```
+pub fn canonicalize_hmac<D: Digest + BlockSizeUser>(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+    key: &[u8],
+) -> Result<Vec<u8>, CanonicalizationError> {
+    let canonical = canonicalize_xml(document, options)?;
+    Ok(hmac::<D>(key, canonical.as_bytes()))
+}
+
+/// HMAC as in RFC 2104
+///
+/// The key is padded in place, and overwritten before the block holding it is freed.
+fn hmac<D: Digest + BlockSizeUser>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut block = vec![0; D::block_size()];
+    if key.len() > block.len() {
+        let mut hashed = D::digest(key);
+        block[..hashed.len()].copy_from_slice(&hashed);
+        wipe(&mut hashed);
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let pad = |block: &mut [u8], pad: u8| block.iter_mut().for_each(|byte| *byte ^= pad);
+    pad(&mut block, 0x36);
+    let inner = D::new().chain_update(&block).chain_update(data).finalize();
+    // undoes the inner padding along the way
+    pad(&mut block, 0x36 ^ 0x5c);
+    let mac = D::new()
+        .chain_update(&block)
+        .chain_update(inner)
+        .finalize()
+        .to_vec();
+    wipe(&mut block);
+    mac
+}
+
+/// Overwrite `bytes` with zeros, in a way the compiler can't optimize out
+fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        // SAFETY: `byte` is a valid, aligned reference
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Sha224, Sha256, Sha384, Sha512};
+
+    #[test]
+    fn verify_sha256() {
+        let input = include_str!("samples/canonical_exclusive/1_input.xml");
+        let expected = Sha256::digest(include_str!("samples/canonical_exclusive/1_output.xml"));
+
+        assert!(
+            verify_digest::<Sha256>(input, CanonicalizationOptions::default(), &expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn base64_rfc_4648() {
+        // the test vectors of section 10
+        for (bytes, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64(bytes.as_bytes()), encoded);
+        }
+
+        // every character of the alphabet in table 1, in order
+        assert_eq!(
+            base64(b"\x00\x10\x83\x10\x51\x87\x20\x92\x8b\x30\xd3\x8f\x41\x14\x93\x51\x55\x97\x61\x96\x9b\x71\xd7\x9f\x82\x18\xa3\x92\x59\xa7\xa2\x9a\xab\xb2\xdb\xaf\xc3\x1c\xb3\xd3\x5d\xb7\xe3\x9e\xbb\xf3\xdf\xbf"),
+            "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+        );
+        assert_eq!(base64(b"\xff\xfe\xfd"), "//79");
+    }
+
+    #[test]
+    fn reference() {
+        let input = include_str!("samples/canonical_exclusive/1_input.xml");
+        let canonical = include_str!("samples/canonical_exclusive/1_output.xml");
+
+        let reference =
+            digest_reference::<Sha512>(input, CanonicalizationOptions::default()).unwrap();
+        assert_eq!(
+            reference.algorithm_uri,
+            "http://www.w3.org/2001/04/xmlenc#sha512"
+        );
+        assert_eq!(reference.value, base64(&Sha512::digest(canonical)));
+        assert_eq!(reference.value.len(), 88);
+
+        assert!(matches!(
+            digest_reference::<Sha256>("<a>", CanonicalizationOptions::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn hmac_rfc_4231() {
+        let hex =
+            |bytes: &[u8]| -> String { bytes.iter().map(|byte| format!("{byte:02x}")).collect() };
+        let key_4: Vec<u8> = (1..=25).collect();
+
+        // test cases 1 to 7, with the MACs for SHA-224, SHA-256, SHA-384 and SHA-512
+        for (key, data, expected) in [
+            (
+                &[0x0b; 20][..],
+                &b"Hi There"[..],
+                [
+                    "896fb1128abbdf196832107cd49df33f47b4b1169912ba4f53684b22",
+                    "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+                    "afd03944d84895626b0825f4ab46907f15f9dadbe4101ec682aa034c7cebc59cfaea9ea9076ede7f4af152e8b2fa9cb6",
+                    "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cdedaa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854",
+                ],
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                [
+                    "a30e01098bc6dbbf45690f3a7e9e6d0f8bbea2a39e6148008fd05e44",
+                    "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+                    "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e8e2240ca5e69e2c78b3239ecfab21649",
+                    "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+                ],
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                [
+                    "7fb3cb3588c6c1f6ffa9694d7d6ad2649365b0c1f65d69d1ec8333ea",
+                    "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+                    "88062608d3e6ad8a0aa2ace014c8a86f0aa635d947ac9febe83ef4e55966144b2a5ab39dc13814b94e3ab6e101a34f27",
+                    "fa73b0089d56a284efb0f0756c890be9b1b5dbdd8ee81a3655f83e33b2279d39bf3e848279a722c806b485a47e67c807b946a337bee8942674278859e13292fb",
+                ],
+            ),
+            (
+                &key_4,
+                &[0xcd; 50],
+                [
+                    "6c11506874013cac6a2abc1bb382627cec6a90d86efc012de7afec5a",
+                    "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+                    "3e8a69b7783c25851933ab6290af6ca77a9981480850009cc5577c6e1f573b4e6801dd23c4a7d679ccf8a386c674cffb",
+                    "b0ba465637458c6990e5a8c5f61d4af7e576d97ff94b872de76f8050361ee3dba91ca5c11aa25eb4d679275cc5788063a5f19741120c4f2de2adebeb10a298dd",
+                ],
+            ),
+            // truncated to 128 bits
+            (
+                &[0x0c; 20],
+                b"Test With Truncation",
+                [
+                    "0e2aea68a90c8d37c988bcdb9fca6fa8",
+                    "a3b6167473100ee06e0c796c2955552b",
+                    "3abf34c3503b2a23a46efc619baef897",
+                    "415fad6271580a531d4179bc891d87a6",
+                ],
+            ),
+            // longer than the block size, so hashed first
+            (
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                [
+                    "95e9a0db962095adaebe9b2d6f0dbce2d499f112f2d2b7273fa6870e",
+                    "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+                    "4ece084485813e9088d2c63a041bc5b44f9ef1012a2b588f3cd11f05033ac4c60c2ef6ab4030fe8296248df163f44952",
+                    "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f3526b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598",
+                ],
+            ),
+            (
+                &[0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.",
+                [
+                    "3a854166ac5d9f023f54d517d0b39dbd946770db9c2b95c9f6f565d1",
+                    "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+                    "6617178e941f020d351e2f254e8fd32c602420feb0b8fb9adccebb82461e99c5a678cc31e799176d3860e6110c46523e",
+                    "e37b6a775dc87dbaa4dfa9f96e5e3ffddebd71f8867289865df5a32d20cdc944b6022cac3c4982b10d5eeb55c3e4de15134676fb6de0446065c97440fa8c6a58",
+                ],
+            ),
+        ] {
+            let macs = [
+                hmac::<Sha224>(key, data),
+                hmac::<Sha256>(key, data),
+                hmac::<Sha384>(key, data),
+                hmac::<Sha512>(key, data),
+            ];
+            for (mac, expected) in macs.iter().zip(expected) {
+                assert_eq!(&hex(mac)[..expected.len()], expected, "{data:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_hmac() {
+        let input = include_str!("samples/canonical_exclusive/1_input.xml");
+        let canonical = include_str!("samples/canonical_exclusive/1_output.xml");
+
+        for key in [&b"key"[..], &[0xaa; 131]] {
+            let mac = canonicalize_hmac::<Sha256>(input, CanonicalizationOptions::default(), key)
+                .unwrap();
+            assert_eq!(mac, hmac::<Sha256>(key, canonical.as_bytes()));
+        }
+        assert!(matches!(
+            canonicalize_hmac::<Sha256>("<a>", CanonicalizationOptions::default(), b"key"),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn wrong_algorithm() {
+        let expected = Sha256::digest("<hi></hi>");
+        assert!(
+            !verify_digest::<Sha512>("<hi/>", CanonicalizationOptions::default(), &expected)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn truncated_digest() {
+        let expected = Sha256::digest("<hi></hi>");
+        assert!(!verify_digest::<Sha256>(
+            "<hi/>",
+            CanonicalizationOptions::default(),
+            &expected[..16]
+        )
+        .unwrap());
+    }
+}
@@ -0,0 +1,576 @@
+//! Parsed documents, owned on the Rust side
+
+use crate::diagnostics;
+use crate::inject;
+use crate::output::XmlOutput;
+use crate::tree;
+use crate::{
+    canonical_document_length, canonicalize_document, canonicalize_to_string,
+    CanonicalizationError, CanonicalizationOptions, NonConformantOptions, ParseError,
+};
+use libxml::bindings::{
+    xmlBufferCCat, xmlBufferCreate, xmlBufferFree, xmlBufferLength, xmlCharEncCloseFunc,
+    xmlCharEncOutFunc, xmlCharEncoding, xmlCharEncoding_XML_CHAR_ENCODING_ERROR,
+    xmlCharEncoding_XML_CHAR_ENCODING_UCS2, xmlCharEncoding_XML_CHAR_ENCODING_UCS4BE,
+    xmlCharEncoding_XML_CHAR_ENCODING_UCS4LE, xmlCharEncoding_XML_CHAR_ENCODING_UCS4_2143,
+    xmlCharEncoding_XML_CHAR_ENCODING_UCS4_3412, xmlCharEncoding_XML_CHAR_ENCODING_UTF16BE,
+    xmlCharEncoding_XML_CHAR_ENCODING_UTF16LE, xmlCtxtReadMemory, xmlDetectCharEncoding,
+    xmlDictSize, xmlDocGetRootElement, xmlDocPtr, xmlFindCharEncodingHandler, xmlFreeDoc,
+    xmlFreeParserCtxt, xmlGetLastError, xmlNewParserCtxt, xmlNodePtr, xmlParseCharEncoding,
+    xmlParserCtxtPtr, xmlParserErrors_XML_ERR_DOCUMENT_EMPTY, xmlReadMemory, xmlResetLastError,
+};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ptr::null;
+
+/// Documents up to this many bytes are parsed with a reused parser context, as setting one up dominates for them
+const SMALL_DOCUMENT_BYTES: usize = 4096;
+
+/// Once the reused context's dictionary holds this many strings, it's replaced, so that memory use stays bounded
+const MAX_REUSED_DICT_SIZE: c_int = 10_000;
+
+/// A parser context that is reused between documents, freed on drop
+///
+/// Allocated when first needed, and replaced once its dictionary grows too large.
+/// libxml2 parser contexts must stay on the thread that uses them, hence the marker making this `!Send` and `!Sync`.
+#[derive(Default)]
+pub(crate) struct ReusedParser(Option<xmlParserCtxtPtr>, PhantomData<*const ()>);
+
+impl ReusedParser {
+    /// Whether the last document parsed with this context was valid, if it was parsed with `XML_PARSE_DTDVALID`
+    pub(crate) fn last_document_valid(&self) -> bool {
+        // SAFETY: we own the context
+        self.0
+            .is_some_and(|context| unsafe { (*context).valid != 0 })
+    }
+
+    /// The context, if one was allocated yet
+    #[cfg(test)]
+    pub(crate) fn context(&self) -> Option<xmlParserCtxtPtr> {
+        self.0
+    }
+
+    /// Parse with the reused context, returning the (possibly null) document
+    ///
+    /// Returns [None] if no context could be allocated.
+    unsafe fn read_memory(
+        &mut self,
+        buffer: *const c_char,
+        size: c_int,
+        url: *const c_char,
+        encoding: *const c_char,
+        options: c_int,
+    ) -> Option<xmlDocPtr> {
+        if let Some(context) = self.0 {
+            if xmlDictSize((*context).dict) > MAX_REUSED_DICT_SIZE {
+                xmlFreeParserCtxt(context);
+                self.0 = None;
+            }
+        }
+
+        if self.0.is_none() {
+            let context = xmlNewParserCtxt();
+            if context.is_null() {
+                return None;
+            }
+            self.0 = Some(context);
+        }
+
+        // resets the context before parsing
+        Some(xmlCtxtReadMemory(
+            self.0?, buffer, size, url, encoding, options,
+        ))
+    }
+}
+
+impl Drop for ReusedParser {
+    fn drop(&mut self) {
+        if let Some(context) = self.0 {
+            // SAFETY: we own the context
+            unsafe { xmlFreeParserCtxt(context) }
+        }
+    }
+}
+
+thread_local! {
+    static SMALL_DOCUMENT_PARSER: RefCell<ReusedParser> = const { RefCell::new(ReusedParser(None, PhantomData)) };
+}
+
+/// Byte position of the first NUL character in `document`, given the encoding libxml2 will parse it in
+///
+/// NUL is never allowed in XML, but libxml2 takes one after the document element for the end of the input, silently
+/// ignoring whatever follows. Outside UTF-16 and UTF-32 (and their UCS relatives) it's a zero byte, in those a code unit
+/// of zero bytes.
+fn first_nul(document: &[u8], encoding: Option<&CStr>) -> Option<usize> {
+    let width = code_unit_width(document, encoding);
+    document
+        .chunks_exact(width)
+        .position(|unit| unit.iter().all(|&byte| byte == 0))
+        .map(|index| index * width)
+}
+
+/// Bytes per code unit of the encoding libxml2 will parse `document` in, `encoding` if given or else detected from
+/// the first bytes of `document`
+#[allow(non_upper_case_globals)]
+pub(crate) fn code_unit_width(document: &[u8], encoding: Option<&CStr>) -> usize {
+    match encoding {
+        // SAFETY: `encoding` is NUL-terminated
+        Some(encoding) => match unsafe { xmlParseCharEncoding(encoding.as_ptr()) } {
+            // a name libxml2 only knows through iconv, like `UTF-16LE` or `csUnicode`
+            xmlCharEncoding_XML_CHAR_ENCODING_ERROR => measured_code_unit_width(encoding),
+            known => known_code_unit_width(known),
+        },
+        None => {
+            let detected = c_int::try_from(document.len().min(4)).unwrap();
+            // SAFETY: libxml2 only looks at the first `detected` bytes
+            known_code_unit_width(unsafe { xmlDetectCharEncoding(document.as_ptr(), detected) })
+        }
+    }
+}
+
+/// Bytes per code unit of an encoding libxml2 has a name for
+#[allow(non_upper_case_globals)]
+fn known_code_unit_width(encoding: xmlCharEncoding) -> usize {
+    match encoding {
+        xmlCharEncoding_XML_CHAR_ENCODING_UTF16LE
+        | xmlCharEncoding_XML_CHAR_ENCODING_UTF16BE
+        | xmlCharEncoding_XML_CHAR_ENCODING_UCS2 => 2,
+        xmlCharEncoding_XML_CHAR_ENCODING_UCS4LE
+        | xmlCharEncoding_XML_CHAR_ENCODING_UCS4BE
+        | xmlCharEncoding_XML_CHAR_ENCODING_UCS4_2143
+        | xmlCharEncoding_XML_CHAR_ENCODING_UCS4_3412 => 4,
+        _ => 1,
+    }
+}
+
+/// Bytes per code unit of `encoding`, from how much longer two characters are than one when encoded with libxml2's
+/// handler for it (so that a byte order mark cancels out), or 1 if libxml2 has no handler for it
+fn measured_code_unit_width(encoding: &CStr) -> usize {
+    let encoded_length = |text: &CStr| {
+        // SAFETY: the handler is closed and both buffers are freed before returning
+        unsafe {
+            let handler = xmlFindCharEncodingHandler(encoding.as_ptr());
+            if handler.is_null() {
+                return None;
+            }
+            let input = xmlBufferCreate();
+            let output = xmlBufferCreate();
+            let mut length = None;
+            if !input.is_null() && !output.is_null() {
+                xmlBufferCCat(input, text.as_ptr());
+                if xmlCharEncOutFunc(handler, output, input) >= 0 {
+                    length = usize::try_from(xmlBufferLength(output)).ok();
+                }
+            }
+            xmlBufferFree(input);
+            xmlBufferFree(output);
+            xmlCharEncCloseFunc(handler);
+            length
+        }
+    };
+    match (encoded_length(c"<"), encoded_length(c"<<")) {
+        (Some(one), Some(two)) if two > one => two - one,
+        _ => 1,
+    }
+}
+
+/// A parsed XML document, freed on drop
+///
+/// Created with [parse] (or [Document::parse]), and can be canonicalized any number of times (e.g. in different modes) without parsing it again.
+///
+/// A document can't be sent to or shared with other threads, as libxml2 keeps per-thread state (like the last error)
+/// that its functions rely on:
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>(_: T) {}
+///
+/// assert_send(xml_c14n::parse("<a/>").unwrap());
+/// ```
+pub struct Document(xmlDocPtr, PhantomData<*const ()>);
+
+impl Document {
+    /// Parse specified XML document, same as [parse]
+    pub fn parse(document: impl AsRef<str>) -> Result<Self, CanonicalizationError> {
+        parse(document)
+    }
+
+    /// Parse the specified document
+    ///
+    /// `encoding` overrides whatever encoding libxml2 would otherwise detect, `options` are `xmlParserOption` flags
+    pub(crate) fn read(
+        document: &[u8],
+        encoding: Option<&CStr>,
+        options: c_int,
+    ) -> Result<Self, CanonicalizationError> {
+        if document.len() <= SMALL_DOCUMENT_BYTES {
+            let reused = SMALL_DOCUMENT_PARSER.try_with(|parser| {
+                // already borrowed if parsing reentrantly, e.g. from a libxml2 callback
+                let mut parser = parser.try_borrow_mut().ok()?;
+                Some(Self::read_with(
+                    document,
+                    encoding,
+                    options,
+                    Some(&mut parser),
+                ))
+            });
+            if let Ok(Some(result)) = reused {
+                return result;
+            }
+        }
+
+        Self::read_with(document, encoding, options, None)
+    }
+
+    /// Parse the specified document to canonicalize it with `options`, like [Document::read] with their parse options
+    ///
+    /// Expands internal entities right away with [coalesce_text](CanonicalizationOptions::coalesce_text), see
+    /// [Document::coalesced_for].
+    pub(crate) fn read_for(
+        document: &[u8],
+        encoding: Option<&CStr>,
+        options: &CanonicalizationOptions,
+    ) -> Result<Self, CanonicalizationError> {
+        Self::read(document, encoding, options.parse_options())
+            .map(|parsed| parsed.coalesced_for(options))
+    }
+
+    /// The document with internal entities expanded if `options` [coalesce_text](CanonicalizationOptions::coalesce_text)
+    ///
+    /// Done right after parsing, before anything is selected from the tree, as expanding replaces text nodes.
+    /// Canonicalizing a document that still has references with that option canonicalizes an expanded copy instead.
+    pub(crate) fn coalesced_for(self, options: &CanonicalizationOptions) -> Self {
+        if options.coalesce_text {
+            // SAFETY: the document is valid, and we own it, so nothing else looks at it
+            unsafe { tree::expand_internal_entities(self.0 as xmlNodePtr) };
+        }
+        self
+    }
+
+    /// Same as [Document::read], but using `parser` if given, instead of a fresh parser context
+    pub(crate) fn read_with(
+        document: &[u8],
+        encoding: Option<&CStr>,
+        options: c_int,
+        parser: Option<&mut ReusedParser>,
+    ) -> Result<Self, CanonicalizationError> {
+        let document = if inject::take(inject::Failure::Parse) {
+            b"<"
+        } else {
+            document
+        };
+        let size =
+            c_int::try_from(document.len()).map_err(|_| CanonicalizationError::InputTooLarge {
+                size: document.len(),
+                limit: c_int::MAX as usize,
+            })?;
+
+        // libxml2's globals must be initialized before several threads use them, this is only an atomic load after that
+        libxml::init_parser();
+        if let Some(position) = first_nul(document, encoding) {
+            return Err(ParseError::nul_character(position).into());
+        }
+        unsafe {
+            xmlResetLastError();
+
+            // TODO...
+            let url = CString::default();
+
+            // Encoding is allowed to be null as per docs
+            let encoding = encoding.map_or(null(), CStr::as_ptr);
+
+            let buffer = document.as_ptr() as *const c_char;
+
+            let (document, diagnostics) = diagnostics::collect_diagnostics(|| {
+                parser
+                    .and_then(|parser| {
+                        parser.read_memory(buffer, size, url.as_ptr(), encoding, options)
+                    })
+                    .unwrap_or_else(|| xmlReadMemory(buffer, size, url.as_ptr(), encoding, options))
+            });
+
+            if document.is_null() {
+                let error = xmlGetLastError();
+                let empty = size == 0
+                    || (!error.is_null()
+                        && (*error).code == xmlParserErrors_XML_ERR_DOCUMENT_EMPTY as c_int);
+                if empty {
+                    Err(CanonicalizationError::EmptyDocument)
+                } else {
+                    Err(ParseError::last().with_diagnostics(diagnostics).into())
+                }
+            } else {
+                Ok(Document(document, PhantomData))
+            }
+        }
+    }
+
+    /// Take ownership of a document parsed elsewhere
+    ///
+    /// # Safety
+    ///
+    /// `document` must point to a valid document that nothing else frees
+    pub(crate) unsafe fn from_raw(document: xmlDocPtr) -> Self {
+        Document(document, PhantomData)
+    }
+
+    /// Canonicalize the document
+    ///
+    /// [max_input_bytes](CanonicalizationOptions::max_input_bytes) doesn't apply here, as the document is already parsed.
+    pub fn canonicalize(
+        &self,
+        options: CanonicalizationOptions,
+    ) -> Result<String, CanonicalizationError> {
+        // SAFETY: the document is valid for as long as self is
+        unsafe { canonicalize_to_string(options, self.0, None, None) }
+    }
+
+    /// Canonicalize the document, leaving the output in libxml2's buffer and skipping any post-processing
+    pub(crate) fn canonicalize_output(
+        &self,
+        options: CanonicalizationOptions,
+    ) -> Result<XmlOutput, CanonicalizationError> {
+        // SAFETY: the document is valid for as long as self is
+        unsafe { canonicalize_document(options, self.0, None, None) }
+    }
+
+    /// Byte length of what [Document::canonicalize] would return, see [canonical_length](crate::canonical_length)
+    pub fn canonical_length(
+        &self,
+        options: CanonicalizationOptions,
+    ) -> Result<usize, CanonicalizationError> {
+        if options.non_conformant != NonConformantOptions::default() {
+            // post-processing needs the whole string anyway
+            return self.canonicalize(options).map(|canonical| canonical.len());
+        }
+        // SAFETY: the document is valid for as long as self is
+        unsafe { canonical_document_length(options, self.0) }
+    }
+
+    /// Canonicalize only the subtrees rooted at the elements in `subtrees`, as one node set
+    ///
+    /// The output is in document order whatever order the subtrees were selected in, since the node set is only ever
+    /// asked whether it contains the node libxml2 is at while walking the document.
+    ///
+    /// # Safety
+    ///
+    /// `subtrees` must point to elements of this document
+    pub(crate) unsafe fn canonicalize_subtrees(
+        &self,
+        options: CanonicalizationOptions,
+        subtrees: &HashSet<xmlNodePtr>,
+    ) -> Result<String, CanonicalizationError> {
+        canonicalize_to_string(options, self.0, Some(subtrees), None)
+    }
+
+    /// Remove every element with the given local name and namespace URI (`""` for no namespace), together with everything
+    /// under them, returning how many there were
+    ///
+    /// The document element itself is never removed. Meant for transforms like dropping a volatile timestamp before
+    /// canonicalizing, see [canonicalize_with_transform](crate::canonicalize_with_transform).
+    pub fn remove_elements(&mut self, namespace_uri: &str, local_name: &str) -> usize {
+        // SAFETY: the document is valid, and self is borrowed mutably so nothing else looks at it
+        unsafe { tree::remove_elements(self.root_element(), namespace_uri, local_name) }
+    }
+
+    /// Remove every attribute with the given local name and namespace URI (`""` for no namespace) from all elements,
+    /// returning how many were removed
+    pub fn remove_attributes(&mut self, namespace_uri: &str, local_name: &str) -> usize {
+        // SAFETY: the document is valid, and self is borrowed mutably so nothing else looks at it
+        unsafe { tree::remove_attributes(self.root_element(), namespace_uri, local_name) }
+    }
+
+    /// Whether the document contains at least one comment, including outside the document element
+    pub(crate) fn contains_comment(&self) -> bool {
+        // SAFETY: the document is valid
+        unsafe { tree::first_comment(self.0 as xmlNodePtr) }.is_some()
+    }
+
+    /// Root element, valid for as long as self is
+    pub(crate) fn root_element(&self) -> xmlNodePtr {
+        // SAFETY: the document is valid
+        unsafe { xmlDocGetRootElement(self.0) }
+    }
+
+    /// The libxml2 document, valid for as long as self is
+    pub(crate) fn as_ptr(&self) -> xmlDocPtr {
+        self.0
+    }
+
+    /// Give up ownership of the libxml2 document, which whoever takes it must free
+    #[cfg_attr(not(feature = "xslt"), allow(dead_code))]
+    pub(crate) fn into_raw(self) -> xmlDocPtr {
+        ManuallyDrop::new(self).0
+    }
+}
+
+impl Drop for Document {
+    fn drop(&mut self) {
+        // SAFETY: we own the document, and nothing borrowing it outlives self
+        unsafe { xmlFreeDoc(self.0) }
+    }
+}
+
+/// Parse specified XML document, to canonicalize it later with [Document::canonicalize]
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{parse, CanonicalizationMode, CanonicalizationOptions};
+///
+/// let document = parse(r#"<a xmlns:x="urn:x"/>"#).unwrap();
+///
+/// let exclusive = document.canonicalize(CanonicalizationOptions::default()).unwrap();
+/// let inclusive = document
+///     .canonicalize(CanonicalizationOptions {
+///         mode: CanonicalizationMode::Canonical1_1,
+///         ..Default::default()
+///     })
+///     .unwrap();
+///
+/// assert_eq!(exclusive, "<a></a>");
+/// assert_eq!(inclusive, r#"<a xmlns:x="urn:x"></a>"#);
+/// ```
+pub fn parse(document: impl AsRef<str>) -> Result<Document, CanonicalizationError> {
+    let document = document.as_ref();
+    let options = CanonicalizationOptions::default().parse_options();
+    Document::read(document.as_bytes(), None, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{canonicalize_xml, CanonicalizationMode};
+
+    #[test]
+    fn canonicalize_repeatedly() {
+        let input = "<a><!-- c --><b/></a>";
+        let document = parse(input).unwrap();
+
+        for keep_comments in [true, false, true] {
+            let options = CanonicalizationOptions {
+                keep_comments,
+                ..Default::default()
+            };
+            assert_eq!(
+                document.canonicalize(options.clone()),
+                canonicalize_xml(input, options)
+            );
+        }
+    }
+
+    #[test]
+    fn canonicalize_with_different_options() {
+        let input = r#"<r xmlns:x="urn:x" xmlns:y="urn:y"><!-- c --><x:a/></r>"#;
+        let document = Document::parse(input).unwrap();
+
+        let with_comments = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
+        let inclusive = CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_1,
+            ..Default::default()
+        };
+        let with_prefix = CanonicalizationOptions {
+            inclusive_ns_prefixes: vec!["y".to_owned()],
+            ..Default::default()
+        };
+        let outputs: Vec<String> = [with_comments, inclusive, with_prefix]
+            .into_iter()
+            .map(|options| {
+                let canonical = document.canonicalize(options.clone()).unwrap();
+                assert_eq!(canonical, canonicalize_xml(input, options).unwrap());
+                canonical
+            })
+            .collect();
+
+        assert_eq!(
+            outputs,
+            [
+                r#"<r><!-- c --><x:a xmlns:x="urn:x"></x:a></r>"#,
+                r#"<r xmlns:x="urn:x" xmlns:y="urn:y"><x:a></x:a></r>"#,
+                r#"<r xmlns:y="urn:y"><x:a xmlns:x="urn:x"></x:a></r>"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn options_leave_document_unchanged() {
+        let input = r#"<!DOCTYPE r [<!ENTITY e "text">]><r xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:nil="true"><?strip me?>&e;</r>"#;
+        let document = Document::parse(input).unwrap();
+
+        let changing = CanonicalizationOptions {
+            coalesce_text: true,
+            strip_xsi_attributes: true,
+            strip_processing_instructions: vec!["strip".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(
+            document.canonicalize(changing),
+            Ok("<r>text</r>".to_owned())
+        );
+
+        // the entity reference is still there, which libxml2 can't canonicalize without the option
+        assert!(matches!(
+            document.canonicalize(CanonicalizationOptions::default()),
+            Err(CanonicalizationError::UnexpandedEntityReference { .. })
+        ));
+        let coalesce_only = CanonicalizationOptions {
+            coalesce_text: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            document.canonicalize(coalesce_only),
+            Ok(r#"<r xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:nil="true"><?strip me?>text</r>"#.to_owned())
+        );
+    }
+
+    #[test]
+    fn reused_parser_context() {
+        let small = "<a><b/></a>";
+        let large = format!("<a>{}</a>", "<b/>".repeat(SMALL_DOCUMENT_BYTES));
+
+        // alternate between the reused context and fresh ones, with errors in between
+        for _ in 0..3 {
+            assert_eq!(
+                parse(small)
+                    .unwrap()
+                    .canonicalize(Default::default())
+                    .unwrap(),
+                "<a><b></b></a>"
+            );
+            assert!(matches!(parse("<a>"), Err(CanonicalizationError::Parse(_))));
+            assert!(parse(&large).is_ok());
+        }
+
+        // documents parsed with the same context must stay valid independently
+        let first = parse("<first/>").unwrap();
+        let second = parse("<second/>").unwrap();
+        drop(first);
+        assert_eq!(
+            second.canonicalize(Default::default()).unwrap(),
+            "<second></second>"
+        );
+    }
+
+    #[test]
+    fn reused_parser_context_dictionary_is_bounded() {
+        for i in 0..2 * MAX_REUSED_DICT_SIZE {
+            parse(format!("<e{i}/>")).unwrap();
+        }
+
+        SMALL_DOCUMENT_PARSER.with(|parser| unsafe {
+            let parser = parser.borrow();
+            let dict = (*parser.0.unwrap()).dict;
+            assert!(xmlDictSize(dict) <= MAX_REUSED_DICT_SIZE + 1);
+        });
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(matches!(parse("<a>"), Err(CanonicalizationError::Parse(_))));
+    }
+}
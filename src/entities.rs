@@ -0,0 +1,272 @@
+//! Resolving external entities with a closure instead of the filesystem
+
+use crate::document::Document;
+use crate::{CanonicalizationError, CanonicalizationOptions};
+use libxml::bindings::{
+    xmlChar, xmlCharEncoding_XML_CHAR_ENCODING_NONE, xmlExternalEntityLoader,
+    xmlFreeParserInputBuffer, xmlGetExternalEntityLoader, xmlNewIOInputStream, xmlParserCtxtPtr,
+    xmlParserInputBufferCreateMem, xmlParserInputPtr, xmlParserOption_XML_PARSE_DTDLOAD,
+    xmlParserOption_XML_PARSE_NOENT, xmlSetExternalEntityLoader, xmlStrdup,
+};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_char, c_int, CStr};
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::ptr::{fn_addr_eq, null_mut};
+use std::sync::OnceLock;
+
+/// A loader closure, with its lifetime erased (to `'static`) while it's registered
+type Loader<'a> = dyn FnMut(&str, Option<&str>) -> Option<Vec<u8>> + 'a;
+
+/// The loader that was installed before ours, for threads that didn't register a closure
+static PREVIOUS_LOADER: OnceLock<xmlExternalEntityLoader> = OnceLock::new();
+
+thread_local! {
+    /// The closure of the innermost [with_entity_loader] call on this thread, if any
+    static LOADER: Cell<Option<*mut Loader<'static>>> = const { Cell::new(None) };
+    /// A panic of the closure, to resume once libxml2 has returned
+    static PANIC: RefCell<Option<Box<dyn Any + Send>>> = const { RefCell::new(None) };
+    /// The system ID of the first entity that couldn't be loaded, as libxml2 goes on parsing without some of them
+    static UNLOADED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Parse specified XML document, loading its external entities and external DTD subset with `loader`, and canonicalize it
+///
+/// `loader` gets the system ID (as resolved by libxml2) and the public ID if there is one, and returns the entity's
+/// bytes, or [None] if it can't be loaded, which fails with [CanonicalizationError::EntityNotLoaded]. Entities are
+/// substituted, so that references to them canonicalize as their content. Nothing is ever loaded from the filesystem or
+/// the network, also when `loader` returns [None].
+///
+/// The loader is registered with `xmlSetExternalEntityLoader`, which is global, but it's only used by parsing on the
+/// calling thread, other threads keep using the previous loader. If something else has called
+/// `xmlSetExternalEntityLoader` since, it's registered again before parsing. If `loader` panics, the panic is resumed
+/// once parsing stops.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_with_entity_loader, CanonicalizationOptions};
+///
+/// let document = r#"<!DOCTYPE a [<!ENTITY signed SYSTEM "urn:bundle:signed">]><a>&signed;</a>"#;
+/// let canonicalized = canonicalize_with_entity_loader(document, CanonicalizationOptions::default(), |system_id, _| {
+///     (system_id == "urn:bundle:signed").then(|| b"<b>from the bundle</b>".to_vec())
+/// })
+/// .unwrap();
+///
+/// assert_eq!(canonicalized, "<a><b>from the bundle</b></a>");
+/// ```
+pub fn canonicalize_with_entity_loader(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+    loader: impl FnMut(&str, Option<&str>) -> Option<Vec<u8>>,
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+
+    let flags = (xmlParserOption_XML_PARSE_NOENT | xmlParserOption_XML_PARSE_DTDLOAD) as c_int;
+    let (parsed, unloaded) = with_entity_loader(loader, || {
+        Document::read(document.as_bytes(), None, options.parse_options() | flags)
+    });
+    if let Some(system_id) = unloaded {
+        return Err(CanonicalizationError::EntityNotLoaded { system_id });
+    }
+    parsed?.coalesced_for(&options).canonicalize(options)
+}
+
+/// Run `f`, with everything libxml2 loads on the current thread in the meantime loaded by `loader` instead
+///
+/// Returns what `f` returns, and the system ID of the first entity that couldn't be loaded, if any. If `loader` panics,
+/// the panic is resumed once `f` returns.
+pub(crate) fn with_entity_loader<R>(
+    mut loader: impl FnMut(&str, Option<&str>) -> Option<Vec<u8>>,
+    f: impl FnOnce() -> R,
+) -> (R, Option<String>) {
+    /// Restores the outer registration, even if `f` panics
+    struct Registration(Option<*mut Loader<'static>>);
+
+    impl Drop for Registration {
+        fn drop(&mut self) {
+            LOADER.set(self.0);
+        }
+    }
+
+    install();
+    let loader: *mut Loader<'_> = &mut loader;
+    // SAFETY: only erases the lifetime, the registration is dropped before `loader` goes out of scope
+    let loader: *mut Loader<'static> = unsafe { std::mem::transmute(loader) };
+    let registration = Registration(LOADER.replace(Some(loader)));
+    UNLOADED.take();
+
+    let result = f();
+
+    drop(registration);
+    if let Some(panic) = PANIC.take() {
+        resume_unwind(panic);
+    }
+    (result, UNLOADED.take())
+}
+
+/// Install [load_entity] as libxml2's external entity loader, unless it already is
+///
+/// The loader it replaces the first time is kept for threads without a closure.
+fn install() {
+    // SAFETY: getting and setting the loader has no preconditions
+    unsafe {
+        PREVIOUS_LOADER.get_or_init(|| xmlGetExternalEntityLoader());
+        let installed = xmlGetExternalEntityLoader().is_some_and(|loader| {
+            fn_addr_eq(loader, load_entity as unsafe extern "C" fn(_, _, _) -> _)
+        });
+        if !installed {
+            xmlSetExternalEntityLoader(Some(load_entity));
+        }
+    }
+}
+
+/// libxml2 external entity loader, calling the closure registered on the current thread, or the previous loader without one
+unsafe extern "C" fn load_entity(
+    url: *const c_char,
+    id: *const c_char,
+    context: xmlParserCtxtPtr,
+) -> xmlParserInputPtr {
+    let Some(loader) = LOADER.get() else {
+        return match PREVIOUS_LOADER.get() {
+            Some(Some(previous)) => previous(url, id, context),
+            _ => null_mut(),
+        };
+    };
+
+    let string =
+        |value: *const c_char| (!value.is_null()).then(|| CStr::from_ptr(value).to_string_lossy());
+    let system_id = string(url);
+    let public_id = string(id);
+
+    // unwinding into libxml2 is undefined behavior
+    let loaded = catch_unwind(AssertUnwindSafe(|| {
+        (*loader)(
+            system_id.as_deref().unwrap_or_default(),
+            public_id.as_deref(),
+        )
+    }));
+    let unloaded = || {
+        UNLOADED.with_borrow_mut(|unloaded| {
+            unloaded.get_or_insert_with(|| system_id.as_deref().unwrap_or_default().to_owned());
+        });
+        null_mut()
+    };
+    let bytes = match loaded {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return unloaded(),
+        Err(panic) => {
+            PANIC.set(Some(panic));
+            return null_mut();
+        }
+    };
+    let Ok(size) = c_int::try_from(bytes.len()) else {
+        return unloaded();
+    };
+
+    // libxml2 copies the bytes into the buffer
+    let buffer = xmlParserInputBufferCreateMem(
+        bytes.as_ptr() as *const c_char,
+        size,
+        xmlCharEncoding_XML_CHAR_ENCODING_NONE,
+    );
+    if buffer.is_null() {
+        return unloaded();
+    }
+    let input = xmlNewIOInputStream(context, buffer, xmlCharEncoding_XML_CHAR_ENCODING_NONE);
+    if input.is_null() {
+        xmlFreeParserInputBuffer(buffer);
+        return unloaded();
+    }
+    // relative references in the entity are resolved against its system ID, and libxml2 frees the copy with the input
+    if !url.is_null() {
+        (*input).filename = xmlStrdup(url as *const xmlChar) as *const c_char;
+    }
+    input
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonicalize_xml;
+
+    const DOCUMENT: &str = r#"<!DOCTYPE a SYSTEM "a.dtd" [
+<!ENTITY by-system SYSTEM "urn:system">
+<!ENTITY by-public PUBLIC "-//example//public" "public.xml">
+]>
+<a>&by-system;&by-public;&from-dtd;</a>"#;
+
+    #[test]
+    fn loads_from_closure() {
+        let mut requests = vec![];
+        let canonicalized = canonicalize_with_entity_loader(
+            DOCUMENT,
+            Default::default(),
+            |system_id, public_id| {
+                requests.push((system_id.to_owned(), public_id.map(str::to_owned)));
+                match system_id {
+                    "a.dtd" => Some(br#"<!ENTITY from-dtd "dtd">"#.to_vec()),
+                    "urn:system" => Some(b"<b c='1'/>".to_vec()),
+                    "public.xml" => Some(b"public".to_vec()),
+                    _ => None,
+                }
+            },
+        )
+        .unwrap();
+
+        assert_eq!(canonicalized, r#"<a><b c="1"></b>publicdtd</a>"#);
+        assert_eq!(
+            requests,
+            [
+                ("a.dtd".to_owned(), None),
+                ("urn:system".to_owned(), None),
+                (
+                    "public.xml".to_owned(),
+                    Some("-//example//public".to_owned())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unresolved() {
+        assert_eq!(
+            canonicalize_with_entity_loader(DOCUMENT, Default::default(), |_, _| None),
+            Err(CanonicalizationError::EntityNotLoaded {
+                system_id: "a.dtd".to_owned()
+            })
+        );
+        // libxml2 would leave the entity empty
+        assert_eq!(
+            canonicalize_with_entity_loader(
+                r#"<!DOCTYPE a [<!ENTITY e SYSTEM "x">]><a>&e;</a>"#,
+                Default::default(),
+                |_, _| None
+            ),
+            Err(CanonicalizationError::EntityNotLoaded {
+                system_id: "x".to_owned()
+            })
+        );
+
+        // without a loader, nothing is loaded
+        assert!(matches!(
+            canonicalize_xml(DOCUMENT, Default::default()),
+            Err(CanonicalizationError::UnexpandedEntityReference { .. })
+        ));
+    }
+
+    #[test]
+    fn panicking_loader() {
+        let result = catch_unwind(|| {
+            canonicalize_with_entity_loader(DOCUMENT, Default::default(), |_, _| {
+                panic!("loader failed")
+            })
+        });
+        let panic = result.unwrap_err();
+        assert_eq!(panic.downcast_ref::<&str>(), Some(&"loader failed"));
+
+        // the registration doesn't outlive the call
+        assert!(LOADER.get().is_none());
+    }
+}
@@ -0,0 +1,97 @@
+//! Escaping canonical output for embedding it elsewhere
+
+/// Escape `text` for use as XML character data, the same way C14N escapes text nodes
+pub(crate) fn xml_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\r' => escaped.push_str("&#xD;"),
+            _ => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+/// Escape `value` for use in a double-quoted attribute value, the same way C14N escapes attribute values
+pub(crate) fn xml_attribute_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for char in value.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' => escaped.push_str("&#x9;"),
+            '\n' => escaped.push_str("&#xA;"),
+            '\r' => escaped.push_str("&#xD;"),
+            _ => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+/// Undo the escaping C14N applies to attribute values
+pub(crate) fn unescape_attribute_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let replacement = [
+            ("&amp;", '&'),
+            ("&lt;", '<'),
+            ("&quot;", '"'),
+            ("&#x9;", '\t'),
+            ("&#xA;", '\n'),
+            ("&#xD;", '\r'),
+        ]
+        .into_iter()
+        .find(|(reference, _)| rest.starts_with(reference));
+        match replacement {
+            Some((reference, char)) => {
+                unescaped.push(char);
+                rest = &rest[reference.len()..];
+            }
+            // can't happen in canonical output
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attribute_values_round_trip() {
+        let value = "a&b<c>\"d'\t\n\r";
+        assert_eq!(
+            xml_attribute_value(value),
+            "a&amp;b&lt;c>&quot;d'&#x9;&#xA;&#xD;"
+        );
+        assert_eq!(unescape_attribute_value(&xml_attribute_value(value)), value);
+    }
+
+    #[test]
+    fn unescapes_attribute_values() {
+        assert_eq!(
+            unescape_attribute_value("a&amp;lt;&lt;>&quot;&#x9;&#xA;&#xD;&"),
+            "a&lt;<>\"\t\n\r&"
+        );
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            xml_text("<a b=\"&amp;\">\r\n'x'</a>"),
+            "&lt;a b=\"&amp;amp;\"&gt;&#xD;\n'x'&lt;/a&gt;"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Canonicalization via `xmlC14NExecute`, for deciding node by node what ends up in the output
+
+use crate::inject;
+use crate::output::XmlOutput;
+use crate::{
+    free_xml_string_vec, to_xml_string_vec, CanonicalizationError, CanonicalizationErrorCode,
+    CanonicalizationMode,
+};
+use libxml::bindings::{
+    xmlC14NExecute, xmlDocPtr, xmlNodePtr, xmlOutputBufferClose, xmlOutputBufferCreateIO,
+};
+use std::ffi::{c_char, c_int, c_void};
+use std::ptr::null_mut;
+
+/// What to do with the output of [canonicalize_with_visibility]
+#[derive(Clone, Copy)]
+pub(crate) enum Sink {
+    /// Collect it, giving [Canonicalized::Output]
+    Collect,
+    /// Only count its bytes, giving [Canonicalized::Length]
+    Count,
+}
+
+/// The canonical form, or with [Sink::Count] only its length in bytes
+pub(crate) enum Canonicalized {
+    Output(XmlOutput),
+    Length(usize),
+}
+
+/// Canonicalize `document`, including only the nodes for which `is_visible(node, parent)` returns true
+///
+/// Note that for namespace nodes, libxml2 passes an `xmlNsPtr` cast to [xmlNodePtr] as `node`,
+/// so `is_visible` must only look at the `type_` field before making sure it's not a namespace.
+///
+/// The output goes to `sink` straight from libxml2's output callback, so it isn't copied out of a libxml2 buffer. With
+/// `max_output_bytes`, fails with [CanonicalizationError::OutputTooLarge] once the output grows beyond it, and never
+/// holds much more than that in memory.
+///
+/// # Safety
+///
+/// `document` must point to a valid document
+pub(crate) unsafe fn canonicalize_with_visibility<F>(
+    document: xmlDocPtr,
+    mode: CanonicalizationMode,
+    inclusive_ns_prefixes: Vec<String>,
+    with_comments: bool,
+    is_visible: &F,
+    max_output_bytes: Option<usize>,
+    sink: Sink,
+) -> Result<Canonicalized, CanonicalizationError>
+where
+    F: Fn(xmlNodePtr, xmlNodePtr) -> bool,
+{
+    let mut ns_list_c = to_xml_string_vec(inclusive_ns_prefixes);
+    let mut output = CollectedOutput {
+        bytes: match sink {
+            Sink::Collect => Some(vec![]),
+            Sink::Count => None,
+        },
+        written: 0,
+        limit: max_output_bytes,
+        exceeded: false,
+    };
+    let buffer = xmlOutputBufferCreateIO(
+        Some(write_collected),
+        None,
+        &mut output as *mut CollectedOutput as *mut c_void,
+        null_mut(),
+    );
+    if buffer.is_null() {
+        free_xml_string_vec(ns_list_c);
+        return Err(CanonicalizationErrorCode::new(-1)
+            .with_message(Some("could not create an output buffer".to_owned()))
+            .into());
+    }
+
+    let mode = if inject::take(inject::Failure::Canonicalization) {
+        -1
+    } else {
+        mode.to_c_int()
+    };
+    let return_code = xmlC14NExecute(
+        document,
+        Some(is_visible_trampoline::<F>),
+        is_visible as *const F as *mut c_void,
+        mode,
+        ns_list_c.as_mut_ptr(),
+        c_int::from(with_comments),
+        buffer,
+    );
+
+    free_xml_string_vec(ns_list_c);
+    // flushes whatever is still buffered into `output`
+    xmlOutputBufferClose(buffer);
+
+    match (max_output_bytes, output.exceeded) {
+        // the limit makes writes fail, so check it before the return code
+        (Some(limit), true) => Err(CanonicalizationError::OutputTooLarge { limit }),
+        _ if return_code < 0 => Err(CanonicalizationErrorCode::new(return_code).into()),
+        _ => Ok(match output.bytes {
+            Some(bytes) => Canonicalized::Output(XmlOutput::from_bytes(bytes)),
+            None => Canonicalized::Length(output.written),
+        }),
+    }
+}
+
+/// Where output goes, with an optional size limit, see [write_collected]
+struct CollectedOutput {
+    /// None if only counting
+    bytes: Option<Vec<u8>>,
+    written: usize,
+    limit: Option<usize>,
+    exceeded: bool,
+}
+
+/// libxml2 output callback appending to (or only counting in) a [CollectedOutput], which fails once the limit would be
+/// exceeded
+///
+/// libxml2 then marks the output buffer as failed and discards everything written to it afterwards.
+unsafe extern "C" fn write_collected(
+    context: *mut c_void,
+    buffer: *const c_char,
+    len: c_int,
+) -> c_int {
+    let output = &mut *(context as *mut CollectedOutput);
+    let Ok(length) = usize::try_from(len) else {
+        return -1;
+    };
+    if output
+        .limit
+        .is_some_and(|limit| output.written + length > limit)
+    {
+        output.exceeded = true;
+        return -1;
+    }
+    output.written += length;
+    if let Some(bytes) = &mut output.bytes {
+        bytes.extend_from_slice(std::slice::from_raw_parts(buffer as *const u8, length));
+    }
+    len
+}
+
+unsafe extern "C" fn is_visible_trampoline<F>(
+    user_data: *mut c_void,
+    node: xmlNodePtr,
+    parent: xmlNodePtr,
+) -> c_int
+where
+    F: Fn(xmlNodePtr, xmlNodePtr) -> bool,
+{
+    let is_visible = &*(user_data as *const F);
+    c_int::from(is_visible(node, parent))
+}
@@ -0,0 +1,100 @@
+//! Listing the IDs of a document, for building reference maps
+
+use crate::document::parse;
+use crate::{tree, CanonicalizationError};
+
+/// Namespace-qualified name of an element
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct QName {
+    /// Namespace URI, empty if the element is in no namespace
+    pub namespace_uri: String,
+    /// Name without the prefix
+    pub local_name: String,
+}
+
+/// Parse specified XML document and list its IDs, each with the name of the element it's on, in document order
+///
+/// IDs are found like [canonicalize_by_ids](crate::canonicalize_by_ids) finds them: `xml:id` attributes, attributes
+/// declared as IDs in the DTD, and unqualified `ID`, `Id` or `id` attributes. An ID used by several elements is listed
+/// once for each of them, so that tooling can spot what `canonicalize_by_ids` would reject. A document without IDs gives
+/// an empty list.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{collect_ids, QName};
+///
+/// let document = r#"<r xmlns:s="urn:s"><s:a ID="first"/><b xml:id="second"/></r>"#;
+/// let ids = collect_ids(document).unwrap();
+///
+/// assert_eq!(
+///     ids,
+///     [
+///         ("first".to_owned(), QName { namespace_uri: "urn:s".to_owned(), local_name: "a".to_owned() }),
+///         ("second".to_owned(), QName { namespace_uri: String::new(), local_name: "b".to_owned() }),
+///     ]
+/// );
+/// ```
+pub fn collect_ids(
+    document: impl AsRef<str>,
+) -> Result<Vec<(String, QName)>, CanonicalizationError> {
+    let document = parse(document)?;
+
+    // SAFETY: the root element and everything under it are valid for as long as the document is
+    unsafe {
+        Ok(tree::ids(document.root_element())
+            .into_iter()
+            .map(|(id, element)| {
+                let (namespace_uri, local_name) = tree::expanded_name(element);
+                (
+                    id,
+                    QName {
+                        namespace_uri,
+                        local_name,
+                    },
+                )
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(namespace_uri: &str, local_name: &str) -> QName {
+        QName {
+            namespace_uri: namespace_uri.to_owned(),
+            local_name: local_name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn ids_in_document_order() {
+        let input = r#"<!DOCTYPE r [<!ATTLIST c key ID #IMPLIED>]>
+<r xmlns="urn:r" id="root">
+  <a Id="1" xml:id="2"><c key="3"/></a>
+  <b ID="1" x:ID="not-an-id" xmlns:x="urn:x"/>
+</r>"#;
+
+        assert_eq!(
+            collect_ids(input).unwrap(),
+            [
+                ("root".to_owned(), name("urn:r", "r")),
+                ("1".to_owned(), name("urn:r", "a")),
+                ("2".to_owned(), name("urn:r", "a")),
+                ("3".to_owned(), name("urn:r", "c")),
+                ("1".to_owned(), name("urn:r", "b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_ids() {
+        assert_eq!(collect_ids("<a><b name='x'/></a>").unwrap(), []);
+        assert!(matches!(
+            collect_ids("<a>"),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+}
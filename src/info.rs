@@ -0,0 +1,110 @@
+//! What the parsed document said about itself, for audit records
+
+use crate::document::Document;
+use crate::{CanonicalizationError, CanonicalizationOptions};
+use libxml::bindings::xmlChar;
+use std::ffi::CStr;
+
+/// The XML version and encoding of a parsed document, see [canonicalize_with_doc_info]
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct DocInfo {
+    /// Version from the XML declaration (`1.0` or `1.1`); libxml2 assumes `1.0` if there is no declaration
+    pub version: Option<String>,
+    /// Encoding the document was decoded from, as declared (e.g. `ISO-8859-1`), or [None] if libxml2 used its default
+    ///
+    /// libxml2 detects UTF-16 from a byte order mark, but only records an encoding that was declared (and the name
+    /// isn't normalized, so `utf-8` stays lower case).
+    pub input_encoding: Option<String>,
+}
+
+/// Parse specified XML document and canonicalize it, returning the canonical form together with the XML version and
+/// input encoding the document declared
+///
+/// For provenance records, e.g. logging what was signed. The document is taken as bytes, so that encodings other than
+/// UTF-8 are decoded as declared; `&str` documents work as well.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_with_doc_info, CanonicalizationOptions, DocInfo};
+///
+/// let document = b"<?xml version='1.0' encoding='ISO-8859-1'?><a>caf\xE9</a>";
+/// let (canonicalized, info) = canonicalize_with_doc_info(document, CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, "<a>café</a>");
+/// assert_eq!(
+///     info,
+///     DocInfo {
+///         version: Some("1.0".to_owned()),
+///         input_encoding: Some("ISO-8859-1".to_owned()),
+///     }
+/// );
+/// ```
+pub fn canonicalize_with_doc_info(
+    document: impl AsRef<[u8]>,
+    options: CanonicalizationOptions,
+) -> Result<(String, DocInfo), CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let parsed = Document::read_for(document, None, &options)?;
+
+    let string = |value: *const xmlChar| {
+        // SAFETY: the strings are null-terminated, and owned by the document
+        (!value.is_null()).then(|| {
+            unsafe { CStr::from_ptr(value as *const _) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    };
+    // SAFETY: the document is valid
+    let info = unsafe {
+        let pointer = parsed.as_ptr();
+        DocInfo {
+            version: string((*pointer).version),
+            input_encoding: string((*pointer).encoding),
+        }
+    };
+
+    Ok((parsed.canonicalize(options)?, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(version: Option<&str>, input_encoding: Option<&str>) -> DocInfo {
+        DocInfo {
+            version: version.map(str::to_owned),
+            input_encoding: input_encoding.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn doc_info() {
+        let utf16: Vec<u8> = "\u{feff}<a>é</a>"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+
+        for (input, expected) in [
+            (b"<a/>".to_vec(), info(Some("1.0"), None)),
+            (
+                b"<?xml version='1.1'?><a/>".to_vec(),
+                info(Some("1.1"), None),
+            ),
+            (
+                b"<?xml version='1.0' encoding='utf-8'?><a/>".to_vec(),
+                info(Some("1.0"), Some("utf-8")),
+            ),
+            (utf16, info(Some("1.0"), None)),
+        ] {
+            let (_, actual) = canonicalize_with_doc_info(&input, Default::default()).unwrap();
+            assert_eq!(actual, expected, "{input:?}");
+        }
+
+        assert!(matches!(
+            canonicalize_with_doc_info("<a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+}
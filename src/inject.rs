@@ -0,0 +1,48 @@
+//! Forcing libxml2 to fail, for covering error paths in tests
+//!
+//! These inputs reliably make a step fail without any help:
+//!
+//! - parsing: malformed XML like `<a>` ([Parse](crate::CanonicalizationError::Parse)), and documents without a document
+//!   element like `<!-- only -->` ([EmptyDocument](crate::CanonicalizationError::EmptyDocument))
+//! - canonicalizing, where libxml2 returns -1: relative namespace URIs like `<a xmlns="relative"/>`
+//!   ([RelativeNamespaceUri](crate::CanonicalizationError::RelativeNamespaceUri)), namespace URIs libxml2 can't
+//!   parse like `<a xmlns="urn:a b"/>` ([InvalidNamespaceUri](crate::CanonicalizationError::InvalidNamespaceUri)),
+//!   and unexpanded entity references ([UnexpandedEntityReference](crate::CanonicalizationError::UnexpandedEntityReference))
+//!
+//! Anything else that makes libxml2 fail (like running out of memory) can't be triggered by input, that's what
+//! `fail_next` is for. It makes libxml2 itself fail, by handing it an invalid argument, so that the whole error path
+//! runs: libxml2's error report, freeing what was allocated, and mapping the error.
+
+#[cfg(test)]
+use std::cell::Cell;
+
+/// A step that can be made to fail
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(crate) enum Failure {
+    /// Parsing, as if the document was `<`
+    Parse,
+    /// Canonicalizing, as if the mode was unknown to libxml2
+    Canonicalization,
+}
+
+#[cfg(test)]
+thread_local! {
+    static NEXT: Cell<Option<Failure>> = const { Cell::new(None) };
+}
+
+/// Make the next `failure` step on the current thread fail
+#[cfg(test)]
+pub(crate) fn fail_next(failure: Failure) {
+    NEXT.set(Some(failure));
+}
+
+/// Whether the `failure` step about to run should fail, which is never the case outside of tests
+pub(crate) fn take(failure: Failure) -> bool {
+    #[cfg(test)]
+    if NEXT.get() == Some(failure) {
+        NEXT.set(None);
+        return true;
+    }
+    let _ = failure;
+    false
+}
@@ -1,12 +1,100 @@
 #![doc = include_str!("../Readme.md")]
 
+use document::ReusedParser;
+use execute::{Canonicalized, Sink};
 use libxml::bindings::{
-    xmlC14NDocDumpMemory, xmlChar, xmlDocPtr, xmlFree, xmlFreeDoc, xmlNodeSet, xmlReadDoc,
+    xmlAttrPtr, xmlC14NDocDumpMemory, xmlChar, xmlCharEncCloseFunc, xmlDocGetRootElement,
+    xmlDocPtr, xmlElementType_XML_ATTRIBUTE_NODE, xmlElementType_XML_COMMENT_NODE,
+    xmlElementType_XML_NAMESPACE_DECL, xmlElementType_XML_PI_NODE, xmlError,
+    xmlErrorDomain_XML_FROM_PARSER, xmlFeature_XML_WITH_C14N, xmlFindCharEncodingHandler,
+    xmlGetLastError, xmlHasFeature, xmlNodePtr, xmlNodeSet, xmlNsPtr,
+    xmlParserErrors_XML_ERR_INVALID_CHAR, xmlParserOption_XML_PARSE_DTDATTR,
+    xmlParserOption_XML_PARSE_DTDVALID, xmlParserOption_XML_PARSE_NONET,
 };
-use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use output::XmlOutput;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fmt;
 use std::iter::once;
-use std::ptr::null;
+use std::ptr::{null, null_mut};
+use std::str::Utf8Error;
 use thiserror::Error;
+use tree::Uncanonicalizable;
+use xpath::{NodeSet, XPathContext};
+
+mod ascii;
+#[cfg(feature = "cache")]
+mod cache;
+mod comparison;
+mod concatenated;
+mod context;
+mod diagnostics;
+mod diff;
+#[cfg(feature = "digest")]
+mod digest;
+mod document;
+mod entities;
+mod escape;
+mod execute;
+mod ids;
+mod info;
+mod inject;
+mod namespaces;
+mod non_conformant;
+mod output;
+mod plan;
+mod push;
+mod ranges;
+mod reference;
+mod schema;
+mod shape;
+#[cfg(feature = "sign")]
+mod sign;
+mod timings;
+mod tokens;
+mod tree;
+mod version;
+mod xmlsec;
+mod xpath;
+#[cfg(feature = "xslt")]
+mod xslt;
+
+pub use ascii::{canonicalize_ascii, NonAsciiHandling};
+#[cfg(feature = "cache")]
+pub use cache::CachedCanonicalizer;
+pub use comparison::{canonicalize_for_comparison, ComparisonForm, ComparisonOptions};
+pub use context::{Canonicalizer, CanonicalizerContext};
+pub use diagnostics::{Diagnostic, DiagnosticLevel};
+pub use diff::{
+    canonical_diff, canonical_equal, explain_mismatch, CanonicalDifference, MismatchReport,
+};
+#[cfg(feature = "digest")]
+pub use digest::{
+    canonicalize_hmac, digest_reference, verify_digest, DigestAlgorithm, DigestReference,
+};
+pub use document::{parse, Document};
+pub use entities::canonicalize_with_entity_loader;
+pub use ids::{collect_ids, QName};
+pub use info::{canonicalize_with_doc_info, DocInfo};
+pub use namespaces::{
+    canonicalize_with_namespace_count, canonicalize_with_namespaces, NamespaceReport,
+};
+#[cfg(feature = "attribute-order")]
+pub use non_conformant::AttributeOrder;
+pub use non_conformant::NonConformantOptions;
+pub use output::RawCanonicalOutput;
+pub use plan::{describe, CanonicalizationPlan};
+pub use push::PushCanonicalizer;
+pub use ranges::{canonicalize_with_element_ranges, ElementRange};
+pub use reference::{canonicalize_reference, reference_octets};
+pub use schema::{canonicalize_validated, Schema};
+#[cfg(feature = "sign")]
+pub use sign::{sign_canonical, SignError, SigningKey};
+pub use timings::{canonicalize_with_timings, Timings};
+pub use version::{libxml_version, LibxmlVersion};
+pub use xmlsec::canonicalize_xmlsec_compat;
+#[cfg(feature = "xslt")]
+pub use xslt::canonicalize_xslt;
 
 /// Options for configuring how to canonicalize XML
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
@@ -18,21 +106,492 @@ pub struct CanonicalizationOptions {
     ///
     /// Doesn't apply to other canonicalization modes.
     pub inclusive_ns_prefixes: Vec<String>,
+    /// If set, keep only comments inside (or selected by) nodes matching this XPath expression, and remove all others.
+    ///
+    /// Overrides [keep_comments](Self::keep_comments).
+    pub keep_comments_under: Option<String>,
+    /// Namespace prefixes (mapped to their URIs) usable in XPath expressions like [keep_comments_under](Self::keep_comments_under)
+    ///
+    /// XPath doesn't see the prefixes declared in the document, only these and the [WELL_KNOWN_XPATH_NAMESPACES], which
+    /// prefixes bound here override.
+    pub xpath_namespaces: BTreeMap<String, String>,
+    /// If true, don't bind the [WELL_KNOWN_XPATH_NAMESPACES] prefixes in XPath expressions, only the
+    /// [xpath_namespaces](Self::xpath_namespaces)
+    pub skip_well_known_xpath_namespaces: bool,
+    /// If set, reject documents longer than this many bytes with [CanonicalizationError::InputTooLarge] before parsing them
+    pub max_input_bytes: Option<usize>,
+    /// If set, fail with [CanonicalizationError::OutputTooLarge] as soon as the canonical form grows beyond this many bytes
+    ///
+    /// The output is never held in memory in full then, but the document is still parsed in full, see
+    /// [max_input_bytes](Self::max_input_bytes) for that. The limit applies before any [non_conformant](Self::non_conformant) rewriting.
+    pub max_output_bytes: Option<usize>,
+    /// If set, fail with [CanonicalizationError::MaxDepthExceeded] before canonicalizing if elements are nested more than
+    /// this many levels deep, counting the document element as 1
+    ///
+    /// For services exposed to untrusted input. libxml2 itself already refuses to parse documents nested more than 256
+    /// levels deep (unless `XML_PARSE_HUGE` is passed in [raw_parse_options](Self::raw_parse_options)), this sets a
+    /// lower bound. The whole document is checked, also when only a subset of it is canonicalized.
+    pub max_depth: Option<usize>,
+    /// If true, add attributes defaulted by the DTD (e.g. `<!ATTLIST e a CDATA "default">`) to elements that don't specify them
+    ///
+    /// This loads the external DTD subset from the local filesystem if there is one, network access is never used.
+    pub dtd_default_attributes: bool,
+    /// Advanced: extra libxml2 `xmlParserOption` bits, OR'd into the flags the named options produce
+    ///
+    /// An escape hatch for flags this crate doesn't name (yet), prefer the named options whenever there is one.
+    /// Nothing stops these from undoing the crate's protections: e.g. `XML_PARSE_NOENT` substitutes entities (opening the door
+    /// to XXE attacks), and `XML_PARSE_NOBLANKS` changes the canonical output of the same document. Don't pass untrusted values here.
+    pub raw_parse_options: i32,
+    /// If true, replace references to internal entities (declared in the internal DTD subset) with their content before
+    /// canonicalizing, so that the text around them becomes one text node
+    ///
+    /// The specs canonicalize entity references as their replacement text, but libxml2 refuses to canonicalize documents that
+    /// still contain them, which they do unless parsed with `XML_PARSE_NOENT`. Unlike that flag, this never loads external
+    /// entities, so it's safe for untrusted input. References to external entities are still an error. A [Document]
+    /// parsed without this option is left alone, and a copy of it canonicalized instead, except that a subset of it
+    /// (see [canonicalize_subtree]) can't be copied and fails with [CanonicalizationError::InvalidOptions].
+    pub coalesce_text: bool,
+    /// If true, leave all attributes in the XML Schema instance namespace (`xsi:schemaLocation`, `xsi:type` and so on)
+    /// out of the output, along with declarations of that namespace that no element uses
+    ///
+    /// **This changes the signed content**: a signature over the result doesn't cover these attributes, so only use it if
+    /// both sides agreed not to sign them. Note that `xsi:type` can be meaningful.
+    pub strip_xsi_attributes: bool,
+    /// Leave processing instructions with these targets (e.g. `xml-stylesheet`) out of the output, keeping all others
+    ///
+    /// **This changes the signed content**: canonical XML keeps processing instructions, so a signature over the result
+    /// doesn't cover the removed ones, only use it if both sides agreed not to sign them. Targets are compared exactly,
+    /// and a processing instruction that is itself the root of a subset (see [canonicalize_subtree]) is kept.
+    pub strip_processing_instructions: Vec<String>,
+    /// If true, check that the output has the shape of canonical XML (no empty-element tags, namespace declarations first
+    /// and sorted, and so on), and fail with [CanonicalizationError::NonCanonicalOutput] if it doesn't
+    ///
+    /// A safety net against libxml2 versions that differ in details, for pinned reference vectors. The check runs before
+    /// any [non_conformant](Self::non_conformant) rewriting, and doesn't catch every possible deviation.
+    pub verify_output: bool,
+    /// If true, in [CanonicalizationMode::ExclusiveCanonical1_0], check that the output declares every one of the
+    /// [inclusive_ns_prefixes](Self::inclusive_ns_prefixes), and fail with
+    /// [CanonicalizationError::InclusivePrefixNotRendered] if it doesn't
+    ///
+    /// A prefix that isn't in scope in the canonicalized part of the document is silently left out, so a typo in the list
+    /// would otherwise go unnoticed. `#default` needs a non-empty default namespace. The check runs before any
+    /// [non_conformant](Self::non_conformant) rewriting and does nothing in the other modes.
+    pub verify_inclusive_prefixes: bool,
+    /// If true, fail with [CanonicalizationError::CommentRejected] if the document (or the selected subset) contains any
+    /// comment, instead of leaving it out of the output
+    ///
+    /// For strict profiles that forbid comments in signed content, so that `WithComments` and plain algorithms can never
+    /// disagree about what was signed. Applies regardless of [keep_comments](Self::keep_comments).
+    pub reject_comments: bool,
+    /// If true, fail with [CanonicalizationError::ControlCharacterRejected] if text or an attribute value in the document
+    /// (or the selected subset) contains a control character other than tab, line feed and carriage return
+    ///
+    /// A content policy for downstream systems that can't handle them. Most C0 controls (like form feed) are never
+    /// allowed in XML 1.0 and already fail to parse, this catches the ones that are allowed: DEL and the C1 controls
+    /// (U+007F to U+009F).
+    pub reject_control_characters: bool,
+    /// If true, fail with [CanonicalizationError::MixedContent] if an element in the document (or the selected subset) has
+    /// both child elements and text other than whitespace, like `<a>text<b/></a>`
+    ///
+    /// A content policy for signature profiles that require element-only content, so that verifiers can't disagree about
+    /// which text belongs where. Text-only elements and whitespace between child elements (like indentation) are fine.
+    pub require_element_only: bool,
+    /// If true, fail with [CanonicalizationError::DuplicateId] if two IDs in the document have the same value, even if
+    /// neither is referenced
+    ///
+    /// Duplicate IDs are how signature wrapping attacks make a reference resolve to an element of the attacker's choosing.
+    /// IDs are found like [canonicalize_by_ids] finds them, plus the [extra_id_attributes](Self::extra_id_attributes). The
+    /// whole document is checked, also when only a subset of it is canonicalized.
+    pub reject_duplicate_ids: bool,
+    /// If true, fail with [CanonicalizationError::PrefixRedefined] if an element in the document (or the selected subset)
+    /// binds a namespace prefix to a different URI than an ancestor (also one outside the subset) does
+    ///
+    /// A hardening measure for signature profiles where the same prefix must mean the same thing throughout signed
+    /// content. Declaring a prefix again with the same URI is fine, and so is changing the default namespace.
+    pub reject_prefix_redefinition: bool,
+    /// Attributes that [reject_duplicate_ids](Self::reject_duplicate_ids) treats as IDs as well, like `wsu:Id`
+    ///
+    /// Use an empty namespace URI for unqualified attributes.
+    pub extra_id_attributes: Vec<QName>,
+    /// Deviations from the spec, for interoperating with broken peers. See [NonConformantOptions].
+    pub non_conformant: NonConformantOptions,
+}
+
+/// Prefixes bound in XPath expressions unless [CanonicalizationOptions::skip_well_known_xpath_namespaces] is set, with
+/// the namespaces XML Signature, WS-Security, SAML 2.0 and SOAP 1.1 documents conventionally use them for
+///
+/// Bind `soap` to `http://www.w3.org/2003/05/soap-envelope` in [CanonicalizationOptions::xpath_namespaces] for SOAP 1.2.
+pub const WELL_KNOWN_XPATH_NAMESPACES: [(&str, &str); 7] = [
+    ("ds", "http://www.w3.org/2000/09/xmldsig#"),
+    ("dsig11", "http://www.w3.org/2009/xmldsig11#"),
+    (
+        "wsse",
+        "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd",
+    ),
+    ("wsu", WSU_NAMESPACE),
+    ("saml", "urn:oasis:names:tc:SAML:2.0:assertion"),
+    ("samlp", "urn:oasis:names:tc:SAML:2.0:protocol"),
+    ("soap", "http://schemas.xmlsoap.org/soap/envelope/"),
+];
+
+/// Namespace of the `InclusiveNamespaces` element of exclusive canonicalization
+const EXCLUSIVE_C14N_NAMESPACE: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+
+/// W3C algorithm identifiers, as used in e.g. `<ds:CanonicalizationMethod Algorithm="...">`, with the mode and comment
+/// handling they stand for
+const ALGORITHM_URIS: [(CanonicalizationMode, bool, &str); 6] = [
+    (
+        CanonicalizationMode::Canonical1_0,
+        false,
+        "http://www.w3.org/TR/2001/REC-xml-c14n-20010315",
+    ),
+    (
+        CanonicalizationMode::Canonical1_0,
+        true,
+        "http://www.w3.org/TR/2001/REC-xml-c14n-20010315#WithComments",
+    ),
+    (
+        CanonicalizationMode::ExclusiveCanonical1_0,
+        false,
+        "http://www.w3.org/2001/10/xml-exc-c14n#",
+    ),
+    (
+        CanonicalizationMode::ExclusiveCanonical1_0,
+        true,
+        "http://www.w3.org/2001/10/xml-exc-c14n#WithComments",
+    ),
+    (
+        CanonicalizationMode::Canonical1_1,
+        false,
+        "http://www.w3.org/2006/12/xml-c14n11",
+    ),
+    (
+        CanonicalizationMode::Canonical1_1,
+        true,
+        "http://www.w3.org/2006/12/xml-c14n11#WithComments",
+    ),
+];
+
+impl CanonicalizationOptions {
+    /// Options for the algorithm with the given W3C identifier, e.g. `http://www.w3.org/2001/10/xml-exc-c14n#`
+    ///
+    /// Sets [mode](Self::mode) and [keep_comments](Self::keep_comments), everything else is left at the default.
+    /// Fails with [CanonicalizationError::UnknownAlgorithm] for anything but the six C14N 1.0, 1.1 and exclusive
+    /// 1.0 identifiers, with and without comments.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use xml_c14n::{CanonicalizationMode, CanonicalizationOptions};
+    ///
+    /// let options =
+    ///     CanonicalizationOptions::from_algorithm_uri("http://www.w3.org/2006/12/xml-c14n11#WithComments").unwrap();
+    ///
+    /// assert_eq!(options.mode, CanonicalizationMode::Canonical1_1);
+    /// assert!(options.keep_comments);
+    /// assert_eq!(options.algorithm_uri(), "http://www.w3.org/2006/12/xml-c14n11#WithComments");
+    /// ```
+    ///
+    /// The result is a starting point, e.g. for the `InclusiveNamespaces PrefixList` of an exclusive transform:
+    ///
+    /// ```
+    /// use xml_c14n::{canonicalize_xml, CanonicalizationOptions};
+    ///
+    /// let options = CanonicalizationOptions {
+    ///     inclusive_ns_prefixes: vec!["x".to_owned()],
+    ///     ..CanonicalizationOptions::from_algorithm_uri("http://www.w3.org/2001/10/xml-exc-c14n#").unwrap()
+    /// };
+    ///
+    /// let canonicalized = canonicalize_xml(r#"<a xmlns:x="urn:x" xmlns:y="urn:y"/>"#, options).unwrap();
+    /// assert_eq!(canonicalized, r#"<a xmlns:x="urn:x"></a>"#);
+    /// ```
+    pub fn from_algorithm_uri(uri: &str) -> Result<Self, CanonicalizationError> {
+        let (mode, keep_comments, _) = ALGORITHM_URIS
+            .into_iter()
+            .find(|&(_, _, algorithm_uri)| algorithm_uri == uri)
+            .ok_or_else(|| CanonicalizationError::UnknownAlgorithm(uri.to_owned()))?;
+        Ok(CanonicalizationOptions {
+            mode,
+            keep_comments,
+            ..Default::default()
+        })
+    }
+
+    /// W3C identifier of the algorithm these options implement, given [mode](Self::mode) and [keep_comments](Self::keep_comments)
+    ///
+    /// Other options (e.g. [keep_comments_under](Self::keep_comments_under) or [non_conformant](Self::non_conformant))
+    /// aren't reflected, as the standard identifiers have no way to express them.
+    pub fn algorithm_uri(&self) -> &'static str {
+        ALGORITHM_URIS
+            .into_iter()
+            .find(|&(mode, keep_comments, _)| {
+                mode == self.mode && keep_comments == self.keep_comments
+            })
+            .map(|(_, _, uri)| uri)
+            .unwrap()
+    }
+
+    /// `<ds:CanonicalizationMethod>` element for these options, to put into a `<ds:SignedInfo>` that declares the `ds` prefix
+    ///
+    /// The `Algorithm` is [algorithm_uri](Self::algorithm_uri). In [CanonicalizationMode::ExclusiveCanonical1_0], any
+    /// [inclusive_ns_prefixes](Self::inclusive_ns_prefixes) go into an `<ec:InclusiveNamespaces PrefixList="...">` child,
+    /// in the order given. Options that change the output in ways no algorithm identifier can express,
+    /// [keep_comments_under](Self::keep_comments_under) and [non_conformant](Self::non_conformant), fail with
+    /// [CanonicalizationError::InvalidOptions], as does anything [validate](Self::validate) rejects. Options that change
+    /// the document before canonicalizing (like [strip_xsi_attributes](Self::strip_xsi_attributes)) aren't reflected.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use xml_c14n::CanonicalizationOptions;
+    ///
+    /// let options = CanonicalizationOptions {
+    ///     inclusive_ns_prefixes: vec!["xs".to_owned(), "#default".to_owned()],
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(
+    ///     options.canonicalization_method_xml().unwrap(),
+    ///     concat!(
+    ///         r#"<ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#">"#,
+    ///         r#"<ec:InclusiveNamespaces xmlns:ec="http://www.w3.org/2001/10/xml-exc-c14n#" PrefixList="xs #default">"#,
+    ///         r#"</ec:InclusiveNamespaces></ds:CanonicalizationMethod>"#,
+    ///     )
+    /// );
+    /// ```
+    pub fn canonicalization_method_xml(&self) -> Result<String, CanonicalizationError> {
+        self.validate()?;
+        if self.keep_comments_under.is_some() {
+            return Err(CanonicalizationError::InvalidOptions(
+                "keep_comments_under can't be expressed as a canonicalization method".to_owned(),
+            ));
+        }
+        if self.non_conformant != NonConformantOptions::default() {
+            return Err(CanonicalizationError::InvalidOptions(
+                "non-conformant output can't be declared as a canonicalization method".to_owned(),
+            ));
+        }
+
+        let algorithm = escape::xml_attribute_value(self.algorithm_uri());
+        let inclusive_namespaces = if self.inclusive_ns_prefixes.is_empty() {
+            String::new()
+        } else {
+            format!(
+                r#"<ec:InclusiveNamespaces xmlns:ec="{EXCLUSIVE_C14N_NAMESPACE}" PrefixList="{}"></ec:InclusiveNamespaces>"#,
+                escape::xml_attribute_value(&self.inclusive_ns_prefixes.join(" "))
+            )
+        };
+        Ok(format!(
+            r#"<ds:CanonicalizationMethod Algorithm="{algorithm}">{inclusive_namespaces}</ds:CanonicalizationMethod>"#
+        ))
+    }
+
+    /// Check that the options make sense together, without parsing or canonicalizing anything
+    ///
+    /// For failing fast on a configuration, e.g. at startup, rather than on the first document. Checks that:
+    ///
+    /// - the [mode](Self::mode) is supported by the libxml2 linked at runtime ([CanonicalizationError::UnsupportedMode])
+    /// - [inclusive_ns_prefixes](Self::inclusive_ns_prefixes) are only given for exclusive canonicalization
+    ///   ([CanonicalizationError::InvalidOptions]), and are prefixes or `#default` ([CanonicalizationError::InvalidNamespacePrefix])
+    /// - [xpath_namespaces](Self::xpath_namespaces) binds prefixes ([CanonicalizationError::InvalidNamespacePrefix])
+    /// - [keep_comments_under](Self::keep_comments_under) is an XPath expression ([CanonicalizationError::InvalidXPath]).
+    ///   Whether it selects nodes is only known once it's evaluated, so e.g. `count(//a)` passes.
+    /// - none of the size and depth limits is 0, which would reject every document ([CanonicalizationError::InvalidOptions])
+    ///
+    /// Encoding names are passed separately, to [canonicalize_xml_bytes], which checks them before parsing.
+    ///
+    /// The canonicalize functions check the mode and that no prefix contains a NUL character themselves, but
+    /// otherwise keep ignoring inclusive prefixes in the other modes, as documented for the field.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use xml_c14n::{CanonicalizationError, CanonicalizationMode, CanonicalizationOptions};
+    ///
+    /// assert_eq!(CanonicalizationOptions::default().validate(), Ok(()));
+    ///
+    /// let options = CanonicalizationOptions {
+    ///     mode: CanonicalizationMode::Canonical1_1,
+    ///     inclusive_ns_prefixes: vec!["ds".to_owned()],
+    ///     ..Default::default()
+    /// };
+    /// assert!(matches!(options.validate(), Err(CanonicalizationError::InvalidOptions(_))));
+    /// ```
+    pub fn validate(&self) -> Result<(), CanonicalizationError> {
+        self.check_usable()?;
+
+        if !self.inclusive_ns_prefixes.is_empty()
+            && self.mode != CanonicalizationMode::ExclusiveCanonical1_0
+        {
+            return Err(CanonicalizationError::InvalidOptions(format!(
+                "inclusive_ns_prefixes only apply to {:?}",
+                CanonicalizationMode::ExclusiveCanonical1_0
+            )));
+        }
+        if let Some(prefix) = self
+            .inclusive_ns_prefixes
+            .iter()
+            .find(|&prefix| prefix != "#default" && !xpath::is_ncname(prefix))
+        {
+            return Err(CanonicalizationError::InvalidNamespacePrefix(
+                prefix.clone(),
+            ));
+        }
+        if let Some(prefix) = self
+            .xpath_namespaces
+            .keys()
+            .find(|prefix| !xpath::is_ncname(prefix))
+        {
+            return Err(CanonicalizationError::InvalidNamespacePrefix(
+                prefix.clone(),
+            ));
+        }
+        if let Some(expression) = &self.keep_comments_under {
+            xpath::compile(expression)?;
+        }
+        for (name, limit) in [
+            ("max_input_bytes", self.max_input_bytes),
+            ("max_output_bytes", self.max_output_bytes),
+            ("max_depth", self.max_depth),
+        ] {
+            if limit == Some(0) {
+                return Err(CanonicalizationError::InvalidOptions(format!(
+                    "{name} is 0, which rejects every document"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The [inclusive_ns_prefixes](Self::inclusive_ns_prefixes) that [verify_inclusive_prefixes](Self::verify_inclusive_prefixes)
+    /// says to check the output for
+    fn prefixes_to_verify(&self) -> Vec<String> {
+        if self.verify_inclusive_prefixes
+            && self.mode == CanonicalizationMode::ExclusiveCanonical1_0
+        {
+            self.inclusive_ns_prefixes.clone()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Prefixes to bind in XPath expressions, the [WELL_KNOWN_XPATH_NAMESPACES] (unless skipped) overridden by
+    /// [xpath_namespaces](Self::xpath_namespaces)
+    fn xpath_bindings(&self) -> BTreeMap<String, String> {
+        let mut bindings = BTreeMap::new();
+        if !self.skip_well_known_xpath_namespaces {
+            bindings.extend(
+                WELL_KNOWN_XPATH_NAMESPACES
+                    .iter()
+                    .map(|&(prefix, uri)| (prefix.to_owned(), uri.to_owned())),
+            );
+        }
+        bindings.extend(self.xpath_namespaces.clone());
+        bindings
+    }
+
+    /// The checks of [validate](Self::validate) for options that canonicalizing can't work with at all
+    fn check_usable(&self) -> Result<(), CanonicalizationError> {
+        if !self.mode.is_supported() {
+            return Err(CanonicalizationError::UnsupportedMode(self.mode));
+        }
+        // they're passed to libxml2 as C strings
+        if let Some(prefix) = self
+            .inclusive_ns_prefixes
+            .iter()
+            .find(|prefix| prefix.contains('\0'))
+        {
+            return Err(CanonicalizationError::InvalidNamespacePrefix(
+                prefix.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// `xmlParserOption` flags to parse documents with
+    fn parse_options(&self) -> c_int {
+        // never fetch external resources, even if a DTD refers to them
+        let mut flags = xmlParserOption_XML_PARSE_NONET;
+        if self.dtd_default_attributes {
+            flags |= xmlParserOption_XML_PARSE_DTDATTR;
+        }
+        flags as c_int | self.raw_parse_options
+    }
+
+    fn check_input_size(&self, size: usize) -> Result<(), CanonicalizationError> {
+        match self.max_input_bytes {
+            Some(limit) if size > limit => {
+                Err(CanonicalizationError::InputTooLarge { size, limit })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Options with the given mode, keeping comments if the flag is set, and everything else at the default (in particular
+/// without inclusive namespace prefixes)
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_xml, CanonicalizationMode::Canonical1_0};
+///
+/// assert_eq!(canonicalize_xml("<a><!-- c --></a>", (Canonical1_0, false).into()).unwrap(), "<a></a>");
+/// assert_eq!(canonicalize_xml("<a><!-- c --></a>", (Canonical1_0, true).into()).unwrap(), "<a><!-- c --></a>");
+/// ```
+impl From<(CanonicalizationMode, bool)> for CanonicalizationOptions {
+    fn from((mode, keep_comments): (CanonicalizationMode, bool)) -> Self {
+        CanonicalizationOptions {
+            mode,
+            keep_comments,
+            ..Default::default()
+        }
+    }
 }
 
 /// Canonicalization specification to use
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
 pub enum CanonicalizationMode {
     /// Original C14N 1.0 spec
+    ///
+    /// When canonicalizing a subset, every `xml:*` attribute in scope is copied onto its apex elements, including
+    /// `xml:id` and relative `xml:base` values, which then mean something else. Prefer [Canonical1_1](Self::Canonical1_1)
+    /// unless a peer requires 1.0.
     Canonical1_0,
     /// Exclusive C14N 1.0 spec
     #[default]
     ExclusiveCanonical1_0,
     /// C14N 1.1 spec
+    ///
+    /// Like 1.0, except that subsets only inherit `xml:lang` and `xml:space`, and `xml:base` values of omitted ancestors
+    /// are resolved into the apex's. `xml:id` is never inherited.
     Canonical1_1,
 }
 
 impl CanonicalizationMode {
+    /// All supported modes
+    pub const ALL: [CanonicalizationMode; 3] = [
+        CanonicalizationMode::Canonical1_0,
+        CanonicalizationMode::ExclusiveCanonical1_0,
+        CanonicalizationMode::Canonical1_1,
+    ];
+
+    /// Whether the libxml2 library linked at runtime supports this mode
+    ///
+    /// libxml2 may be built without C14N support at all, and versions before 2.7.0 don't support C14N 1.1.
+    pub fn is_supported(self) -> bool {
+        // SAFETY: xmlHasFeature only reads compile-time configuration
+        if unsafe { xmlHasFeature(xmlFeature_XML_WITH_C14N) } == 0 {
+            return false;
+        }
+        match self {
+            CanonicalizationMode::Canonical1_1 => {
+                libxml_version().is_some_and(|version| version.number() >= 20700)
+            }
+            _ => true,
+        }
+    }
+
     fn to_c_int(self) -> c_int {
         c_int::from(match self {
             CanonicalizationMode::Canonical1_0 => 0,
@@ -42,10 +601,280 @@ impl CanonicalizationMode {
     }
 }
 
-/// An error code (always negative) returned by libxml2 when attempting to canonicalize some XML
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Error)]
-#[error("canonicalization error ({0})")]
-pub struct CanonicalizationErrorCode(i32);
+/// An error code (always negative) returned by libxml2 when attempting to canonicalize some XML,
+/// along with the first error message libxml2 reported, if it reported any
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Error)]
+pub struct CanonicalizationErrorCode {
+    code: i32,
+    message: Option<String>,
+}
+
+impl CanonicalizationErrorCode {
+    fn new(code: i32) -> Self {
+        CanonicalizationErrorCode {
+            code,
+            message: None,
+        }
+    }
+
+    fn with_message(self, message: Option<String>) -> Self {
+        CanonicalizationErrorCode { message, ..self }
+    }
+
+    /// The code libxml2 returned
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    /// What libxml2 reported about the problem, e.g. `Relative namespace UR is invalid here : (null)`
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+impl fmt::Display for CanonicalizationErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "canonicalization error ({})", self.code)?;
+        if let Some(message) = &self.message {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An error reported by libxml2 while parsing the document
+///
+/// `message`, `line`, `code`, `domain` and `level` describe the error that made parsing fail, the last one libxml2
+/// reported. What led up to it is in `diagnostics`, which often start with a more telling message, e.g.
+/// `Specification mandates value for attribute xml` before `Couldn't find end of Start Tag`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Error)]
+pub struct ParseError {
+    /// Message as reported by libxml2, e.g. `Opening and ending tag mismatch: a line 1 and b`
+    pub message: String,
+    /// Line the error occurred on, if known
+    pub line: Option<u32>,
+    /// libxml2 error code, one of the `xmlParserErrors` values, or 0 if unknown
+    pub code: i32,
+    /// libxml2 error domain, one of the `xmlErrorDomain` values, or 0 if unknown
+    pub domain: i32,
+    pub level: DiagnosticLevel,
+    /// Everything libxml2 reported while parsing, in order, including the error itself
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl ParseError {
+    /// Take the last error libxml2 reported on the current thread
+    unsafe fn last() -> Self {
+        Self::from_xml_error(xmlGetLastError())
+    }
+
+    /// # Safety
+    ///
+    /// `error` must point to a valid error (or be null)
+    unsafe fn from_xml_error(error: *const xmlError) -> Self {
+        if error.is_null() || (*error).message.is_null() {
+            return ParseError::new("unknown error");
+        }
+
+        let diagnostic = Diagnostic::from_xml_error(error);
+        ParseError {
+            message: diagnostic.message.clone(),
+            line: diagnostic.line,
+            code: diagnostic.code,
+            domain: diagnostic.domain,
+            level: diagnostic.level,
+            diagnostics: vec![diagnostic],
+        }
+    }
+
+    /// A NUL character at byte `position` of the document, which libxml2 doesn't always report
+    fn nul_character(position: usize) -> Self {
+        ParseError {
+            code: xmlParserErrors_XML_ERR_INVALID_CHAR as i32,
+            domain: xmlErrorDomain_XML_FROM_PARSER as i32,
+            ..ParseError::new(&format!("NUL character at byte {position}"))
+        }
+    }
+
+    /// An error libxml2 didn't report, with nothing but a message
+    fn new(message: &str) -> Self {
+        ParseError {
+            message: message.to_owned(),
+            line: None,
+            code: 0,
+            domain: 0,
+            level: DiagnosticLevel::Fatal,
+            diagnostics: vec![],
+        }
+    }
+
+    /// Replace the diagnostics with everything that was collected while parsing, if anything was
+    fn with_diagnostics(self, diagnostics: Vec<Diagnostic>) -> Self {
+        if diagnostics.is_empty() {
+            return self;
+        }
+        ParseError {
+            diagnostics,
+            ..self
+        }
+    }
+}
+
+/// Anything that can go wrong when canonicalizing XML
+///
+/// The [Display](fmt::Display) output is kept short, details are available via [std::error::Error::source].
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
+pub enum CanonicalizationError {
+    /// The document could not be parsed
+    #[error("failed to parse XML document")]
+    Parse(#[from] ParseError),
+    /// There is nothing to canonicalize, as the document (apart from e.g. whitespace, comments or the XML declaration) is empty
+    #[error("XML document is empty")]
+    EmptyDocument,
+    /// The document was parsed, but libxml2 failed to canonicalize it, for a reason not covered by the more specific variants
+    #[error("failed to canonicalize XML document")]
+    Canonicalization(#[from] CanonicalizationErrorCode),
+    /// The document declares a namespace with a relative URI (e.g. `xmlns="relative"`), which the C14N specs require an
+    /// error for
+    ///
+    /// libxml2 only warns about these when parsing, so this is how valid XML most commonly fails to canonicalize.
+    /// Every declaration is checked, even ones that exclusive canonicalization would leave out.
+    #[error("relative namespace URI `{uri}` can't be canonicalized{}", line.map(|line| format!(" on line {line}")).unwrap_or_default())]
+    RelativeNamespaceUri { uri: String, line: Option<u32> },
+    /// The document declares a namespace with a URI libxml2 can't parse (e.g. with a space in it), so it can't check
+    /// that it's absolute
+    #[error("invalid namespace URI `{uri}`{}", line.map(|line| format!(" on line {line}")).unwrap_or_default())]
+    InvalidNamespaceUri { uri: String, line: Option<u32> },
+    /// The document still contains a reference to entity `name`, which libxml2 can't canonicalize
+    ///
+    /// Entity references are kept as they are unless the document is parsed with `XML_PARSE_NOENT`, see
+    /// [coalesce_text](CanonicalizationOptions::coalesce_text) for expanding internal entities safely. References to
+    /// external entities can't be expanded that way.
+    #[error("unexpanded reference to entity `{name}`{}", line.map(|line| format!(" on line {line}")).unwrap_or_default())]
+    UnexpandedEntityReference { name: String, line: Option<u32> },
+    /// The loader passed to [canonicalize_with_entity_loader] didn't return the entity or DTD with this system ID
+    #[error("external entity `{system_id}` could not be loaded")]
+    EntityNotLoaded { system_id: String },
+    /// The libxml2 library linked at runtime can't canonicalize in this mode, see [CanonicalizationMode::is_supported]
+    #[error("mode {0:?} not supported by this libxml2 build")]
+    UnsupportedMode(CanonicalizationMode),
+    /// The canonical output is not valid UTF-8
+    #[error("canonical output is not valid UTF-8")]
+    InvalidUtf8(#[from] Utf8Error),
+    /// The canonical output contains a NUL byte, so it can't be returned as a C string
+    #[error("canonical output contains a NUL byte at position {position}")]
+    InteriorNul { position: usize },
+    /// The document is longer than [CanonicalizationOptions::max_input_bytes]
+    #[error("input of {size} bytes exceeds the limit of {limit} bytes")]
+    InputTooLarge { size: usize, limit: usize },
+    /// The canonical form is longer than [CanonicalizationOptions::max_output_bytes]
+    #[error("output exceeds the limit of {limit} bytes")]
+    OutputTooLarge { limit: usize },
+    /// The document has elements nested deeper than [CanonicalizationOptions::max_depth], the first of them on `line`
+    #[error("elements nested more than {limit} levels deep{}", line.map(|line| format!(" on line {line}")).unwrap_or_default())]
+    MaxDepthExceeded { limit: usize, line: Option<u32> },
+    /// The encoding name passed to [canonicalize_xml_bytes] is not known to libxml2
+    #[error("unknown encoding `{0}`")]
+    UnknownEncoding(String),
+    /// The document has no element with this name
+    #[error("no `{local_name}` element in namespace `{namespace_uri}`")]
+    ElementNotFound {
+        namespace_uri: String,
+        local_name: String,
+    },
+    /// No element has the requested ID
+    #[error("no element with ID `{0}`")]
+    MissingId(String),
+    /// More than one element has the requested ID, or any ID if [CanonicalizationOptions::reject_duplicate_ids] is set
+    #[error("ID `{0}` is used by more than one element")]
+    DuplicateId(String),
+    /// There are fewer matching elements than the requested index
+    #[error("element index {index} out of range, found {count} matching elements")]
+    ElementIndexOutOfRange { index: usize, count: usize },
+    /// No element has the attribute with the requested value, see [canonicalize_where_attr]
+    #[error("no element with attribute {name}=\"{value}\"")]
+    NoAttributeMatch { name: String, value: String },
+    /// More than one element has the attribute with the requested value, see [canonicalize_where_attr]
+    #[error("{count} elements with attribute {name}=\"{value}\"")]
+    AmbiguousAttributeMatch {
+        name: String,
+        value: String,
+        count: usize,
+    },
+    /// A namespace prefix could not be bound for XPath evaluation, e.g. because it is empty
+    #[error("invalid namespace prefix `{0}`")]
+    InvalidNamespacePrefix(String),
+    /// The given string is not an XPath expression that selects nodes
+    ///
+    /// `message` is what libxml2 reported, e.g. `Undefined namespace prefix`, if anything.
+    #[error("invalid XPath expression `{expression}`{}", message.as_ref().map(|message| format!(": {message}")).unwrap_or_default())]
+    InvalidXPath {
+        expression: String,
+        message: Option<String>,
+    },
+    /// The options contradict each other or can't be met by any document, see [CanonicalizationOptions::validate]
+    #[error("invalid options: {0}")]
+    InvalidOptions(String),
+    /// Canonicalizing the canonical form again gave a different result, see [canonicalize_checked]
+    #[error("canonical form changes when canonicalized again, first at byte {position}")]
+    NotIdempotent { position: usize },
+    /// The document contains a comment, and [CanonicalizationOptions::reject_comments] is set
+    #[error("comment found{}", line.map(|line| format!(" on line {line}")).unwrap_or_default())]
+    CommentRejected { line: Option<u32> },
+    /// The document contains a control character, and [CanonicalizationOptions::reject_control_characters] is set
+    #[error("control character U+{:04X} found{}", u32::from(*character), line.map(|line| format!(" on line {line}")).unwrap_or_default())]
+    ControlCharacterRejected { character: char, line: Option<u32> },
+    /// An element has both child elements and text, and [CanonicalizationOptions::require_element_only] is set
+    #[error("element `{element}` has mixed content{}", line.map(|line| format!(" on line {line}")).unwrap_or_default())]
+    MixedContent { element: String, line: Option<u32> },
+    /// The URI passed to [CanonicalizationOptions::from_algorithm_uri] doesn't identify a canonicalization algorithm
+    #[error("unknown canonicalization algorithm `{0}`")]
+    UnknownAlgorithm(String),
+    /// A namespace prefix is bound to different URIs, and [CanonicalizationOptions::reject_prefix_redefinition] is set
+    #[error("namespace prefix `{prefix}` redefined{}", line.map(|line| format!(" on line {line}")).unwrap_or_default())]
+    PrefixRedefined { prefix: String, line: Option<u32> },
+    /// libxml2 produced output that isn't shaped like canonical XML, see [CanonicalizationOptions::verify_output]
+    #[error("output is not canonical: {0}")]
+    NonCanonicalOutput(String),
+    /// The canonical form has a character outside ASCII at this byte position, see [canonicalize_ascii]
+    #[error("non-ASCII character U+{:04X} at byte {position} of the canonical form", u32::from(*character))]
+    NonAsciiCharacter { character: char, position: usize },
+    /// One of the [CanonicalizationOptions::inclusive_ns_prefixes] isn't declared anywhere in the output, see
+    /// [CanonicalizationOptions::verify_inclusive_prefixes]
+    #[error("inclusive namespace prefix `{0}` is not declared in the output")]
+    InclusivePrefixNotRendered(String),
+    /// The document is not valid against its DTD, see [canonicalize_dtd_validated]
+    ///
+    /// Lists what libxml2 reported, which is empty if it didn't explain why (e.g. if it couldn't allocate a parser).
+    #[error("XML document is not valid against its DTD")]
+    DtdInvalid(Vec<Diagnostic>),
+    /// libxml2 couldn't compile the XML Schema passed to [Schema::new], listing what it reported
+    #[error("XML Schema could not be compiled")]
+    SchemaCompilation(Vec<Diagnostic>),
+    /// The document is not valid against the schema passed to [canonicalize_validated]
+    ///
+    /// Lists the validation errors libxml2 reported.
+    #[error("XML document is not valid against its schema")]
+    SchemaInvalid(Vec<Diagnostic>),
+    /// libxslt couldn't compile the stylesheet passed to `canonicalize_xslt`, listing what it reported
+    #[error("XSLT stylesheet could not be compiled")]
+    InvalidStylesheet(Vec<Diagnostic>),
+    /// Applying the stylesheet passed to `canonicalize_xslt` failed, listing what libxslt reported
+    #[error("XSLT transform failed")]
+    TransformFailed(Vec<Diagnostic>),
+    /// The URI passed to [canonicalize_reference] is not one of the supported same-document references
+    #[error("unsupported reference URI `{0}`")]
+    UnsupportedReference(String),
+}
 
 /// Parse specified XML document and canonicalize it
 ///
@@ -60,223 +889,4179 @@ pub struct CanonicalizationErrorCode(i32);
 ///         mode: CanonicalizationMode::Canonical1_0,
 ///         keep_comments: false,
 ///         inclusive_ns_prefixes: vec![],
+///         ..Default::default()
 ///     }
 /// ).unwrap();
 ///
 /// assert_eq!(canonicalized, "<hi></hi>")
 /// ```
+///
+/// Anything that can be viewed as a `&str` works as the document, e.g. an owned [String] or a [Cow](std::borrow::Cow):
+///
+/// ```
+/// use std::borrow::Cow;
+/// use xml_c14n::{canonicalize_xml, CanonicalizationOptions};
+///
+/// let owned = String::from("<hi/>");
+/// let borrowed: Cow<str> = Cow::Borrowed("<hi/>");
+///
+/// assert_eq!(canonicalize_xml(owned, CanonicalizationOptions::default()).unwrap(), "<hi></hi>");
+/// assert_eq!(canonicalize_xml(borrowed, CanonicalizationOptions::default()).unwrap(), "<hi></hi>");
+/// assert_eq!(canonicalize_xml(Box::<str>::from("<hi/>"), CanonicalizationOptions::default()).unwrap(), "<hi></hi>");
+/// ```
 pub fn canonicalize_xml(
-    document: &str,
+    document: impl AsRef<str>,
     options: CanonicalizationOptions,
-) -> Result<String, CanonicalizationErrorCode> {
-    // not sure how this works, but if XML is valid, this still succeeds, but canonicalize_document_to_c_pointer fails below.
-    let document = read_document(document);
-
-    unsafe {
-        let (output, return_code) = canonicalize_document_to_c_pointer(options, document);
-
-        let result = if return_code < 0 {
-            Err(CanonicalizationErrorCode(return_code))
-        } else {
-            // SAFETY: xmlC14NDocDumpMemory completed successfully, so a proper C string was allocated and assigned to `output`
-            let c_str = CStr::from_ptr(output as *const _);
-            let str_slice: &str = c_str.to_str().unwrap();
-            Ok(str_slice.to_owned())
-        };
-
-        // let free_function = xmlFree.unwrap();
-        // free_function(output as *mut c_void);
-
-        xmlFreeDoc(document);
-
-        result
-    }
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    canonicalize_document_bytes(document.as_bytes(), None, options)
 }
 
-/// Canonicalize document
+/// Parse specified XML document from raw bytes and canonicalize it
+///
+/// Unlike [canonicalize_xml], the document doesn't have to be UTF-8.
+/// By default libxml2 detects the encoding from the byte order mark or the XML declaration, which covers e.g. UTF-16 with a BOM.
+/// If `encoding` is given (e.g. `"UTF-16LE"` or `"ISO-8859-1"`), it overrides whatever libxml2 would detect,
+/// which is needed for documents in UTF-16 without a BOM or XML declaration.
+///
+/// It also overrides the encoding declaration in the prolog, which corrects documents that lie about their encoding,
+/// like Latin-1 declared as `encoding="UTF-8"`. Misused, it corrupts correctly declared ones just as well: forcing Latin-1
+/// on actual UTF-8 turns every non-ASCII character into several wrong ones, without any error.
+///
+/// The canonical output is always UTF-8, as required by the spec.
+///
+/// A NUL character anywhere in the document fails with [CanonicalizationError::Parse], also after the document element,
+/// where libxml2 would otherwise take it for the end of the input. An encoding libxml2 doesn't know fails with
+/// [CanonicalizationError::UnknownEncoding].
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_xml_bytes, CanonicalizationOptions};
+///
+/// let utf16: Vec<u8> = "<hi/>".encode_utf16().flat_map(u16::to_le_bytes).collect();
+/// let canonicalized =
+///     canonicalize_xml_bytes(&utf16, Some("UTF-16LE"), CanonicalizationOptions::default()).unwrap();
 ///
-/// If the operation completes successfully (return code is not negative), the returned pointer points to a valid C String
-unsafe fn canonicalize_document_to_c_pointer(
+/// assert_eq!(canonicalized, "<hi></hi>")
+/// ```
+pub fn canonicalize_xml_bytes(
+    document: &[u8],
+    encoding: Option<&str>,
     options: CanonicalizationOptions,
-    document: xmlDocPtr,
-) -> (*const xmlChar, c_int) {
-    // "NULL if all document nodes should be included"
-    let nodes = null::<xmlNodeSet>() as *mut _;
-
-    let mut ns_list_c = to_xml_string_vec(options.inclusive_ns_prefixes);
-    let with_comments = c_int::from(options.keep_comments);
+) -> Result<String, CanonicalizationError> {
+    options.check_input_size(document.len())?;
+    let encoding = encoding.map(c_encoding_name).transpose()?;
+    canonicalize_document_bytes(document, encoding.as_deref(), options)
+}
 
-    let mut output = null::<xmlChar>() as *mut xmlChar;
+/// Parse specified null-terminated XML document and canonicalize it
+///
+/// Same as [canonicalize_xml], but the document is passed to libxml2 as-is, without copying it.
+/// Useful if you already have a null-terminated UTF-8 buffer, e.g. one received over FFI.
+///
+/// Example:
+///
+/// ```
+/// use std::ffi::CStr;
+/// use xml_c14n::{canonicalize_cstr, CanonicalizationOptions};
+///
+/// let document = CStr::from_bytes_with_nul(b"<hi/>\0").unwrap();
+/// let canonicalized = canonicalize_cstr(document, CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, "<hi></hi>")
+/// ```
+pub fn canonicalize_cstr(
+    document: &CStr,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    options.check_input_size(document.to_bytes().len())?;
+    canonicalize_document_bytes(document.to_bytes(), None, options)
+}
 
-    let return_code = xmlC14NDocDumpMemory(
-        document,
-        nodes,
-        options.mode.to_c_int(),
-        ns_list_c.as_mut_ptr(),
-        with_comments,
-        (&mut output) as *mut _,
-    );
+fn canonicalize_document_bytes(
+    document: &[u8],
+    encoding: Option<&CStr>,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    Document::read_for(document, encoding, &options)?.canonicalize(options)
+}
 
-    free_xml_string_vec(ns_list_c);
+/// Parse specified XML document, canonicalize it, and escape the result for use as text content in another XML document
+///
+/// `<`, `>`, `&` and carriage returns are escaped, like C14N itself escapes text nodes.
+/// Parsing the result as text gives back what [canonicalize_xml] returns, which is the form to hash or sign.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_escaped, CanonicalizationOptions};
+///
+/// let escaped = canonicalize_escaped("<a>&amp;</a>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(escaped, "&lt;a&gt;&amp;amp;&lt;/a&gt;")
+/// ```
+pub fn canonicalize_escaped(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    canonicalize_xml(document, options).map(|canonicalized| escape::xml_text(&canonicalized))
+}
 
-    (output, return_code)
+/// Parse specified XML document, canonicalize it, and return the result as a JSON string literal, quotes included
+///
+/// For embedding canonical XML in JSON, e.g. in structured logs. Parsing the literal as JSON gives back what
+/// [canonicalize_xml] returns, which is the form to hash or sign.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_json_escaped, CanonicalizationOptions};
+///
+/// let escaped = canonicalize_json_escaped("<a b='\"'>\n</a>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(escaped, r#""<a b=\"&quot;\">\n</a>""#)
+/// ```
+#[cfg(feature = "serde_json")]
+pub fn canonicalize_json_escaped(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let canonicalized = canonicalize_xml(document, options)?;
+    // serializing a string can't fail
+    Ok(serde_json::to_string(&canonicalized).unwrap())
 }
 
-/// Create a [Vec] of null-terminated [*mut xmlChar] strings
-fn to_xml_string_vec(vec: Vec<String>) -> Vec<*mut xmlChar> {
-    vec.into_iter()
-        .map(|s| CString::new(s).unwrap().into_raw() as *mut xmlChar)
-        .chain(once(std::ptr::null_mut()))
-        .collect()
+/// Parse specified XML document and canonicalize it into a null-terminated string, e.g. for passing it on to C
+///
+/// XML can't contain NUL characters, so this only returns [CanonicalizationError::InteriorNul] if something is seriously wrong.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_to_cstring, CanonicalizationOptions};
+///
+/// let canonicalized = canonicalize_to_cstring("<hi/>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized.as_bytes_with_nul(), b"<hi></hi>\0")
+/// ```
+pub fn canonicalize_to_cstring(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<CString, CanonicalizationError> {
+    let canonicalized = canonicalize_xml(document, options)?;
+    CString::new(canonicalized).map_err(|error| CanonicalizationError::InteriorNul {
+        position: error.nul_position(),
+    })
 }
 
-/// Deallocate a [Vec] of null-terminated [*mut xmlChar] strings
+/// Parse specified XML document and canonicalize it, returning the output in the buffer libxml2 allocated for it
 ///
-/// # Safety
+/// For handing the canonical bytes to C code without copying them: [RawCanonicalOutput::as_ptr] and
+/// [RawCanonicalOutput::len] are the null-terminated UTF-8 output and its length. The buffer is freed when the handle is
+/// dropped, so whoever gets the pointer must neither free it nor use it after that. Getting the pointer is safe, using it
+/// from C is where the care is needed.
 ///
-/// The argument must have been created with [to_xml_string_vec]
-unsafe fn free_xml_string_vec(vec: Vec<*mut xmlChar>) {
-    for s in vec {
-        if !s.is_null() {
-            let _ = CString::from_raw(s as *mut c_char);
-        }
+/// Not quite zero-copy with [non_conformant](CanonicalizationOptions::non_conformant) options, as rewriting the output
+/// builds a new one.
+///
+/// Example:
+///
+/// ```
+/// use std::ffi::CStr;
+/// use xml_c14n::{canonicalize_raw, CanonicalizationOptions};
+///
+/// let output = canonicalize_raw("<hi/>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(output.len(), 9);
+/// assert_eq!(unsafe { CStr::from_ptr(output.as_ptr() as *const _) }, c"<hi></hi>");
+/// ```
+pub fn canonicalize_raw(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<RawCanonicalOutput, CanonicalizationError> {
+    if options.non_conformant != NonConformantOptions::default() {
+        let canonicalized = canonicalize_to_cstring(document, options)?;
+        return Ok(RawCanonicalOutput::new(XmlOutput::from_bytes(
+            canonicalized.into_bytes(),
+        )));
     }
-}
 
-/// Parse the specified string to a [xmlDocPtr]
-fn read_document(document: &str) -> xmlDocPtr {
-    unsafe {
-        let c_document = CString::new(document).unwrap();
-        // TODO...
-        let url = CString::default();
-
-        // Encoding is allowed to be null as per docs
-        let encoding = null();
-
-        xmlReadDoc(
-            // Pointer to null-terminated UTF8 required, which is what we pass here
-            c_document.as_ptr() as *const xmlChar,
-            url.as_ptr(),
-            encoding,
-            c_int::from(0),
-        )
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+    let verify_output = options.verify_output;
+    let prefixes_to_verify = options.prefixes_to_verify();
+    let output = document.canonicalize_output(options)?;
+
+    let canonical = std::str::from_utf8(output.as_bytes())?;
+    if verify_output {
+        shape::check_shape(canonical).map_err(CanonicalizationError::NonCanonicalOutput)?;
     }
+    check_prefixes_declared(canonical, &prefixes_to_verify)?;
+    Ok(RawCanonicalOutput::new(output))
 }
 
-#[cfg(test)]
-mod tests {
-    //! Test cases are taken from official spec and other sources. For more info see corresponding Readmes.
-    use super::*;
-
-    #[test]
+/// Parse specified XML document and canonicalize it into `out`, replacing whatever it contained
+///
+/// The canonical bytes are copied straight from libxml2's buffer into `out`, which only grows if its capacity is too
+/// small, so reusing one `Vec` in a loop (e.g. one that hashes every document) doesn't allocate once it's large enough.
+/// `out` is left empty if canonicalization fails.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_into_vec, CanonicalizationOptions};
+///
+/// let mut out = Vec::new();
+/// for document in ["<a/>", "<b c='d'/>"] {
+///     canonicalize_into_vec(document, CanonicalizationOptions::default(), &mut out).unwrap();
+///     // e.g. hash `out` here
+/// }
+///
+/// assert_eq!(out, br#"<b c="d"></b>"#);
+/// ```
+pub fn canonicalize_into_vec(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+    out: &mut Vec<u8>,
+) -> Result<(), CanonicalizationError> {
+    out.clear();
+    let output = canonicalize_raw(document, options)?;
+    out.extend_from_slice(output.as_bytes());
+    Ok(())
+}
+
+/// Parse specified XML document and canonicalize it, returning the canonical bytes
+///
+/// Canonical XML is an octet stream, and this is the form to hash or sign. It's the same as [canonicalize_xml] returns,
+/// without going through [String]. libxml2's buffer is freed before returning.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_xml_to_bytes, CanonicalizationOptions};
+///
+/// let canonicalized = canonicalize_xml_to_bytes("<a b='é'/>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, "<a b=\"é\"></a>".as_bytes());
+/// ```
+pub fn canonicalize_xml_to_bytes(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<Vec<u8>, CanonicalizationError> {
+    let mut out = Vec::new();
+    canonicalize_into_vec(document, options, &mut out)?;
+    Ok(out)
+}
+
+/// Split a stream of concatenated XML documents and canonicalize each of them independently
+///
+/// XML only allows one document element per document, so a document ends once its document element is closed.
+/// Comments and processing instructions after it still belong to it, and the next document starts at the next
+/// XML declaration (`<?xml ...?>`), document type declaration or start tag. Whitespace between documents is dropped.
+/// If a boundary can't be found because the input is malformed, the rest of the input is treated as the last document,
+/// which then fails to parse.
+///
+/// Options apply to each document separately, e.g. [max_input_bytes](CanonicalizationOptions::max_input_bytes) limits
+/// the size of every single document.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_concatenated, CanonicalizationOptions};
+///
+/// let input = "<?xml version=\"1.0\"?>\n<a/>\n<?xml version=\"1.0\"?>\n<b>unclosed\n";
+/// let canonicalized = canonicalize_concatenated(input, CanonicalizationOptions::default());
+///
+/// assert_eq!(canonicalized.len(), 2);
+/// assert_eq!(canonicalized[0], Ok("<a></a>".to_owned()));
+/// assert!(canonicalized[1].is_err());
+/// ```
+pub fn canonicalize_concatenated(
+    input: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Vec<Result<String, CanonicalizationError>> {
+    // documents in one stream tend to use the same names, so one context (and its dictionary) serves them all
+    let mut context = CanonicalizerContext::new();
+    concatenated::split_documents(input.as_ref())
+        .into_iter()
+        .map(|document| context.canonicalize(document, options.clone()))
+        .collect()
+}
+
+/// Canonicalize each of `documents` independently and concatenate the canonical forms in order, for signing a
+/// collection of documents with a single digest
+///
+/// There is no separator: the result is exactly the canonical forms, one after another, as bytes to digest. A verifier
+/// reconstructs it by canonicalizing the same documents in the same order with the same options. Since every
+/// canonical document has exactly one document element, and comments and PIs outside it have their line feed on the
+/// side facing it, the boundaries are still unambiguous. Options apply to each document separately.
+/// Fails with the error of the first document that fails, and an empty collection results in an empty string.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_collection, CanonicalizationOptions};
+///
+/// let canonicalized = canonicalize_collection(["<a/>", "<b c='d'/>"], CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, r#"<a></a><b c="d"></b>"#);
+/// ```
+pub fn canonicalize_collection(
+    documents: impl IntoIterator<Item = impl AsRef<str>>,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let mut context = CanonicalizerContext::new();
+    documents
+        .into_iter()
+        .map(|document| context.canonicalize(document, options.clone()))
+        .collect()
+}
+
+/// Parse specified XML document and canonicalize it, also returning any warnings libxml2 reported
+///
+/// libxml2 recovers from some problems (e.g. an invalid `xml:space` value) and only warns about them.
+/// [canonicalize_xml] ignores such warnings, this function returns them as structured records instead.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_xml_with_diagnostics, CanonicalizationOptions, DiagnosticLevel};
+///
+/// let (canonicalized, diagnostics) = canonicalize_xml_with_diagnostics(
+///     r#"<hi xml:space="sometimes"/>"#,
+///     CanonicalizationOptions::default(),
+/// ).unwrap();
+///
+/// assert_eq!(canonicalized, r#"<hi xml:space="sometimes"></hi>"#);
+/// assert_eq!(diagnostics[0].level, DiagnosticLevel::Warning);
+/// ```
+pub fn canonicalize_xml_with_diagnostics(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<(String, Vec<Diagnostic>), CanonicalizationError> {
+    let document = document.as_ref();
+    let (result, diagnostics) =
+        diagnostics::collect_diagnostics(|| canonicalize_xml(document, options));
+    result.map(|canonicalized| (canonicalized, diagnostics))
+}
+
+/// Parse specified XML document and canonicalize only the nodes the XPath expression `xpath` selects
+///
+/// The selected nodes are the document subset, exactly as the C14N specs define it: selecting an element doesn't include
+/// its attributes, namespace nodes or children, those have to be selected too. The usual XML Signature expression for an
+/// element with everything under it is `(//. | //@* | //namespace::*)[ancestor-or-self::e]`, for which
+/// [canonicalize_nth_element] or [canonicalize_by_ids] are simpler. Prefixes in the expression are bound by
+/// [xpath_namespaces](CanonicalizationOptions::xpath_namespaces) and the [WELL_KNOWN_XPATH_NAMESPACES], not by the
+/// document.
+///
+/// An expression that selects nothing gives an empty string. One that doesn't compile, uses an unbound prefix or doesn't
+/// evaluate to a node set (like `count(//a)`) fails with [CanonicalizationError::InvalidXPath]. Options that check the
+/// selected part of the document (like [reject_comments](CanonicalizationOptions::reject_comments)) check all of it.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_xml_subset, CanonicalizationOptions};
+///
+/// let document = r#"<r><a n="1">one</a><b/><a n="2">two</a></r>"#;
+///
+/// // only the elements, without their attributes and text
+/// let canonicalized = canonicalize_xml_subset(document, "//a", CanonicalizationOptions::default()).unwrap();
+/// assert_eq!(canonicalized, "<a></a><a></a>");
+///
+/// let canonicalized = canonicalize_xml_subset(document, "//a | //a/@n", CanonicalizationOptions::default()).unwrap();
+/// assert_eq!(canonicalized, r#"<a n="1"></a><a n="2"></a>"#);
+/// ```
+pub fn canonicalize_xml_subset(
+    document: impl AsRef<str>,
+    xpath: &str,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+
+    // SAFETY: the document outlives the XPath context, and the selected nodes are all in the document
+    unsafe {
+        let node_set = XPathContext::new(document.as_ptr(), &options.xpath_bindings())?
+            .select_node_set(xpath)?;
+        canonicalize_to_string(options, document.as_ptr(), None, Some(&node_set))
+    }
+}
+
+/// Parse specified XML document and canonicalize only the `index`-th (starting at 0) element with the given name
+///
+/// Elements are counted in document order. `namespace_uri` is the namespace the element is in, or `""` for no namespace.
+/// The element is canonicalized as a document subset, so (depending on the mode) namespaces declared on its ancestors are rendered on it.
+///
+/// If there are no more than `index` such elements, returns [CanonicalizationError::ElementIndexOutOfRange].
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_nth_element, CanonicalizationOptions};
+///
+/// let document = r#"<r xmlns:s="urn:s"><s:a>first</s:a><s:a>second</s:a></r>"#;
+/// let canonicalized =
+///     canonicalize_nth_element(document, "urn:s", "a", 1, CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, r#"<s:a xmlns:s="urn:s">second</s:a>"#)
+/// ```
+pub fn canonicalize_nth_element(
+    document: impl AsRef<str>,
+    namespace_uri: &str,
+    local_name: &str,
+    index: usize,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+
+    unsafe {
+        let matching: Vec<xmlNodePtr> = tree::elements(document.root_element())
+            .into_iter()
+            .filter(|&element| tree::has_name(element, namespace_uri, local_name))
+            .collect();
+
+        match matching.get(index) {
+            Some(&element) => document.canonicalize_subtrees(options, &HashSet::from([element])),
+            None => Err(CanonicalizationError::ElementIndexOutOfRange {
+                index,
+                count: matching.len(),
+            }),
+        }
+    }
+}
+
+/// Parse specified XML document and canonicalize the element with an unqualified attribute named `attr_local_name` whose
+/// value is `attr_value`, together with everything under it
+///
+/// For selecting e.g. `<Object name="payload">` without XPath and without the attribute being an ID. Values are compared
+/// after entity and character references are replaced, but otherwise exactly, without normalizing whitespace.
+///
+/// Returns [CanonicalizationError::NoAttributeMatch] if no element matches, and
+/// [CanonicalizationError::AmbiguousAttributeMatch] if several do, rather than picking the first one: an attacker could
+/// otherwise add a matching element before the intended one and choose what gets signed.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_where_attr, CanonicalizationOptions};
+///
+/// let document = r#"<r><part name="header"/><part name="body">text</part></r>"#;
+/// let canonicalized = canonicalize_where_attr(document, "name", "body", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, r#"<part name="body">text</part>"#)
+/// ```
+pub fn canonicalize_where_attr(
+    document: impl AsRef<str>,
+    attr_local_name: &str,
+    attr_value: &str,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+
+    unsafe {
+        let matching =
+            tree::elements_with_attribute(document.root_element(), "", attr_local_name, attr_value);
+
+        match matching[..] {
+            [element] => document.canonicalize_subtrees(options, &HashSet::from([element])),
+            [] => Err(CanonicalizationError::NoAttributeMatch {
+                name: attr_local_name.to_owned(),
+                value: attr_value.to_owned(),
+            }),
+            _ => Err(CanonicalizationError::AmbiguousAttributeMatch {
+                name: attr_local_name.to_owned(),
+                value: attr_value.to_owned(),
+                count: matching.len(),
+            }),
+        }
+    }
+}
+
+/// Parse specified XML document and list the attributes of the `index`th element (counting from 0, in document order)
+/// with the given local name and namespace URI, as its canonical form has them
+///
+/// The attributes are returned as qualified names and values, in canonical order, with values normalized and escaped
+/// exactly as in the output (e.g. a tab is `&#x9;`). Namespace declarations are left out. The element is canonicalized
+/// as the apex of a subset like in [canonicalize_nth_element], so e.g. [Canonical1_0](CanonicalizationMode::Canonical1_0)
+/// includes `xml:*` attributes inherited from its ancestors.
+///
+/// Meant for inspecting and testing attribute handling, the canonical form of the whole element is what gets signed.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonical_attributes, CanonicalizationOptions};
+///
+/// let document = r#"<r xmlns:x="urn:x"><e x:b="2" b="1&#9;tab" a='"'/></r>"#;
+/// let attributes = canonical_attributes(document, "", "e", 0, CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(
+///     attributes,
+///     [
+///         ("a".to_owned(), "&quot;".to_owned()),
+///         ("b".to_owned(), "1&#x9;tab".to_owned()),
+///         ("x:b".to_owned(), "2".to_owned()),
+///     ]
+/// );
+/// ```
+pub fn canonical_attributes(
+    document: impl AsRef<str>,
+    namespace_uri: &str,
+    local_name: &str,
+    index: usize,
+    options: CanonicalizationOptions,
+) -> Result<Vec<(String, String)>, CanonicalizationError> {
+    let canonical = canonicalize_nth_element(document, namespace_uri, local_name, index, options)?;
+    let tags = tokens::start_tags(&canonical);
+    let Some(tag) = tags.first() else {
+        return Ok(vec![]);
+    };
+
+    Ok(tag
+        .attributes
+        .iter()
+        .filter(|attribute| attribute.declared_prefix().is_none())
+        .map(|attribute| {
+            (
+                attribute.name.to_owned(),
+                attribute.value(&canonical).to_owned(),
+            )
+        })
+        .collect())
+}
+
+/// Parse specified XML document and canonicalize the elements with the given IDs, together with everything under them, as one node set
+///
+/// This is what XML Signature needs for references to several same-document fragments, e.g. in a manifest.
+/// The subtrees are output in document order, whatever the order of `ids`, and overlapping ones (e.g. an element and one of
+/// its descendants) are only output once. Repeating an ID in `ids` has no effect.
+///
+/// IDs are `xml:id` attributes, attributes declared as IDs in the DTD, and unqualified `ID`, `Id` or `id` attributes.
+/// Returns [CanonicalizationError::MissingId] if no element has one of the IDs, and [CanonicalizationError::DuplicateId]
+/// if several elements do, as picking one of them could let an attacker choose what gets signed.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_by_ids, CanonicalizationOptions};
+///
+/// let document = r#"<r><a ID="first"/><b ID="second"><c/></b><d ID="third"/></r>"#;
+/// let canonicalized =
+///     canonicalize_by_ids(document, &["third", "first"], CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, r#"<a ID="first"></a><d ID="third"></d>"#)
+/// ```
+pub fn canonicalize_by_ids(
+    document: impl AsRef<str>,
+    ids: &[&str],
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+
+    unsafe {
+        let elements_by_id = tree::elements_by_id(document.root_element());
+
+        let subtrees = ids
+            .iter()
+            .map(|id| element_with_id(&elements_by_id, id))
+            .collect::<Result<_, _>>()?;
+
+        document.canonicalize_subtrees(options, &subtrees)
+    }
+}
+
+/// Namespace of `wsu:Id` and the other WS-Security utility attributes
+const WSU_NAMESPACE: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd";
+
+/// Parse specified XML document and canonicalize the element whose `wsu:Id` attribute is `id`, together with everything
+/// under it, the way WS-Security references parts of a SOAP message
+///
+/// `wsu:Id` is the `Id` attribute in the WS-Security utility namespace (whatever its prefix), which isn't an ID to
+/// libxml2 without a DTD. Any other ID attributes, like an unqualified `Id`, are not considered.
+///
+/// WS-Security requires exclusive canonicalization, so other [modes](CanonicalizationOptions::mode) are rejected with
+/// [CanonicalizationError::InvalidOptions]. Returns [CanonicalizationError::MissingId] if no element has the ID, and
+/// [CanonicalizationError::DuplicateId] if several elements do.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_wsu_id, CanonicalizationOptions};
+///
+/// let document = r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"
+///     xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd">
+///   <soap:Body wsu:Id="body"><order/></soap:Body>
+/// </soap:Envelope>"#;
+/// let canonicalized = canonicalize_wsu_id(document, "body", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(
+///     canonicalized,
+///     r#"<soap:Body xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd" wsu:Id="body"><order></order></soap:Body>"#
+/// );
+/// ```
+pub fn canonicalize_wsu_id(
+    document: impl AsRef<str>,
+    id: &str,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    if options.mode != CanonicalizationMode::ExclusiveCanonical1_0 {
+        return Err(CanonicalizationError::InvalidOptions(format!(
+            "WS-Security references are canonicalized with {:?}",
+            CanonicalizationMode::ExclusiveCanonical1_0
+        )));
+    }
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+
+    unsafe {
+        let matching =
+            tree::elements_with_attribute(document.root_element(), WSU_NAMESPACE, "Id", id);
+
+        match matching[..] {
+            [element] => document.canonicalize_subtrees(options, &HashSet::from([element])),
+            [] => Err(CanonicalizationError::MissingId(id.to_owned())),
+            _ => Err(CanonicalizationError::DuplicateId(id.to_owned())),
+        }
+    }
+}
+
+/// Parse an XML fragment as if it were the content of an element with the given namespace declarations, and canonicalize
+/// the fragment without that element
+///
+/// For fragments cut out of a larger document (e.g. a SOAP body stored on its own) that use prefixes declared on their
+/// former ancestors. `context_declarations` are `(prefix, URI)` pairs, with an empty prefix for the default namespace.
+/// They're rendered like declarations on the ancestors of a document subset: on the fragment's outermost elements in the
+/// inclusive modes, and only where they're used in exclusive mode. The fragment may have several top-level elements, as
+/// well as text, comments and processing instructions, but no XML declaration or DTD. libxml2 renders top-level comments
+/// and processing instructions like it does outside a document element, with a line feed between them and their
+/// neighbours.
+///
+/// Returns [CanonicalizationError::InvalidNamespacePrefix] for a declaration whose prefix isn't a prefix (or empty).
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_in_context, CanonicalizationOptions};
+///
+/// let canonicalized = canonicalize_in_context(
+///     "<ds:Reference URI=''/>",
+///     &[("ds", "http://www.w3.org/2000/09/xmldsig#")],
+///     CanonicalizationOptions::default(),
+/// )
+/// .unwrap();
+///
+/// assert_eq!(canonicalized, r#"<ds:Reference xmlns:ds="http://www.w3.org/2000/09/xmldsig#" URI=""></ds:Reference>"#);
+/// ```
+pub fn canonicalize_in_context(
+    fragment: impl AsRef<str>,
+    context_declarations: &[(&str, &str)],
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let fragment = fragment.as_ref();
+    options.check_input_size(fragment.len())?;
+
+    let mut wrapper = "<context".to_owned();
+    for &(prefix, uri) in context_declarations {
+        if !prefix.is_empty() && !xpath::is_ncname(prefix) {
+            return Err(CanonicalizationError::InvalidNamespacePrefix(
+                prefix.to_owned(),
+            ));
+        }
+        let name = if prefix.is_empty() {
+            "xmlns".to_owned()
+        } else {
+            format!("xmlns:{prefix}")
+        };
+        wrapper += &format!(r#" {name}="{}""#, escape::xml_attribute_value(uri));
+    }
+    // on one line, so that line numbers in errors are the fragment's
+    let wrapped = format!("{wrapper}>{fragment}</context>");
+    let document = Document::read_for(wrapped.as_bytes(), None, &options)?;
+
+    unsafe {
+        let mut content = HashSet::new();
+        let mut child = (*document.root_element()).children;
+        while !child.is_null() {
+            content.insert(child);
+            child = (*child).next;
+        }
+        document.canonicalize_subtrees(options, &content)
+    }
+}
+
+/// Parse specified XML document once, and canonicalize both all of it and each of the elements with the given IDs (with
+/// everything under them)
+///
+/// For manifest-heavy documents, where the whole document and the fragments its references point to are needed, without
+/// parsing it again for each of them. The map has an entry for each distinct ID in `ids`, with the same canonical form
+/// [canonicalize_by_ids] would output for that ID alone. IDs are found like there, with the same errors.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_with_ids, CanonicalizationOptions};
+///
+/// let document = r#"<r xmlns:x="urn:x"><a ID="first"/><x:b ID="second"/></r>"#;
+/// let (canonicalized, subtrees) =
+///     canonicalize_with_ids(document, &["first", "second"], CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, r#"<r><a ID="first"></a><x:b xmlns:x="urn:x" ID="second"></x:b></r>"#);
+/// assert_eq!(subtrees["first"], r#"<a ID="first"></a>"#);
+/// assert_eq!(subtrees["second"], r#"<x:b xmlns:x="urn:x" ID="second"></x:b>"#);
+/// ```
+pub fn canonicalize_with_ids(
+    document: impl AsRef<str>,
+    ids: &[&str],
+    options: CanonicalizationOptions,
+) -> Result<(String, BTreeMap<String, String>), CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+
+    unsafe {
+        let elements_by_id = tree::elements_by_id(document.root_element());
+
+        let mut subtrees = BTreeMap::new();
+        for &id in ids {
+            let element = element_with_id(&elements_by_id, id)?;
+            if !subtrees.contains_key(id) {
+                let canonical =
+                    document.canonicalize_subtrees(options.clone(), &HashSet::from([element]))?;
+                subtrees.insert(id.to_owned(), canonical);
+            }
+        }
+
+        Ok((document.canonicalize(options)?, subtrees))
+    }
+}
+
+/// The only element with ID `id`, as found by [tree::elements_by_id]
+fn element_with_id(
+    elements_by_id: &HashMap<String, Vec<xmlNodePtr>>,
+    id: &str,
+) -> Result<xmlNodePtr, CanonicalizationError> {
+    match elements_by_id.get(id).map(Vec::as_slice) {
+        Some(&[element]) => Ok(element),
+        Some(_) => Err(CanonicalizationError::DuplicateId(id.to_owned())),
+        None => Err(CanonicalizationError::MissingId(id.to_owned())),
+    }
+}
+
+/// Canonicalize the subtree rooted at `node` of a document parsed elsewhere, e.g. with the `libxml` crate
+///
+/// `node` and its descendants are canonicalized as a document subset, so (depending on the mode) namespaces and `xml:*`
+/// attributes in scope from its ancestors are rendered on it. This is what the other subset functions (like
+/// [canonicalize_by_ids]) do once they've found their elements, for building your own selection logic on top.
+/// Canonical XML is UTF-8 without a byte order mark, and the canonical form of a whole document always starts with
+/// markup, but a subset starting with a text node starts with whatever that text does, U+FEFF included.
+/// The document is never modified, so a document that still contains references to internal entities fails with
+/// [CanonicalizationError::InvalidOptions] if [coalesce_text](CanonicalizationOptions::coalesce_text) is set.
+///
+/// # Safety
+///
+/// `document` must point to a valid document, `node` must point to a node of that document, and neither may be
+/// modified or freed by anyone else during the call.
+///
+/// Example:
+///
+/// ```
+/// use libxml::bindings::{xmlDocGetRootElement, xmlFreeDoc, xmlReadMemory};
+/// use xml_c14n::{canonicalize_subtree, CanonicalizationMode, CanonicalizationOptions};
+///
+/// let xml = r#"<a xmlns:x="urn:x"><b>text</b></a>"#;
+/// let options = CanonicalizationOptions {
+///     mode: CanonicalizationMode::Canonical1_1,
+///     ..Default::default()
+/// };
+///
+/// unsafe {
+///     let document = xmlReadMemory(xml.as_ptr() as _, xml.len() as _, c"".as_ptr(), std::ptr::null(), 0);
+///     let b = (*xmlDocGetRootElement(document)).children;
+///
+///     assert_eq!(canonicalize_subtree(document, b, options).unwrap(), r#"<b xmlns:x="urn:x">text</b>"#);
+///     xmlFreeDoc(document);
+/// }
+/// ```
+pub unsafe fn canonicalize_subtree(
+    document: xmlDocPtr,
+    node: xmlNodePtr,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    canonicalize_to_string(options, document, Some(&HashSet::from([node])), None)
+}
+
+/// Namespace of XML Schema instance attributes, like `xsi:schemaLocation`
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// Namespace of XML Signature elements
+const DSIG_NAMESPACE: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+/// Parse specified signed XML document and canonicalize its `<ds:SignedInfo>` element, as needed for computing or checking the signature value
+///
+/// `SignedInfo` is canonicalized as a document subset, so it inherits the namespace context of its ancestors:
+/// in the inclusive modes, every namespace in scope (e.g. `xmlns:ds` declared on the enclosing `<ds:Signature>`) is rendered on it,
+/// while [CanonicalizationMode::ExclusiveCanonical1_0] only renders the visibly used ones (plus [CanonicalizationOptions::inclusive_ns_prefixes]).
+/// Pick the mode named by the signature's `CanonicalizationMethod`.
+///
+/// If the document has several signatures, the first `SignedInfo` in document order is used.
+/// If there is none, returns [CanonicalizationError::ElementNotFound].
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_signed_info, CanonicalizationMode, CanonicalizationOptions};
+///
+/// let document = r#"<a xmlns:x="urn:x"><ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:SignedInfo/></ds:Signature></a>"#;
+/// let canonicalized = canonicalize_signed_info(
+///     document,
+///     CanonicalizationOptions {
+///         mode: CanonicalizationMode::Canonical1_0,
+///         ..Default::default()
+///     },
+/// ).unwrap();
+///
+/// assert_eq!(
+///     canonicalized,
+///     r#"<ds:SignedInfo xmlns:ds="http://www.w3.org/2000/09/xmldsig#" xmlns:x="urn:x"></ds:SignedInfo>"#
+/// )
+/// ```
+pub fn canonicalize_signed_info(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    canonicalize_nth_element(document, DSIG_NAMESPACE, "SignedInfo", 0, options).map_err(|error| {
+        match error {
+            CanonicalizationError::ElementIndexOutOfRange { .. } => {
+                CanonicalizationError::ElementNotFound {
+                    namespace_uri: DSIG_NAMESPACE.to_owned(),
+                    local_name: "SignedInfo".to_owned(),
+                }
+            }
+            error => error,
+        }
+    })
+}
+
+/// Parse specified XML document, canonicalize it, and report whether it contained any comments
+///
+/// The flag is about the source document, regardless of [keep_comments](CanonicalizationOptions::keep_comments),
+/// so verifiers can warn when a `WithComments` algorithm is used on a document without comments, or the other way around.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_reporting_comments, CanonicalizationOptions};
+///
+/// let (canonicalized, had_comments) =
+///     canonicalize_reporting_comments("<a><!-- c --></a>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, "<a></a>");
+/// assert!(had_comments);
+/// ```
+pub fn canonicalize_reporting_comments(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<(String, bool), CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+    let contains_comment = document.contains_comment();
+    Ok((document.canonicalize(options)?, contains_comment))
+}
+
+/// Parse specified XML document, let `transform` modify it, and canonicalize the result
+///
+/// For signing workflows that change the document before canonicalizing it (e.g. dropping a volatile timestamp),
+/// without serializing and parsing it again in between. See [Document] for what can be modified.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_with_transform, CanonicalizationOptions};
+///
+/// let document = r#"<a xmlns:t="urn:t"><t:timestamp>now</t:timestamp><b t:at="now">text</b></a>"#;
+/// let canonicalized = canonicalize_with_transform(document, CanonicalizationOptions::default(), |document| {
+///     document.remove_elements("urn:t", "timestamp");
+///     document.remove_attributes("urn:t", "at");
+/// })
+/// .unwrap();
+///
+/// assert_eq!(canonicalized, "<a><b>text</b></a>");
+/// ```
+pub fn canonicalize_with_transform(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+    transform: impl FnOnce(&mut Document),
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let mut document = Document::read_for(document.as_bytes(), None, &options)?;
+    transform(&mut document);
+    document.canonicalize(options)
+}
+
+/// Parse specified XML document, validate it against its DTD, and canonicalize it if it's valid
+///
+/// The DTD's default attributes are added to the elements that don't specify them, like with
+/// [dtd_default_attributes](CanonicalizationOptions::dtd_default_attributes). Returns [CanonicalizationError::DtdInvalid]
+/// with libxml2's validation errors if the document isn't valid, including when it has no DTD.
+///
+/// Only for documents whose DTD you trust. An external DTD subset is loaded from the local filesystem, but like every
+/// other external resource, never over the network, and entities are never substituted.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_dtd_validated, CanonicalizationError, CanonicalizationOptions};
+///
+/// let dtd = "<!DOCTYPE a [<!ELEMENT a EMPTY><!ATTLIST a b CDATA 'default'>]>";
+///
+/// let canonicalized = canonicalize_dtd_validated(format!("{dtd}<a/>"), CanonicalizationOptions::default()).unwrap();
+/// assert_eq!(canonicalized, r#"<a b="default"></a>"#);
+///
+/// let invalid = canonicalize_dtd_validated(format!("{dtd}<a>text</a>"), CanonicalizationOptions::default());
+/// assert!(matches!(invalid, Err(CanonicalizationError::DtdInvalid(_))));
+/// ```
+pub fn canonicalize_dtd_validated(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+
+    // a parser of our own, to find out whether the document was valid
+    let mut parser = ReusedParser::default();
+    let parse_options = options.parse_options()
+        | (xmlParserOption_XML_PARSE_DTDVALID | xmlParserOption_XML_PARSE_DTDATTR) as c_int;
+    let (parsed, diagnostics) = diagnostics::collect_diagnostics(|| {
+        Document::read_with(document.as_bytes(), None, parse_options, Some(&mut parser))
+    });
+    let parsed = parsed?;
+
+    if !parser.last_document_valid() {
+        return Err(CanonicalizationError::DtdInvalid(
+            diagnostics
+                .into_iter()
+                .filter(|diagnostic| diagnostic.level >= DiagnosticLevel::Error)
+                .collect(),
+        ));
+    }
+    parsed.coalesced_for(&options).canonicalize(options)
+}
+
+/// Parse specified XML document and canonicalize it, then check that parsing and canonicalizing the result again gives
+/// the same bytes
+///
+/// Canonical XML is a fixed point of canonicalization, so any difference means libxml2 got this document wrong, and
+/// whatever gets signed may not be what a verifier computes. Fails with [CanonicalizationError::NotIdempotent] then,
+/// with the position of the first differing byte. Costs about twice as much as [canonicalize_xml].
+///
+/// Output rewritten by [non_conformant](CanonicalizationOptions::non_conformant) options is never a fixed point, so
+/// they're rejected with [CanonicalizationError::InvalidOptions].
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_checked, CanonicalizationOptions};
+///
+/// let canonicalized = canonicalize_checked("<a b='1' a='2'><c/></a>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, r#"<a a="2" b="1"><c></c></a>"#);
+/// ```
+pub fn canonicalize_checked(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    if options.non_conformant != NonConformantOptions::default() {
+        return Err(CanonicalizationError::InvalidOptions(
+            "non-conformant output can't be checked by canonicalizing it again".to_owned(),
+        ));
+    }
+
+    let canonical = canonicalize_xml(document, options.clone())?;
+    let again = canonicalize_xml(&canonical, options)?;
+    match first_difference(&canonical, &again) {
+        Some(position) => Err(CanonicalizationError::NotIdempotent { position }),
+        None => Ok(canonical),
+    }
+}
+
+/// Position of the first byte that differs between `first` and `second`, counting the end of the shorter one as a
+/// difference if the other goes on
+fn first_difference(first: &str, second: &str) -> Option<usize> {
+    let common = first
+        .bytes()
+        .zip(second.bytes())
+        .position(|(first, second)| first != second);
+    common.or_else(|| (first.len() != second.len()).then(|| first.len().min(second.len())))
+}
+
+/// Parse specified XML document and canonicalize it, returning the canonical form together with a hash of it
+///
+/// The hash is 64-bit FNV-1a over the canonical bytes, which never changes between versions, platforms or processes (unlike
+/// [std::hash::Hash]), so it can key a content-addressed store or deduplicate documents. It's not cryptographic: anyone
+/// can make two documents collide, so compare the canonical forms too where that matters, or use a digest.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_with_hash, CanonicalizationOptions};
+///
+/// let (canonicalized, hash) = canonicalize_with_hash("<hi/>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, "<hi></hi>");
+/// assert_eq!(hash, 0x903f_fb4c_c885_e686);
+/// ```
+pub fn canonicalize_with_hash(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<(String, u64), CanonicalizationError> {
+    let canonical = canonicalize_xml(document, options)?;
+    let hash = fnv1a(canonical.as_bytes());
+    Ok((canonical, hash))
+}
+
+/// Parse specified XML document, canonicalize it, and hash the structure of the canonical form: the qualified names of
+/// its elements and how they nest, ignoring attributes, text, comments and processing instructions
+///
+/// For cheaply bucketing documents by shape before comparing them in full, e.g. to route messages by type: documents
+/// with the same elements in the same places share a fingerprint, whatever data they carry. Names are compared as the
+/// canonical form has them, with their prefixes, so the same structure written with different prefixes fingerprints
+/// differently. The hash is 64-bit FNV-1a, stable like [canonicalize_with_hash]'s, and just as easy to collide.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{structural_fingerprint, CanonicalizationOptions};
+///
+/// let fingerprint = |document| structural_fingerprint(document, CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(fingerprint("<order id='1'><item>a</item></order>"), fingerprint("<order id='2'><item>b</item></order>"));
+/// assert_ne!(fingerprint("<order><item/></order>"), fingerprint("<order><item/><item/></order>"));
+/// ```
+pub fn structural_fingerprint(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<u64, CanonicalizationError> {
+    let canonical = canonicalize_xml(document, options)?;
+
+    // `<name` for start tags and `</name` for end tags, which can't run into one another
+    let structure: String = tokens::tag_positions(&canonical)
+        .into_iter()
+        .map(|position| {
+            let tag = &canonical[position..];
+            &tag[..tag.find([' ', '>']).unwrap()]
+        })
+        .collect();
+    Ok(fnv1a(structure.as_bytes()))
+}
+
+/// 64-bit FNV-1a hash
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
+/// Parse specified XML document and compute the byte length of its canonical form, without holding the canonical form
+///
+/// Useful e.g. for a `Content-Length` header. The document is still canonicalized in full, but the output is only
+/// counted as libxml2 writes it, and never kept in memory. Except with [non_conformant](CanonicalizationOptions::non_conformant)
+/// options, as rewriting the output needs all of it.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonical_length, CanonicalizationOptions};
+///
+/// let length = canonical_length("<a b='ā'/>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(length, r#"<a b="ā"></a>"#.len());
+/// ```
+pub fn canonical_length(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<usize, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    Document::read_for(document.as_bytes(), None, &options)?.canonical_length(options)
+}
+
+/// Parse specified XML document and count its elements
+///
+/// Useful e.g. for sizing buffers or rejecting absurdly large documents before canonicalizing them.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::element_count;
+///
+/// assert_eq!(element_count("<a><b/><!-- c --><b>text</b></a>").unwrap(), 3);
+/// ```
+pub fn element_count(document: impl AsRef<str>) -> Result<usize, CanonicalizationError> {
+    let document = parse(document)?;
+    // SAFETY: the root element is valid for as long as the document is
+    Ok(unsafe { tree::elements(document.root_element()) }.len())
+}
+
+/// Parse specified XML document and find how deeply its elements are nested, counting the document element as 1
+///
+/// Counts like [max_depth](CanonicalizationOptions::max_depth) does, so a document passes that limit exactly if this is
+/// no more than it. Useful for choosing the limit, or for reporting on documents before canonicalizing them.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::max_depth;
+///
+/// assert_eq!(max_depth("<a><b><c>text</c></b><d/></a>").unwrap(), 3);
+/// ```
+pub fn max_depth(document: impl AsRef<str>) -> Result<usize, CanonicalizationError> {
+    let document = parse(document)?;
+    // SAFETY: the root element is valid for as long as the document is
+    Ok(unsafe { tree::depth(document.root_element()) })
+}
+
+/// Parse specified XML document and list the distinct names of its elements, as (namespace URI, local name) pairs
+///
+/// The namespace URI is empty for elements in no namespace. Prefixes are left out, as they don't change what an element
+/// is: `<ds:Signature>` and `<Signature xmlns="...">` are the same name. Meant for content policies, e.g. only signing
+/// documents whose names are all on an allowlist.
+///
+/// Example:
+///
+/// ```
+/// use std::collections::BTreeSet;
+/// use xml_c14n::element_names;
+///
+/// let names = element_names(r#"<a xmlns:x="urn:x"><x:b/><b/><x:b/></a>"#).unwrap();
+///
+/// let allowed = BTreeSet::from([
+///     (String::new(), "a".to_owned()),
+///     (String::new(), "b".to_owned()),
+///     ("urn:x".to_owned(), "b".to_owned()),
+/// ]);
+/// assert_eq!(names, allowed);
+/// assert!(names.is_subset(&allowed));
+/// ```
+pub fn element_names(
+    document: impl AsRef<str>,
+) -> Result<BTreeSet<(String, String)>, CanonicalizationError> {
+    let document = parse(document)?;
+    // SAFETY: the root element and everything under it are valid for as long as the document is
+    unsafe {
+        Ok(tree::elements(document.root_element())
+            .into_iter()
+            .map(|element| tree::expanded_name(element))
+            .collect())
+    }
+}
+
+/// Find the modes in which the specified document is already canonical
+///
+/// Useful for figuring out which algorithm produced a canonical-looking document,
+/// e.g. when diagnosing under which algorithm a signature is valid.
+/// Comments are kept while checking, so a document containing comments can still be detected.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{detect_mode, CanonicalizationMode};
+///
+/// // exclusive canonicalization would remove the unused namespace
+/// let modes = detect_mode(r#"<a xmlns:x="urn:x"></a>"#).unwrap();
+///
+/// assert_eq!(modes, [CanonicalizationMode::Canonical1_0, CanonicalizationMode::Canonical1_1]);
+/// ```
+pub fn detect_mode(
+    document: impl AsRef<str>,
+) -> Result<Vec<CanonicalizationMode>, CanonicalizationError> {
+    let document = document.as_ref();
+    let canonical_forms = canonicalize_all_modes(document, true)?;
+    Ok(canonical_forms
+        .into_iter()
+        .filter(|(_, canonicalized)| canonicalized == document)
+        .map(|(mode, _)| mode)
+        .collect())
+}
+
+/// Parse specified XML document once and canonicalize it in every [CanonicalizationMode]
+///
+/// Useful e.g. for showing how the modes differ for a given document.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_all_modes, CanonicalizationMode};
+///
+/// let canonical_forms = canonicalize_all_modes(r#"<a xmlns:x="urn:x"/>"#, false).unwrap();
+///
+/// assert_eq!(canonical_forms[&CanonicalizationMode::ExclusiveCanonical1_0], "<a></a>");
+/// assert_eq!(canonical_forms[&CanonicalizationMode::Canonical1_1], r#"<a xmlns:x="urn:x"></a>"#);
+/// ```
+pub fn canonicalize_all_modes(
+    document: impl AsRef<str>,
+    keep_comments: bool,
+) -> Result<BTreeMap<CanonicalizationMode, String>, CanonicalizationError> {
+    let document = document.as_ref();
+    let document = parse(document)?;
+    CanonicalizationMode::ALL
+        .into_iter()
+        .map(|mode| {
+            let options = CanonicalizationOptions {
+                mode,
+                keep_comments,
+                ..Default::default()
+            };
+            document
+                .canonicalize(options)
+                .map(|canonicalized| (mode, canonicalized))
+        })
+        .collect()
+}
+
+/// Canonicalize document, or only the subtrees rooted at the elements in `subtrees` or the nodes in `node_set`, applying
+/// all options
+unsafe fn canonicalize_to_string(
+    options: CanonicalizationOptions,
+    document: xmlDocPtr,
+    subtrees: Option<&HashSet<xmlNodePtr>>,
+    node_set: Option<&NodeSet>,
+) -> Result<String, CanonicalizationError> {
+    if let Some(copy) = expanded_copy(&options, document, subtrees.is_some() || node_set.is_some())?
+    {
+        return canonicalize_to_string(options, copy.as_ptr(), None, None);
+    }
+    let non_conformant = options.non_conformant.clone();
+
+    let verify_output = options.verify_output;
+    let prefixes_to_verify = options.prefixes_to_verify();
+
+    let canonical = canonicalize_document(options, document, subtrees, node_set)?.to_string()?;
+    if verify_output {
+        shape::check_shape(&canonical).map_err(CanonicalizationError::NonCanonicalOutput)?;
+    }
+    check_prefixes_declared(&canonical, &prefixes_to_verify)?;
+    let output_elements = || {
+        tree::elements(xmlDocGetRootElement(document))
+            .into_iter()
+            .filter(|&element| {
+                in_subtrees(element, subtrees)
+                    && node_set.is_none_or(|node_set| node_set.contains(element, null_mut()))
+            })
+            .collect()
+    };
+    Ok(non_conformant.apply(output_elements, canonical))
+}
+
+/// Check that some start tag in `canonical` declares each of `prefixes`, see
+/// [CanonicalizationOptions::verify_inclusive_prefixes]
+fn check_prefixes_declared(
+    canonical: &str,
+    prefixes: &[String],
+) -> Result<(), CanonicalizationError> {
+    if prefixes.is_empty() {
+        return Ok(());
+    }
+    let declared: HashSet<_> = tokens::start_tags(canonical)
+        .iter()
+        .flat_map(|tag| {
+            tag.attributes
+                .iter()
+                .filter_map(|attribute| attribute.declared_prefix())
+        })
+        .map(str::to_owned)
+        .collect();
+
+    match prefixes.iter().find(|&prefix| {
+        let prefix = if prefix == "#default" {
+            ""
+        } else {
+            prefix.as_str()
+        };
+        !declared.contains(prefix)
+    }) {
+        Some(missing) => Err(CanonicalizationError::InclusivePrefixNotRendered(
+            missing.clone(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The first thing `find` finds in one of `subtrees`, or in `whole` if there's no subset
+///
+/// Subtrees are searched in document order, so that the one reported doesn't depend on the hash order of the set.
+unsafe fn find_in_subtrees<T>(
+    document: xmlDocPtr,
+    subtrees: Option<&HashSet<xmlNodePtr>>,
+    whole: xmlNodePtr,
+    mut find: impl FnMut(xmlNodePtr) -> Option<T>,
+) -> Option<T> {
+    match subtrees {
+        Some(roots) => tree::elements(xmlDocGetRootElement(document))
+            .into_iter()
+            .filter(|element| roots.contains(element))
+            .find_map(&mut find),
+        None => find(whole),
+    }
+}
+
+/// Whether `node` is in one of `subtrees` (always true if there's no subset)
+unsafe fn in_subtrees(node: xmlNodePtr, subtrees: Option<&HashSet<xmlNodePtr>>) -> bool {
+    subtrees
+        .is_none_or(|roots| tree::ancestors_or_self(node).any(|ancestor| roots.contains(&ancestor)))
+}
+
+/// With [coalesce_text](CanonicalizationOptions::coalesce_text), a copy of `document` with internal entities expanded,
+/// if it still has any
+///
+/// Documents parsed here are expanded right after parsing (see [Document::read_for]), this is for documents parsed
+/// elsewhere. Expanding replaces nodes, so it's never done in place: they may be borrowed, or a subset may point into
+/// them. A subset can't be carried over into a copy, so canonicalizing a `subset` fails with
+/// [CanonicalizationError::InvalidOptions].
+unsafe fn expanded_copy(
+    options: &CanonicalizationOptions,
+    document: xmlDocPtr,
+    subset: bool,
+) -> Result<Option<Document>, CanonicalizationError> {
+    if !options.coalesce_text || !tree::contains_internal_entity_reference(document as xmlNodePtr) {
+        return Ok(None);
+    }
+    if subset {
+        return Err(CanonicalizationError::InvalidOptions(
+            "coalesce_text can't expand entity references in a subset of a document parsed without it".to_owned(),
+        ));
+    }
+
+    let copy = tree::copy_document(document);
+    if copy.is_null() {
+        return Err(CanonicalizationErrorCode::new(-1)
+            .with_message(Some("could not copy the document".to_owned()))
+            .into());
+    }
+    let copy = Document::from_raw(copy);
+    tree::expand_internal_entities(copy.as_ptr() as xmlNodePtr);
+    Ok(Some(copy))
+}
+
+/// Canonicalize document, or only the subtrees rooted at the elements in `subtrees` or the nodes in `node_set`
+///
+/// Checks that look at a subset only look at `subtrees`, with a `node_set` they check the whole document.
+unsafe fn canonicalize_document(
+    options: CanonicalizationOptions,
+    document: xmlDocPtr,
+    subtrees: Option<&HashSet<xmlNodePtr>>,
+    node_set: Option<&NodeSet>,
+) -> Result<XmlOutput, CanonicalizationError> {
+    match canonicalize_document_to(options, document, subtrees, node_set, Sink::Collect)? {
+        Canonicalized::Output(output) => Ok(output),
+        Canonicalized::Length(_) => unreachable!("only counting gives a length"),
+    }
+}
+
+/// Byte length of the canonical form of `document`, counted as libxml2 writes it instead of collecting it
+unsafe fn canonical_document_length(
+    options: CanonicalizationOptions,
+    document: xmlDocPtr,
+) -> Result<usize, CanonicalizationError> {
+    match canonicalize_document_to(options, document, None, None, Sink::Count)? {
+        Canonicalized::Length(length) => Ok(length),
+        Canonicalized::Output(output) => Ok(output.as_bytes().len()),
+    }
+}
+
+/// Same as [canonicalize_document], with the output going to `sink`
+unsafe fn canonicalize_document_to(
+    options: CanonicalizationOptions,
+    document: xmlDocPtr,
+    subtrees: Option<&HashSet<xmlNodePtr>>,
+    node_set: Option<&NodeSet>,
+    sink: Sink,
+) -> Result<Canonicalized, CanonicalizationError> {
+    options.check_usable()?;
+
+    if let Some(copy) = expanded_copy(&options, document, subtrees.is_some() || node_set.is_some())?
+    {
+        return canonicalize_document_to(options, copy.as_ptr(), None, None, sink);
+    }
+    let hidden_namespaces = if options.strip_xsi_attributes {
+        tree::declarations_unused_by_elements(xmlDocGetRootElement(document), XSI_NAMESPACE)
+    } else {
+        HashSet::new()
+    };
+
+    if let Some(limit) = options.max_depth {
+        if let Some(element) =
+            tree::first_element_deeper_than(xmlDocGetRootElement(document), limit)
+        {
+            return Err(CanonicalizationError::MaxDepthExceeded {
+                limit,
+                line: tree::line_of(element),
+            });
+        }
+    }
+    if options.reject_duplicate_ids {
+        let mut seen = HashSet::new();
+        let ids = tree::ids_including(xmlDocGetRootElement(document), &options.extra_id_attributes);
+        if let Some((id, _)) = ids.into_iter().find(|(id, _)| !seen.insert(id.clone())) {
+            return Err(CanonicalizationError::DuplicateId(id));
+        }
+    }
+    if options.reject_prefix_redefinition {
+        let found = find_in_subtrees(document, subtrees, xmlDocGetRootElement(document), |root| {
+            tree::first_prefix_redefinition(root)
+        });
+        if let Some((element, prefix)) = found {
+            return Err(CanonicalizationError::PrefixRedefined {
+                prefix,
+                line: tree::line_of(element),
+            });
+        }
+    }
+    if options.reject_comments {
+        let comment = find_in_subtrees(document, subtrees, document as xmlNodePtr, |root| {
+            tree::first_comment(root)
+        });
+        if let Some(comment) = comment {
+            return Err(CanonicalizationError::CommentRejected {
+                line: tree::line_of(comment),
+            });
+        }
+    }
+    if options.reject_control_characters {
+        let found = find_in_subtrees(document, subtrees, document as xmlNodePtr, |root| {
+            tree::first_control_character(root)
+        });
+        if let Some((node, character)) = found {
+            return Err(CanonicalizationError::ControlCharacterRejected {
+                character,
+                line: tree::line_of(node),
+            });
+        }
+    }
+    if options.require_element_only {
+        let found = find_in_subtrees(document, subtrees, xmlDocGetRootElement(document), |root| {
+            tree::first_mixed_content(root)
+        });
+        if let Some(element) = found {
+            return Err(CanonicalizationError::MixedContent {
+                element: tree::qualified_name(element),
+                line: tree::line_of(element),
+            });
+        }
+    }
+
+    // libxml2 explains what went wrong in error reports, and the last one is usually just "saving doc to output buffer"
+    let (result, diagnostics) = diagnostics::collect_diagnostics(|| {
+        let comment_roots: Option<HashSet<xmlNodePtr>> = match &options.keep_comments_under {
+            Some(expression) => Some(
+                XPathContext::new(document, &options.xpath_bindings())?
+                    .select_nodes(expression)?
+                    .into_iter()
+                    .collect(),
+            ),
+            None => None,
+        };
+
+        if matches!(sink, Sink::Count)
+            || subtrees.is_some()
+            || node_set.is_some()
+            || comment_roots.is_some()
+            || options.max_output_bytes.is_some()
+            || !options.strip_processing_instructions.is_empty()
+            || options.strip_xsi_attributes
+        {
+            let is_visible = |node: xmlNodePtr, parent: xmlNodePtr| {
+                let node_type = (*node).type_;
+
+                // namespace nodes are not real nodes, so `parent` is the element they're on
+                let element = if node_type == xmlElementType_XML_NAMESPACE_DECL {
+                    parent
+                } else {
+                    node
+                };
+                let in_subset = in_subtrees(element, subtrees)
+                    && node_set.is_none_or(|node_set| node_set.contains(node, parent));
+
+                let comment_visible = match &comment_roots {
+                    Some(roots) if node_type == xmlElementType_XML_COMMENT_NODE => {
+                        tree::ancestors_or_self(node).any(|ancestor| roots.contains(&ancestor))
+                    }
+                    _ => true,
+                };
+
+                // hidden rather than removed, so the document and any node set selected from it stay intact
+                let stripped = node_type == xmlElementType_XML_PI_NODE
+                    && subtrees.is_none_or(|roots| !roots.contains(&node))
+                    && options.strip_processing_instructions.iter().any(|target| {
+                        target.as_bytes() == CStr::from_ptr((*node).name as *const _).to_bytes()
+                    });
+
+                let stripped = stripped
+                    || (options.strip_xsi_attributes
+                        && node_type == xmlElementType_XML_ATTRIBUTE_NODE
+                        && tree::in_namespace((*(node as xmlAttrPtr)).ns, XSI_NAMESPACE))
+                    || (node_type == xmlElementType_XML_NAMESPACE_DECL
+                        && hidden_namespaces.contains(&(node as xmlNsPtr)));
+
+                in_subset && comment_visible && !stripped
+            };
+
+            return execute::canonicalize_with_visibility(
+                document,
+                options.mode,
+                options.inclusive_ns_prefixes,
+                // with an XPath, comments are filtered by is_visible instead
+                options.keep_comments || comment_roots.is_some(),
+                &is_visible,
+                options.max_output_bytes,
+                sink,
+            );
+        }
+
+        // "NULL if all document nodes should be included"
+        let nodes = null::<xmlNodeSet>() as *mut _;
+
+        let mut ns_list_c = to_xml_string_vec(options.inclusive_ns_prefixes);
+        let with_comments = c_int::from(options.keep_comments);
+
+        let mut output = null::<xmlChar>() as *mut xmlChar;
+
+        let mode = if inject::take(inject::Failure::Canonicalization) {
+            -1
+        } else {
+            options.mode.to_c_int()
+        };
+        let return_code = xmlC14NDocDumpMemory(
+            document,
+            nodes,
+            mode,
+            ns_list_c.as_mut_ptr(),
+            with_comments,
+            (&mut output) as *mut _,
+        );
+
+        free_xml_string_vec(ns_list_c);
+
+        if return_code < 0 {
+            Err(CanonicalizationErrorCode::new(return_code).into())
+        } else {
+            // SAFETY: xmlC14NDocDumpMemory completed successfully, so `output` is either null or a string it allocated,
+            // and it returns the string's length
+            Ok(Canonicalized::Output(XmlOutput::from_raw_parts(
+                output,
+                return_code as usize,
+            )))
+        }
+    });
+
+    let output = result.map_err(|error| match error {
+        CanonicalizationError::Canonicalization(code) => {
+            match tree::first_uncanonicalizable(xmlDocGetRootElement(document)) {
+                Some(Uncanonicalizable::RelativeNamespace(element, uri)) => {
+                    return CanonicalizationError::RelativeNamespaceUri {
+                        uri,
+                        line: tree::line_of(element),
+                    }
+                }
+                Some(Uncanonicalizable::InvalidNamespace(element, uri)) => {
+                    return CanonicalizationError::InvalidNamespaceUri {
+                        uri,
+                        line: tree::line_of(element),
+                    }
+                }
+                Some(Uncanonicalizable::EntityReference(reference, name)) => {
+                    return CanonicalizationError::UnexpandedEntityReference {
+                        name,
+                        line: tree::line_of(reference),
+                    }
+                }
+                None => {}
+            }
+
+            let first_error = diagnostics
+                .into_iter()
+                .find(|diagnostic| diagnostic.level >= DiagnosticLevel::Error);
+            code.with_message(first_error.map(|diagnostic| diagnostic.message))
+                .into()
+        }
+        error => error,
+    })?;
+    Ok(output)
+}
+
+/// Create a [Vec] of null-terminated [*mut xmlChar] strings
+fn to_xml_string_vec(vec: Vec<String>) -> Vec<*mut xmlChar> {
+    vec.into_iter()
+        .map(|s| CString::new(s).unwrap().into_raw() as *mut xmlChar)
+        .chain(once(std::ptr::null_mut()))
+        .collect()
+}
+
+/// Deallocate a [Vec] of null-terminated [*mut xmlChar] strings
+///
+/// # Safety
+///
+/// The argument must have been created with [to_xml_string_vec]
+unsafe fn free_xml_string_vec(vec: Vec<*mut xmlChar>) {
+    for s in vec {
+        if !s.is_null() {
+            let _ = CString::from_raw(s as *mut c_char);
+        }
+    }
+}
+
+/// Convert an encoding name to a C string, making sure libxml2 knows the encoding
+///
+/// libxml2 silently ignores unknown encodings, so this needs to be checked upfront.
+fn c_encoding_name(encoding: &str) -> Result<CString, CanonicalizationError> {
+    let unknown = || CanonicalizationError::UnknownEncoding(encoding.to_owned());
+    let c_encoding = CString::new(encoding).map_err(|_| unknown())?;
+
+    unsafe {
+        let handler = xmlFindCharEncodingHandler(c_encoding.as_ptr());
+        if handler.is_null() {
+            return Err(unknown());
+        }
+        xmlCharEncCloseFunc(handler);
+    }
+
+    Ok(c_encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    //! Test cases are taken from official spec and other sources. For more info see corresponding Readmes.
+    use super::*;
+    use libxml::bindings::{xmlElementType_XML_ENTITY_REF_NODE, xmlParserOption_XML_PARSE_RECOVER};
+
+    #[test]
     fn canonical_1_1_example_3_1_no_comment() {
         let input = include_str!("samples/canonical_1_1/3_1_input.xml");
-        let expected = include_str!("samples/canonical_1_1/3_1_output_no_comment.xml");
+        let expected = include_str!("samples/canonical_1_1/3_1_output_no_comment.xml");
+
+        let canonicalized = canonicalize_xml(
+            input,
+            CanonicalizationOptions {
+                mode: CanonicalizationMode::Canonical1_1,
+                keep_comments: false,
+                inclusive_ns_prefixes: vec![],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(canonicalized, expected)
+    }
+
+    #[test]
+    fn canonical_1_1_example_3_1() {
+        let input = include_str!("samples/canonical_1_1/3_1_input.xml");
+        let expected = include_str!("samples/canonical_1_1/3_1_output.xml");
+
+        let canonicalized = canonicalize_xml(
+            input,
+            CanonicalizationOptions {
+                mode: CanonicalizationMode::Canonical1_1,
+                keep_comments: true,
+                inclusive_ns_prefixes: vec![],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(canonicalized, expected)
+    }
+
+    #[test]
+    fn xml_attributes_on_subset_apex() {
+        let cases = [
+            (
+                include_str!("samples/xml_attributes/id_input.xml"),
+                "",
+                "e1",
+                include_str!("samples/xml_attributes/id_e1_output_1_0.xml"),
+                include_str!("samples/xml_attributes/id_e1_output_1_1.xml"),
+            ),
+            (
+                include_str!("samples/xml_attributes/base_input.xml"),
+                "http://www.ietf.org",
+                "e12",
+                include_str!("samples/xml_attributes/base_e12_output_1_0.xml"),
+                include_str!("samples/xml_attributes/base_e12_output_1_1.xml"),
+            ),
+            (
+                include_str!("samples/xml_attributes/base_input.xml"),
+                "http://www.ietf.org",
+                "e311",
+                include_str!("samples/xml_attributes/base_e311_output_1_0.xml"),
+                include_str!("samples/xml_attributes/base_e311_output_1_1.xml"),
+            ),
+        ];
+
+        for (input, namespace_uri, local_name, expected_1_0, expected_1_1) in cases {
+            for (mode, expected) in [
+                (CanonicalizationMode::Canonical1_0, expected_1_0),
+                (CanonicalizationMode::Canonical1_1, expected_1_1),
+            ] {
+                let canonicalized = canonicalize_nth_element(
+                    input,
+                    namespace_uri,
+                    local_name,
+                    0,
+                    CanonicalizationOptions {
+                        mode,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                assert_eq!(canonicalized, expected, "{local_name} in {mode:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_1_1_example_3_2() {
+        let input = include_str!("samples/canonical_1_1/3_2_input.xml");
+        let expected = include_str!("samples/canonical_1_1/3_2_output.xml");
+
+        let canonicalized = canonicalize_xml(
+            input,
+            CanonicalizationOptions {
+                mode: CanonicalizationMode::Canonical1_1,
+                keep_comments: true,
+                inclusive_ns_prefixes: vec![],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(canonicalized, expected)
+    }
+
+    #[test]
+    fn canonical_exclusive_example_1() {
+        let input = include_str!("samples/canonical_exclusive/1_input.xml");
+        let expected = include_str!("samples/canonical_exclusive/1_output.xml");
+
+        let canonicalized = canonicalize_xml(
+            input,
+            CanonicalizationOptions {
+                mode: CanonicalizationMode::ExclusiveCanonical1_0,
+                keep_comments: true,
+                inclusive_ns_prefixes: vec![],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(canonicalized, expected)
+    }
+
+    #[test]
+    fn canonical_exclusive_example_2() {
+        let input = include_str!("samples/canonical_exclusive/2_input.xml");
+        let expected = include_str!("samples/canonical_exclusive/2_output.xml");
+
+        let canonicalized = canonicalize_xml(
+            input,
+            CanonicalizationOptions {
+                mode: CanonicalizationMode::ExclusiveCanonical1_0,
+                keep_comments: true,
+                inclusive_ns_prefixes: ["stay1".to_string(), "stay2".to_string()].to_vec(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(canonicalized, expected)
+    }
+
+    #[test]
+    fn inclusive_default_and_named_prefixes() {
+        let input = r#"<p xmlns="urn:d" xmlns:a="urn:a" xmlns:b="urn:b" xmlns:c="urn:c"><a:r><y/></a:r></p>"#;
+        // duplicates and the position of #default in the list make no difference
+        for prefixes in [
+            vec!["#default", "a", "b"],
+            vec!["b", "#default", "a", "#default", "b"],
+        ] {
+            let options = CanonicalizationOptions {
+                inclusive_ns_prefixes: prefixes.into_iter().map(str::to_owned).collect(),
+                ..Default::default()
+            };
+
+            assert_eq!(
+                canonicalize_xml(input, options.clone()).unwrap(),
+                r#"<p xmlns="urn:d" xmlns:a="urn:a" xmlns:b="urn:b"><a:r><y></y></a:r></p>"#
+            );
+            // the apex of a subset inherits the default namespace, so it's rendered there even though a:r doesn't use it
+            assert_eq!(
+                canonicalize_nth_element(input, "urn:a", "r", 0, options).unwrap(),
+                r#"<a:r xmlns="urn:d" xmlns:a="urn:a" xmlns:b="urn:b"><y></y></a:r>"#
+            );
+        }
+    }
+
+    #[test]
+    fn single_quoted_attributes() {
+        let input = r#"<a xmlns:x='urn:x' b='c' d='say "hi"' e="it's" x:f='&apos;'/>"#;
+        let expected = r#"<a xmlns:x="urn:x" b="c" d="say &quot;hi&quot;" e="it's" x:f="'"></a>"#;
+
+        for mode in CanonicalizationMode::ALL {
+            for namespace_document_order in [false, true] {
+                let canonicalized = canonicalize_xml(
+                    input,
+                    CanonicalizationOptions {
+                        mode,
+                        non_conformant: NonConformantOptions {
+                            namespace_document_order,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                assert_eq!(canonicalized, expected, "{mode:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn cstr_matches_str() {
+        let input = include_str!("samples/canonical_1_1/3_1_input.xml");
+        let c_input = CString::new(input).unwrap();
+        let options = CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_1,
+            keep_comments: true,
+            inclusive_ns_prefixes: vec![],
+            ..Default::default()
+        };
+
+        let from_str = canonicalize_xml(input, options.clone()).unwrap();
+        let from_cstr = canonicalize_cstr(&c_input, options).unwrap();
+        assert_eq!(from_str, from_cstr)
+    }
+
+    #[test]
+    fn to_bytes() {
+        let input = include_str!("samples/canonical_1_1/3_1_input.xml");
+        let options = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            canonicalize_xml_to_bytes(input, options).unwrap(),
+            include_bytes!("samples/canonical_1_1/3_1_output.xml")
+        );
+    }
+
+    #[test]
+    fn invalid_xml() {
+        let input = "<invalid xml";
+        let canonicalized = canonicalize_xml(
+            input,
+            CanonicalizationOptions {
+                mode: CanonicalizationMode::Canonical1_0,
+                keep_comments: false,
+                inclusive_ns_prefixes: vec![],
+                ..Default::default()
+            },
+        );
+        let Err(CanonicalizationError::Parse(error)) = canonicalized else {
+            panic!("invalid XML was accepted: {canonicalized:?}");
+        };
+        assert_eq!(
+            error.to_string(),
+            "line 1: Couldn't find end of Start Tag invalid line 1"
+        );
+        assert_eq!((error.level, error.domain), (DiagnosticLevel::Fatal, 1));
+
+        // what led up to it, starting with the actual problem
+        let messages: Vec<_> = error
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "Specification mandates value for attribute xml",
+                "attributes construct error",
+                "Couldn't find end of Start Tag invalid line 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn cstring_matches_str() {
+        let input = include_str!("samples/canonical_1_1/3_1_input.xml");
+        let options = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
+
+        let canonicalized = canonicalize_to_cstring(input, options.clone()).unwrap();
+        assert_eq!(
+            canonicalized.to_str(),
+            Ok(canonicalize_xml(input, options).unwrap().as_str())
+        );
+    }
+
+    #[test]
+    fn escaped_round_trip() {
+        let input = "<a x=\"&lt;\"><!-- 1 > 0 -->\r\n<b>&amp;&#xD;</b></a>";
+        let options = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
+
+        let canonicalized = canonicalize_xml(input, options.clone()).unwrap();
+        let escaped = canonicalize_escaped(input, options.clone()).unwrap();
+        assert!(!escaped.contains(['<', '>', '\r']));
+
+        let unescaped = escaped
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&#xD;", "\r")
+            .replace("&amp;", "&");
+        assert_eq!(unescaped, canonicalized);
+
+        // embedded as text, it survives canonicalization of the enclosing document unchanged
+        let wrapper = format!("<manifest>{escaped}</manifest>");
+        assert_eq!(canonicalize_xml(&wrapper, options).unwrap(), wrapper);
+    }
+
+    #[test]
+    fn dtd_default_attributes() {
+        let input =
+            r#"<!DOCTYPE e [<!ATTLIST e a CDATA "default" b CDATA #FIXED "fixed">]><e c="x"/>"#;
+        let with_defaults = CanonicalizationOptions {
+            dtd_default_attributes: true,
+            ..Default::default()
+        };
+
+        // alternate, in case parser state leaks between documents
+        for _ in 0..2 {
+            assert_eq!(
+                canonicalize_xml(input, with_defaults.clone()).unwrap(),
+                r#"<e a="default" b="fixed" c="x"></e>"#
+            );
+            assert_eq!(
+                canonicalize_xml(input, CanonicalizationOptions::default()).unwrap(),
+                r#"<e c="x"></e>"#
+            );
+        }
+
+        // specified values win over the defaults
+        let canonicalized = canonicalize_xml(
+            r#"<!DOCTYPE e [<!ATTLIST e a CDATA "default">]><e a="set"/>"#,
+            with_defaults,
+        )
+        .unwrap();
+        assert_eq!(canonicalized, r#"<e a="set"></e>"#);
+    }
+
+    #[test]
+    fn raw_parse_options() {
+        let input = "<a><b></a>";
+        assert!(matches!(
+            canonicalize_xml(input, CanonicalizationOptions::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+
+        let canonicalized = canonicalize_xml(
+            input,
+            CanonicalizationOptions {
+                raw_parse_options: xmlParserOption_XML_PARSE_RECOVER as i32,
+                ..Default::default()
+            },
+        );
+        assert_eq!(canonicalized, Ok("<a><b></b></a>".to_owned()));
+    }
+
+    #[test]
+    fn empty_document() {
+        for input in ["", "  \n", "<!-- no element -->", "<?xml version=\"1.0\"?>"] {
+            assert_eq!(
+                canonicalize_xml(input, CanonicalizationOptions::default()),
+                Err(CanonicalizationError::EmptyDocument),
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_xml_error_source() {
+        use std::error::Error;
+
+        let err = canonicalize_xml("<invalid xml", CanonicalizationOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "failed to parse XML document");
+
+        let source = err.source().unwrap().downcast_ref::<ParseError>().unwrap();
+        assert_eq!(source.line, Some(1));
+        assert!(!source.message.is_empty());
+    }
+
+    #[test]
+    fn xml_subset() {
+        let input = r#"<r xmlns="urn:r" xmlns:x="urn:x"><a n="1" x:m="2"><c/>text<!--k--></a><a n="2"/><b/></r>"#;
+        let options = CanonicalizationOptions {
+            keep_comments: true,
+            xpath_namespaces: BTreeMap::from([("r".to_owned(), "urn:r".to_owned())]),
+            ..Default::default()
+        };
+        let subset = |xpath: &str, mode: CanonicalizationMode| {
+            canonicalize_xml_subset(
+                input,
+                xpath,
+                CanonicalizationOptions {
+                    mode,
+                    ..options.clone()
+                },
+            )
+        };
+
+        // a single element with everything under it, as a subtree
+        for mode in [
+            CanonicalizationMode::Canonical1_0,
+            CanonicalizationMode::ExclusiveCanonical1_0,
+        ] {
+            assert_eq!(
+                subset(
+                    "(//. | //@* | //namespace::*)[ancestor-or-self::r:a[@n='1']]",
+                    mode
+                )
+                .unwrap(),
+                canonicalize_nth_element(
+                    input,
+                    "urn:r",
+                    "a",
+                    0,
+                    CanonicalizationOptions {
+                        mode,
+                        keep_comments: true,
+                        ..Default::default()
+                    }
+                )
+                .unwrap()
+            );
+        }
+
+        // a flat set of siblings, without anything under them
+        assert_eq!(
+            subset("/r:r/r:a", CanonicalizationMode::Canonical1_0).unwrap(),
+            "<a></a><a></a>"
+        );
+        assert_eq!(
+            subset("/r:r/r:a | /r:r/r:a/@*", CanonicalizationMode::Canonical1_0).unwrap(),
+            r#"<a n="1" x:m="2"></a><a n="2"></a>"#
+        );
+        assert_eq!(
+            subset(
+                "//r:a/descendant-or-self::node()",
+                CanonicalizationMode::Canonical1_0
+            )
+            .unwrap(),
+            "<a><c></c>text<!--k--></a><a></a>"
+        );
+
+        // attribute and namespace nodes on their own
+        assert_eq!(
+            subset("//r:a/@n", CanonicalizationMode::Canonical1_0).unwrap(),
+            r#" n="1" n="2""#
+        );
+        assert_eq!(
+            subset(
+                "//r:a[@n='2']/namespace::*",
+                CanonicalizationMode::Canonical1_0
+            )
+            .unwrap(),
+            r#" xmlns="urn:r" xmlns:x="urn:x""#
+        );
+
+        assert_eq!(
+            subset("//r:nothing", CanonicalizationMode::Canonical1_0).unwrap(),
+            ""
+        );
+        for (invalid, message) in [
+            ("//r:a[", Some("Invalid expression")),
+            ("//y:a", Some("Undefined namespace prefix")),
+            ("count(//r:a)", None),
+            ("a\0", None),
+        ] {
+            assert_eq!(
+                subset(invalid, CanonicalizationMode::Canonical1_0),
+                Err(CanonicalizationError::InvalidXPath {
+                    expression: invalid.to_owned(),
+                    message: message.map(str::to_owned),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn keep_comments_under_xpath() {
+        let input =
+            "<doc><!--a--><keep><!--b--><x><!--c--></x></keep><other><!--d--></other></doc>";
+
+        let canonicalized = canonicalize_xml(
+            input,
+            CanonicalizationOptions {
+                keep_comments_under: Some("//keep".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            canonicalized,
+            "<doc><keep><!--b--><x><!--c--></x></keep><other></other></doc>"
+        )
+    }
+
+    #[test]
+    fn keep_comments_under_prefixed_xpath() {
+        let input = r#"<a xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><!-- a --><ds:Signature><!-- sig --></ds:Signature></a>"#;
+
+        let canonicalized = canonicalize_xml(
+            input,
+            CanonicalizationOptions {
+                mode: CanonicalizationMode::Canonical1_1,
+                keep_comments_under: Some("//dsig:Signature".to_owned()),
+                xpath_namespaces: BTreeMap::from([(
+                    "dsig".to_owned(),
+                    "http://www.w3.org/2000/09/xmldsig#".to_owned(),
+                )]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            canonicalized,
+            r#"<a xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:Signature><!-- sig --></ds:Signature></a>"#
+        );
+
+        // the document's own prefixes are not bound
+        let input = input
+            .replace("ds:", "sig:")
+            .replace("xmlns:ds", "xmlns:sig");
+        let canonicalized = canonicalize_xml(
+            &input,
+            CanonicalizationOptions {
+                keep_comments_under: Some("//sig:Signature".to_owned()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            canonicalized,
+            Err(CanonicalizationError::InvalidXPath {
+                expression: "//sig:Signature".to_owned(),
+                message: Some("Undefined namespace prefix".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn well_known_xpath_namespaces() {
+        let input = r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><!-- envelope --><soap:Body><s:Signature xmlns:s="http://www.w3.org/2000/09/xmldsig#"><!-- sig --></s:Signature><!-- body --></soap:Body></soap:Envelope>"#;
+        let options = |expression: &str| CanonicalizationOptions {
+            keep_comments_under: Some(expression.to_owned()),
+            ..Default::default()
+        };
+
+        let canonicalized = canonicalize_xml(input, options("//ds:Signature")).unwrap();
+        assert_eq!(
+            canonicalized,
+            r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body><s:Signature xmlns:s="http://www.w3.org/2000/09/xmldsig#"><!-- sig --></s:Signature></soap:Body></soap:Envelope>"#
+        );
+
+        // bound prefixes override the well-known ones
+        let soap12 = CanonicalizationOptions {
+            xpath_namespaces: BTreeMap::from([(
+                "soap".to_owned(),
+                "http://www.w3.org/2003/05/soap-envelope".to_owned(),
+            )]),
+            ..options("//soap:Body")
+        };
+        assert_eq!(
+            canonicalize_xml(input, soap12).unwrap(),
+            canonicalize_xml(input, Default::default()).unwrap()
+        );
+
+        let skipped = CanonicalizationOptions {
+            skip_well_known_xpath_namespaces: true,
+            ..options("//ds:Signature")
+        };
+        assert_eq!(
+            canonicalize_xml(input, skipped),
+            Err(CanonicalizationError::InvalidXPath {
+                expression: "//ds:Signature".to_owned(),
+                message: Some("Undefined namespace prefix".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn keep_comments_under_invalid_xpath() {
+        let canonicalized = canonicalize_xml(
+            "<doc/>",
+            CanonicalizationOptions {
+                keep_comments_under: Some("//[".to_owned()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            canonicalized,
+            Err(CanonicalizationError::InvalidXPath {
+                expression: "//[".to_owned(),
+                message: Some("Invalid expression".to_owned()),
+            })
+        )
+    }
+
+    #[test]
+    fn detect_mode_all() {
+        let modes = detect_mode("<a><!-- hi --><b></b></a>").unwrap();
+        assert_eq!(modes, CanonicalizationMode::ALL);
+    }
+
+    #[test]
+    fn detect_mode_none() {
+        let modes = detect_mode("<a><b/></a>").unwrap();
+        assert_eq!(modes, []);
+    }
+
+    #[test]
+    fn modes_supported() {
+        // any libxml2 the libxml crate works with is recent enough
+        assert!(libxml_version().unwrap().number() >= 20700);
+        for mode in CanonicalizationMode::ALL {
+            assert!(mode.is_supported(), "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn all_modes() {
+        let input = r#"<a xmlns:x="urn:x"><!-- c --></a>"#;
+        let canonical_forms = canonicalize_all_modes(input, true).unwrap();
+
+        assert_eq!(
+            canonical_forms.keys().copied().collect::<Vec<_>>(),
+            CanonicalizationMode::ALL
+        );
+        for (mode, canonicalized) in canonical_forms {
+            let options = CanonicalizationOptions {
+                mode,
+                keep_comments: true,
+                ..Default::default()
+            };
+            assert_eq!(canonicalized, canonicalize_xml(input, options).unwrap());
+        }
+    }
+
+    #[test]
+    fn count_elements() {
+        let input = include_str!("samples/canonical_1_1/3_1_input.xml");
+        assert_eq!(element_count(input), Ok(1));
+
+        let deep = format!("{}{}", "<a>".repeat(100), "</a>".repeat(100));
+        assert_eq!(element_count(deep), Ok(100));
+
+        assert!(matches!(
+            element_count("<a>"),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn deepest_element() {
+        let input = "<a><b/><c><d><!-- e --><?f?>text<g/></d></c><h><i/></h></a>";
+        assert_eq!(super::max_depth(input).unwrap(), 4);
+        assert_eq!(super::max_depth("<a/>").unwrap(), 1);
+
+        for (limit, passes) in [(3, false), (4, true)] {
+            let options = CanonicalizationOptions {
+                max_depth: Some(limit),
+                ..Default::default()
+            };
+            assert_eq!(canonicalize_xml(input, options).is_ok(), passes, "{limit}");
+        }
+
+        assert!(matches!(
+            super::max_depth("<a>"),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn names_of_elements() {
+        let input = r#"<r xmlns="urn:default" xmlns:x="urn:x">
+            <x:a><a xmlns=""/><y:a xmlns:y="urn:x"/></x:a><!-- <c/> --><b>text</b>
+        </r>"#;
+        let names = element_names(input).unwrap();
+        assert_eq!(
+            names.into_iter().collect::<Vec<_>>(),
+            [
+                (String::new(), "a".to_owned()),
+                ("urn:default".to_owned(), "b".to_owned()),
+                ("urn:default".to_owned(), "r".to_owned()),
+                ("urn:x".to_owned(), "a".to_owned()),
+            ]
+        );
+
+        assert!(matches!(
+            element_names("<a>"),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn max_input_bytes() {
+        let options = CanonicalizationOptions {
+            max_input_bytes: Some(5),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            canonicalize_xml("<hi/>", options.clone()).unwrap(),
+            "<hi></hi>"
+        );
+        assert_eq!(
+            canonicalize_xml("<hi />", options.clone()),
+            Err(CanonicalizationError::InputTooLarge { size: 6, limit: 5 })
+        );
+        assert_eq!(
+            canonicalize_cstr(c"<hi />", options),
+            Err(CanonicalizationError::InputTooLarge { size: 6, limit: 5 })
+        );
+    }
+
+    #[test]
+    fn max_output_bytes() {
+        let large = format!("<a>{}</a>", "<b x='1'>text</b>".repeat(10_000));
+        let canonical = canonicalize_xml(&large, Default::default()).unwrap();
+
+        for (limit, expected) in [
+            (canonical.len(), Ok(canonical.clone())),
+            (
+                canonical.len() - 1,
+                Err(CanonicalizationError::OutputTooLarge {
+                    limit: canonical.len() - 1,
+                }),
+            ),
+            (
+                100,
+                Err(CanonicalizationError::OutputTooLarge { limit: 100 }),
+            ),
+        ] {
+            let options = CanonicalizationOptions {
+                max_output_bytes: Some(limit),
+                ..Default::default()
+            };
+            assert_eq!(canonicalize_xml(&large, options.clone()), expected);
+            // also with a subset
+            assert_eq!(
+                canonicalize_nth_element(&large, "", "a", 0, options),
+                expected
+            );
+        }
+
+        let options = CanonicalizationOptions {
+            max_output_bytes: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            canonicalize_xml("<a/>", options.clone()),
+            Err(CanonicalizationError::OutputTooLarge { limit: 0 })
+        );
+        assert!(matches!(
+            canonicalize_xml("<a>&e;</a>", options),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    fn utf16(document: &str, little_endian: bool, bom: bool) -> Vec<u8> {
+        let document = if bom {
+            format!("\u{feff}{document}")
+        } else {
+            document.to_owned()
+        };
+        document
+            .encode_utf16()
+            .flat_map(|unit| {
+                if little_endian {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn utf16_input() {
+        let input = include_str!("samples/canonical_1_1/3_1_input.xml");
+        let expected = include_str!("samples/canonical_1_1/3_1_output.xml");
+        let options = CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_1,
+            keep_comments: true,
+            ..Default::default()
+        };
+
+        for little_endian in [true, false] {
+            let with_bom = utf16(input, little_endian, true);
+            let canonicalized = canonicalize_xml_bytes(&with_bom, None, options.clone()).unwrap();
+            assert_eq!(canonicalized, expected);
+
+            let encoding = if little_endian {
+                "UTF-16LE"
+            } else {
+                "UTF-16BE"
+            };
+            let without_bom = utf16(input, little_endian, false);
+            let canonicalized =
+                canonicalize_xml_bytes(&without_bom, Some(encoding), options.clone()).unwrap();
+            assert_eq!(canonicalized, expected);
+        }
+    }
+
+    #[test]
+    fn forced_encoding() {
+        let input = include_bytes!("samples/encoding/mislabeled_latin1_input.xml");
+        let expected = include_str!("samples/encoding/mislabeled_latin1_output.xml");
+
+        // as declared, it's invalid UTF-8
+        assert!(matches!(
+            canonicalize_xml_bytes(input, None, Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+        let canonicalized =
+            canonicalize_xml_bytes(input, Some("ISO-8859-1"), Default::default()).unwrap();
+        assert_eq!(canonicalized, expected);
+
+        // forcing the wrong encoding on a correctly declared document silently corrupts it
+        let canonicalized = canonicalize_xml_bytes(
+            "<a>é</a>".as_bytes(),
+            Some("ISO-8859-1"),
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(canonicalized, "<a>Ã©</a>");
+    }
+
+    #[test]
+    fn unknown_encoding() {
+        let canonicalized = canonicalize_xml_bytes(
+            b"<hi/>",
+            Some("no-such-encoding"),
+            CanonicalizationOptions::default(),
+        );
+        assert_eq!(
+            canonicalized,
+            Err(CanonicalizationError::UnknownEncoding(
+                "no-such-encoding".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn embedded_nul() {
+        for (document, encoding) in [
+            (&b"<a>x\0y</a>"[..], None),
+            (b"<a b='\0'/>", None),
+            (b"<a/>\0", None),
+            (b"<a>x\0y</a>", Some("ISO-8859-1")),
+        ] {
+            assert!(
+                matches!(
+                    canonicalize_xml_bytes(document, encoding, Default::default()),
+                    Err(CanonicalizationError::Parse(_))
+                ),
+                "{document:?}"
+            );
+        }
+        assert!(matches!(
+            canonicalize_xml("<a>\0</a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+
+        // libxml2 would stop at one after the document element, ignoring the rest
+        let Err(CanonicalizationError::Parse(error)) =
+            canonicalize_xml_bytes(b"<a/>\0<b/>", None, Default::default())
+        else {
+            panic!("NUL character was accepted");
+        };
+        assert_eq!(error.message, "NUL character at byte 4");
+        assert!(matches!(
+            canonicalize_xml_bytes(&utf16("<a/>\0", true, true), None, Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+
+        // NUL bytes of UTF-16 code units are fine, but not in the encoding name
+        let utf16_bom = utf16("<a>x</a>", true, true);
+        let utf16 = utf16("<a>x</a>", true, false);
+        assert_eq!(
+            canonicalize_xml_bytes(&utf16, Some("UTF-16LE"), Default::default()).unwrap(),
+            "<a>x</a>"
+        );
+        for alias in ["ISO-10646-UCS-2", "UNICODE", "csUnicode"] {
+            assert_eq!(
+                canonicalize_xml_bytes(&utf16_bom, Some(alias), Default::default()).unwrap(),
+                "<a>x</a>",
+                "{alias}"
+            );
+        }
+        assert_eq!(
+            canonicalize_xml_bytes(&utf16, Some("UTF-16LE\0"), Default::default()),
+            Err(CanonicalizationError::UnknownEncoding(
+                "UTF-16LE\0".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn diagnostics_for_recoverable_warning() {
+        let input = "<doc>\n  <a xml:space='retain'/>\n</doc>";
+        let (canonicalized, diagnostics) =
+            canonicalize_xml_with_diagnostics(input, CanonicalizationOptions::default()).unwrap();
+
+        assert_eq!(
+            canonicalized,
+            "<doc>\n  <a xml:space=\"retain\"></a>\n</doc>"
+        );
+        let [diagnostic] = diagnostics.as_slice() else {
+            panic!("expected a single diagnostic, got {diagnostics:?}");
+        };
+        assert_eq!(diagnostic.level, DiagnosticLevel::Warning);
+        // XML_WAR_SPACE_VALUE
+        assert_eq!(diagnostic.code, 102);
+        assert_eq!(
+            diagnostic.message,
+            r#"Invalid value "retain" for xml:space : "default" or "preserve" expected"#
+        );
+        assert_eq!(diagnostic.line, Some(2));
+        // exact column differs between libxml2 versions
+        assert!(diagnostic.column.is_some());
+    }
+
+    #[test]
+    fn nth_element() {
+        let input = r#"<samlp:Response xmlns:samlp="urn:samlp" xmlns:other="urn:other">
+            <samlp:Assertion ID="1"/>
+            <x:Assertion xmlns:x="urn:samlp" ID="2"><!-- c --></x:Assertion>
+            <Assertion ID="3"/>
+        </samlp:Response>"#;
+
+        let canonicalized = canonicalize_nth_element(
+            input,
+            "urn:samlp",
+            "Assertion",
+            1,
+            CanonicalizationOptions {
+                mode: CanonicalizationMode::Canonical1_1,
+                keep_comments: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            canonicalized,
+            r#"<x:Assertion xmlns:other="urn:other" xmlns:samlp="urn:samlp" xmlns:x="urn:samlp" ID="2"><!-- c --></x:Assertion>"#
+        );
+
+        let canonicalized = canonicalize_nth_element(
+            input,
+            "",
+            "Assertion",
+            0,
+            CanonicalizationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(canonicalized, r#"<Assertion ID="3"></Assertion>"#);
+    }
+
+    #[test]
+    fn by_ids() {
+        let input = r#"<r xmlns:x="urn:x">
+            <x:a ID="a"><b Id="b"/></x:a>
+            <c xml:id="c"><!-- c --></c>
+            <d id="d"/>
+        </r>"#;
+        let options = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
+
+        let canonicalized = canonicalize_by_ids(input, &["d", "b", "c"], options.clone()).unwrap();
+        assert_eq!(
+            canonicalized,
+            r#"<b Id="b"></b><c xml:id="c"><!-- c --></c><d id="d"></d>"#
+        );
+
+        // the same node is never output twice
+        for ids in [&["a", "b"][..], &["b", "a"], &["a", "a"]] {
+            let canonicalized = canonicalize_by_ids(input, ids, options.clone()).unwrap();
+            assert_eq!(
+                canonicalized, r#"<x:a xmlns:x="urn:x" ID="a"><b Id="b"></b></x:a>"#,
+                "{ids:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn selected_in_reverse_order() {
+        let input =
+            r#"<r><a ID="1"><!-- a --></a><b ID="2"><c ID="3"><!-- c --></c></b><d ID="4"/></r>"#;
+        let options = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            canonicalize_by_ids(input, &["4", "3", "1"], options).unwrap(),
+            r#"<a ID="1"><!-- a --></a><c ID="3"><!-- c --></c><d ID="4"></d>"#
+        );
+
+        // reverse axes select nodes from the last one backwards
+        let options = CanonicalizationOptions {
+            keep_comments_under: Some("//d/preceding::*[@ID]".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            canonicalize_by_ids(input, &["4", "3", "1"], options).unwrap(),
+            r#"<a ID="1"><!-- a --></a><c ID="3"><!-- c --></c><d ID="4"></d>"#
+        );
+    }
+
+    #[test]
+    fn where_attr() {
+        let input = r#"<r xmlns:x="urn:x">
+            <a name="one" x:name="two"><b name="t&#119;o"/></a>
+            <c name="three"/><d name="three"/>
+        </r>"#;
+
+        assert_eq!(
+            canonicalize_where_attr(input, "name", "two", Default::default()).unwrap(),
+            r#"<b name="two"></b>"#
+        );
+        assert_eq!(
+            canonicalize_where_attr(input, "name", "one", Default::default()).unwrap(),
+            r#"<a xmlns:x="urn:x" name="one" x:name="two"><b name="two"></b></a>"#
+        );
+
+        assert_eq!(
+            canonicalize_where_attr(input, "name", "four", Default::default()),
+            Err(CanonicalizationError::NoAttributeMatch {
+                name: "name".to_owned(),
+                value: "four".to_owned()
+            })
+        );
+        // the namespace is part of the name
+        assert!(matches!(
+            canonicalize_where_attr(input, "x:name", "two", Default::default()),
+            Err(CanonicalizationError::NoAttributeMatch { .. })
+        ));
+        assert_eq!(
+            canonicalize_where_attr(input, "name", "three", Default::default()),
+            Err(CanonicalizationError::AmbiguousAttributeMatch {
+                name: "name".to_owned(),
+                value: "three".to_owned(),
+                count: 2
+            })
+        );
+    }
+
+    #[test]
+    fn by_ids_errors() {
+        let input = r#"<r><a ID="a"/><b ID="dup"/><c ID="dup"/></r>"#;
+
+        assert_eq!(
+            canonicalize_by_ids(input, &["a", "missing"], CanonicalizationOptions::default()),
+            Err(CanonicalizationError::MissingId("missing".to_owned()))
+        );
+        assert_eq!(
+            canonicalize_by_ids(input, &["a", "dup"], CanonicalizationOptions::default()),
+            Err(CanonicalizationError::DuplicateId("dup".to_owned()))
+        );
+    }
+
+    #[test]
+    fn with_ids() {
+        let input = r#"<r xmlns:x="urn:x">
+            <x:a ID="a"><b Id="b"/></x:a>
+            <c xml:id="c"><!-- c --></c>
+        </r>"#;
+        let options = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
+
+        let (canonicalized, subtrees) =
+            canonicalize_with_ids(input, &["c", "a", "b", "c"], options.clone()).unwrap();
+        assert_eq!(
+            canonicalized,
+            canonicalize_xml(input, options.clone()).unwrap()
+        );
+        assert_eq!(subtrees.len(), 3);
+        for (id, canonical) in &subtrees {
+            assert_eq!(
+                canonical,
+                &canonicalize_by_ids(input, &[id], options.clone()).unwrap()
+            );
+        }
+        assert_eq!(subtrees["b"], r#"<b Id="b"></b>"#);
+
+        assert_eq!(
+            canonicalize_with_ids(input, &["a", "missing"], options),
+            Err(CanonicalizationError::MissingId("missing".to_owned()))
+        );
+    }
+
+    #[test]
+    fn in_context() {
+        let fragment = "<x:a y:b='1'><c/></x:a>\n<!-- comment --><d>text</d>";
+        let context = [
+            ("x", "urn:x"),
+            ("y", "urn:y"),
+            ("", "urn:default"),
+            ("unused", "urn:unused"),
+        ];
+
+        let exclusive = canonicalize_in_context(fragment, &context, Default::default()).unwrap();
+        assert_eq!(
+            exclusive,
+            r#"<x:a xmlns:x="urn:x" xmlns:y="urn:y" y:b="1"><c xmlns="urn:default"></c></x:a>
+<d xmlns="urn:default">text</d>"#
+        );
+
+        let options = CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_1,
+            keep_comments: true,
+            ..Default::default()
+        };
+        // libxml2 separates top-level comments from what precedes them with a line feed
+        let inclusive = canonicalize_in_context(fragment, &context, options).unwrap();
+        assert_eq!(
+            inclusive,
+            r#"<x:a xmlns="urn:default" xmlns:unused="urn:unused" xmlns:x="urn:x" xmlns:y="urn:y" y:b="1"><c></c></x:a>
+
+<!-- comment --><d xmlns="urn:default" xmlns:unused="urn:unused" xmlns:x="urn:x" xmlns:y="urn:y">text</d>"#
+        );
+
+        assert_eq!(
+            canonicalize_in_context("<a/>", &[("x:y", "urn:x")], Default::default()),
+            Err(CanonicalizationError::InvalidNamespacePrefix(
+                "x:y".to_owned()
+            ))
+        );
+        let Err(CanonicalizationError::Parse(error)) =
+            canonicalize_in_context("<a>", &context, Default::default())
+        else {
+            panic!("unclosed element was accepted");
+        };
+        assert_eq!(error.line, Some(1));
+    }
+
+    #[test]
+    fn root_adjacent_comments() {
+        let input = include_str!("samples/root_level/2_input.xml");
+        for mode in CanonicalizationMode::ALL {
+            for (keep_comments, expected) in [
+                (true, include_str!("samples/root_level/2_output.xml")),
+                (
+                    false,
+                    include_str!("samples/root_level/2_output_no_comment.xml"),
+                ),
+            ] {
+                let options = CanonicalizationOptions {
+                    mode,
+                    keep_comments,
+                    ..Default::default()
+                };
+                // compared byte for byte, the fixtures don't end with a newline either
+                let canonicalized = canonicalize_xml(input, options.clone()).unwrap();
+                assert_eq!(canonicalized.as_bytes(), expected.as_bytes(), "{options:?}");
+                assert_eq!(
+                    canonicalize_raw(input, options).unwrap().as_bytes(),
+                    expected.as_bytes()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn wsu_id() {
+        let input = include_str!("samples/wss/1_input.xml");
+        for (id, expected) in [
+            ("Body-1", include_str!("samples/wss/1_body_output.xml")),
+            ("TS-1", include_str!("samples/wss/1_timestamp_output.xml")),
+        ] {
+            assert_eq!(
+                canonicalize_wsu_id(input, id, CanonicalizationOptions::default()).unwrap(),
+                expected
+            );
+        }
+
+        // an unqualified Id is not a wsu:Id
+        assert_eq!(
+            canonicalize_wsu_id(input, "not-a-wsu-id", Default::default()),
+            Err(CanonicalizationError::MissingId("not-a-wsu-id".to_owned()))
+        );
+
+        let duplicated = input.replace(r#"wsu:Id="TS-1""#, r#"wsu:Id="Body-1""#);
+        assert_eq!(
+            canonicalize_wsu_id(&duplicated, "Body-1", Default::default()),
+            Err(CanonicalizationError::DuplicateId("Body-1".to_owned()))
+        );
+
+        let inclusive = CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            canonicalize_wsu_id(input, "Body-1", inclusive),
+            Err(CanonicalizationError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn signed_info_exclusive() {
+        let input = include_str!("samples/signed_info/1_input.xml");
+        let expected = include_str!("samples/signed_info/1_output_exclusive.xml");
+
+        let canonicalized =
+            canonicalize_signed_info(input, CanonicalizationOptions::default()).unwrap();
+        assert_eq!(canonicalized, expected)
+    }
+
+    #[test]
+    fn signed_info_inclusive() {
+        let input = include_str!("samples/signed_info/1_input.xml");
+        let expected = include_str!("samples/signed_info/1_output_inclusive.xml");
+
+        for mode in [
+            CanonicalizationMode::Canonical1_0,
+            CanonicalizationMode::Canonical1_1,
+        ] {
+            let canonicalized = canonicalize_signed_info(
+                input,
+                CanonicalizationOptions {
+                    mode,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(canonicalized, expected)
+        }
+    }
+
+    #[test]
+    fn signed_info_missing() {
+        // right name, wrong namespace
+        let canonicalized = canonicalize_signed_info(
+            "<Signature><SignedInfo/></Signature>",
+            CanonicalizationOptions::default(),
+        );
+        assert_eq!(
+            canonicalized,
+            Err(CanonicalizationError::ElementNotFound {
+                namespace_uri: DSIG_NAMESPACE.to_owned(),
+                local_name: "SignedInfo".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn nth_element_out_of_range() {
+        let canonicalized = canonicalize_nth_element(
+            "<a><b/><b/></a>",
+            "",
+            "b",
+            2,
+            CanonicalizationOptions::default(),
+        );
+        assert_eq!(
+            canonicalized,
+            Err(CanonicalizationError::ElementIndexOutOfRange { index: 2, count: 2 })
+        );
+    }
+
+    #[test]
+    fn relative_namespace_uri() {
+        // C14N rejects relative namespace URIs, which libxml2 only warns about when parsing
+        for mode in CanonicalizationMode::ALL {
+            let options = CanonicalizationOptions {
+                mode,
+                ..Default::default()
+            };
+            assert_eq!(
+                canonicalize_xml(r#"<a xmlns="relative"/>"#, options.clone()),
+                Err(CanonicalizationError::RelativeNamespaceUri {
+                    uri: "relative".to_owned(),
+                    line: Some(1)
+                })
+            );
+            // even if it's unused, and on an element outside the subset
+            assert_eq!(
+                canonicalize_nth_element(
+                    "<a>\n<b xmlns:x='#x'/><c/></a>",
+                    "",
+                    "c",
+                    0,
+                    options.clone()
+                ),
+                Err(CanonicalizationError::RelativeNamespaceUri {
+                    uri: "#x".to_owned(),
+                    line: Some(2)
+                })
+            );
+        }
+        assert_eq!(
+            CanonicalizationError::RelativeNamespaceUri {
+                uri: "relative".to_owned(),
+                line: Some(3)
+            }
+            .to_string(),
+            "relative namespace URI `relative` can't be canonicalized on line 3"
+        );
+        assert!(canonicalize_xml(r#"<a xmlns="urn:absolute"/>"#, Default::default()).is_ok());
+    }
+
+    #[test]
+    fn invalid_namespace_uri() {
+        for uri in ["urn:a b", "http://[x"] {
+            assert_eq!(
+                canonicalize_xml(format!(r#"<a xmlns:p="{uri}"/>"#), Default::default()),
+                Err(CanonicalizationError::InvalidNamespaceUri {
+                    uri: uri.to_owned(),
+                    line: Some(1)
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn unexpanded_entity_reference() {
+        let document = "<!DOCTYPE a [<!ENTITY e 'x'>]>\n<a>\n<b>&e;</b></a>";
+        assert_eq!(
+            canonicalize_xml(document, Default::default()),
+            Err(CanonicalizationError::UnexpandedEntityReference {
+                name: "e".to_owned(),
+                line: Some(3)
+            })
+        );
+        assert_eq!(
+            canonicalize_xml(
+                document,
+                CanonicalizationOptions {
+                    coalesce_text: true,
+                    ..Default::default()
+                }
+            ),
+            Ok("<a>\n<b>x</b></a>".to_owned())
+        );
+        // entities in attribute values are always expanded
+        assert_eq!(
+            canonicalize_xml(
+                "<!DOCTYPE a [<!ENTITY e 'x'>]><a b='&e;'/>",
+                Default::default()
+            ),
+            Ok(r#"<a b="x"></a>"#.to_owned())
+        );
+    }
+
+    #[test]
+    fn reject_comments() {
+        let options = CanonicalizationOptions {
+            reject_comments: true,
+            ..Default::default()
+        };
+
+        let without_comments = r#"<a><b xmlns="urn:b">text<?pi?></b></a>"#;
+        assert_eq!(
+            canonicalize_xml(without_comments, options.clone()).unwrap(),
+            r#"<a><b xmlns="urn:b">text<?pi?></b></a>"#
+        );
+
+        // even when comments would be dropped anyway, and even outside the document element
+        for input in ["<a>\n<b><!-- c --></b></a>", "<a/>\n<!-- c -->"] {
+            for keep_comments in [true, false] {
+                let options = CanonicalizationOptions {
+                    keep_comments,
+                    ..options.clone()
+                };
+                assert_eq!(
+                    canonicalize_xml(input, options),
+                    Err(CanonicalizationError::CommentRejected { line: Some(2) })
+                );
+            }
+        }
+
+        // only the selected subset matters
+        let input = r#"<a><!-- c --><b xmlns="urn:b"/><b xmlns="urn:b"><!-- c --></b></a>"#;
+        assert_eq!(
+            canonicalize_nth_element(input, "urn:b", "b", 0, options.clone()).unwrap(),
+            r#"<b xmlns="urn:b"></b>"#
+        );
+        assert!(matches!(
+            canonicalize_nth_element(input, "urn:b", "b", 1, options.clone()),
+            Err(CanonicalizationError::CommentRejected { .. })
+        ));
+
+        // the first one in document order, whatever the order of the IDs
+        let input =
+            "<a>\n<b ID='x'><!-- c --></b>\n<b ID='y'><!-- c --></b>\n<b ID='z'><!-- c --></b></a>";
+        for _ in 0..20 {
+            assert_eq!(
+                canonicalize_by_ids(input, &["z", "y", "x"], options.clone()),
+                Err(CanonicalizationError::CommentRejected { line: Some(2) })
+            );
+        }
+    }
+
+    #[test]
+    fn reject_duplicate_ids() {
+        let options = CanonicalizationOptions {
+            reject_duplicate_ids: true,
+            extra_id_attributes: vec![QName {
+                namespace_uri: WSU_NAMESPACE.to_owned(),
+                local_name: "Id".to_owned(),
+            }],
+            ..Default::default()
+        };
+        let input = |second_id: &str| {
+            format!(
+                r#"<!DOCTYPE r [<!ATTLIST d key ID #IMPLIED>]>
+<r xmlns:wsu="{WSU_NAMESPACE}"><a ID="1"/><b xml:id="2"/><c wsu:Id="3"/><d key="4"/><e {second_id}/></r>"#
+            )
+        };
+
+        assert!(canonicalize_xml(input(r#"ref="1""#), options.clone()).is_ok());
+        for duplicate in [r#"Id="1""#, r#"xml:id="2""#, r#"wsu:Id="3""#, r#"ID="4""#] {
+            assert_eq!(
+                canonicalize_xml(input(duplicate), options.clone()),
+                Err(CanonicalizationError::DuplicateId(
+                    duplicate[duplicate.len() - 2..duplicate.len() - 1].to_owned()
+                )),
+                "{duplicate}"
+            );
+        }
+
+        // the duplicate is outside the referenced subtree, which canonicalize_by_ids alone doesn't check
+        let wrapped = input(r#"wsu:Id="3""#);
+        assert!(canonicalize_by_ids(&wrapped, &["1"], Default::default()).is_ok());
+        assert_eq!(
+            canonicalize_by_ids(&wrapped, &["1"], options.clone()),
+            Err(CanonicalizationError::DuplicateId("3".to_owned()))
+        );
+
+        // wsu:Id is only an ID if listed
+        let listed_only = CanonicalizationOptions {
+            extra_id_attributes: vec![],
+            ..options
+        };
+        assert!(canonicalize_xml(&wrapped, listed_only).is_ok());
+    }
+
+    #[test]
+    fn reject_prefix_redefinition() {
+        let options = CanonicalizationOptions {
+            reject_prefix_redefinition: true,
+            ..Default::default()
+        };
+
+        let allowed = r#"<a xmlns:x="urn:x" xmlns="urn:a"><b xmlns:x="urn:x" xmlns="urn:b"><c xmlns:y="urn:y"/></b><d xmlns:y="urn:other"/></a>"#;
+        assert_eq!(
+            canonicalize_xml(allowed, options.clone()),
+            canonicalize_xml(allowed, Default::default())
+        );
+
+        let redefined = "<a xmlns:x='urn:x'>\n  <b>\n    <c xmlns:x='urn:other'/>\n  </b>\n</a>";
+        assert_eq!(
+            canonicalize_xml(redefined, options.clone()),
+            Err(CanonicalizationError::PrefixRedefined {
+                prefix: "x".to_owned(),
+                line: Some(3)
+            })
+        );
+        // the binding on an ancestor of the subset counts too
+        assert!(matches!(
+            canonicalize_nth_element(redefined, "", "c", 0, options.clone()),
+            Err(CanonicalizationError::PrefixRedefined { .. })
+        ));
+        assert!(canonicalize_xml(redefined, Default::default()).is_ok());
+    }
+
+    #[test]
+    fn require_element_only() {
+        let options = CanonicalizationOptions {
+            require_element_only: true,
+            ..Default::default()
+        };
+
+        for allowed in [
+            "<a>\n  <b>text</b>\r\n\t<c/>\n</a>",
+            "<a>text &amp; more</a>",
+            "<a><b/><![CDATA[  ]]></a>",
+        ] {
+            assert_eq!(
+                canonicalize_xml(allowed, options.clone()),
+                canonicalize_xml(allowed, Default::default()),
+                "{allowed}"
+            );
+        }
+
+        let input =
+            "<r xmlns:x='urn:x'>\n<x:a><b/>\n<x:c>text<d/></x:c></x:a>\n<e>&#160;<f/></e></r>";
+        assert_eq!(
+            canonicalize_xml(input, options.clone()),
+            Err(CanonicalizationError::MixedContent {
+                element: "x:c".to_owned(),
+                line: Some(3)
+            })
+        );
+        assert_eq!(
+            canonicalize_xml(input, options.clone())
+                .unwrap_err()
+                .to_string(),
+            "element `x:c` has mixed content on line 3"
+        );
+        // a non-breaking space isn't whitespace to XML
+        assert_eq!(
+            canonicalize_nth_element(input, "", "e", 0, options.clone()),
+            Err(CanonicalizationError::MixedContent {
+                element: "e".to_owned(),
+                line: Some(4)
+            })
+        );
+        // only the subset is checked
+        assert!(canonicalize_nth_element(input, "", "b", 0, options).is_ok());
+    }
+
+    #[test]
+    fn reject_control_characters() {
+        let options = CanonicalizationOptions {
+            reject_control_characters: true,
+            ..Default::default()
+        };
+
+        let allowed = "<a b='\t&#xA;'>\t\r\n&#xD;\u{a0}</a>";
+        assert_eq!(
+            canonicalize_xml(allowed, options.clone()),
+            canonicalize_xml(allowed, Default::default())
+        );
+
+        for (input, character, line) in [
+            ("<a>\n<b>\u{7f}</b></a>", '\u{7f}', 2),
+            ("<a>\n<b c='\u{85}'/></a>", '\u{85}', 2),
+            ("<a><![CDATA[\u{9f}]]></a>", '\u{9f}', 1),
+        ] {
+            assert!(
+                canonicalize_xml(input, Default::default()).is_ok(),
+                "{input}"
+            );
+            assert_eq!(
+                canonicalize_xml(input, options.clone()),
+                Err(CanonicalizationError::ControlCharacterRejected {
+                    character,
+                    line: Some(line)
+                })
+            );
+        }
+        assert_eq!(
+            canonicalize_xml("<a>\u{85}</a>", options.clone())
+                .unwrap_err()
+                .to_string(),
+            "control character U+0085 found on line 1"
+        );
+
+        // a form feed isn't even allowed by XML 1.0, with or without the option
+        for options in [options.clone(), Default::default()] {
+            for input in ["<a>\u{c}</a>", "<a>&#xC;</a>", "<a b='&#xC;'/>"] {
+                assert!(matches!(
+                    canonicalize_xml(input, options.clone()),
+                    Err(CanonicalizationError::Parse(_))
+                ));
+            }
+        }
 
-        let canonicalized = canonicalize_xml(
-            input,
+        // only the selected subset matters
+        let input = "<a><b/><b>\u{85}</b></a>";
+        assert!(canonicalize_nth_element(input, "", "b", 0, options.clone()).is_ok());
+        assert!(canonicalize_nth_element(input, "", "b", 1, options).is_err());
+    }
+
+    #[test]
+    fn raw_output() {
+        let input = r#"<a xmlns:z="urn:z" xmlns:b="urn:b"><z:c b:d="1"/></a>"#;
+        for options in [
+            CanonicalizationOptions::default(),
             CanonicalizationOptions {
                 mode: CanonicalizationMode::Canonical1_1,
-                keep_comments: false,
-                inclusive_ns_prefixes: vec![],
+                verify_output: true,
+                ..Default::default()
             },
-        )
-        .unwrap();
-        assert_eq!(canonicalized, expected)
+            CanonicalizationOptions {
+                mode: CanonicalizationMode::Canonical1_0,
+                non_conformant: NonConformantOptions {
+                    namespace_document_order: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ] {
+            let raw = canonicalize_raw(input, options.clone()).unwrap();
+            let expected = canonicalize_xml(input, options).unwrap();
+            assert_eq!(raw.as_bytes(), expected.as_bytes());
+            assert_eq!(raw.len(), expected.len());
+            assert_eq!(
+                unsafe { CStr::from_ptr(raw.as_ptr() as *const c_char) }.to_bytes(),
+                expected.as_bytes()
+            );
+        }
+
+        assert!(matches!(
+            canonicalize_raw("<a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
     }
 
     #[test]
-    fn canonical_1_1_example_3_1() {
+    fn into_vec() {
+        let mut out = Vec::with_capacity(1024);
+        out.extend_from_slice(b"stale");
+        let buffer = out.as_ptr();
+
+        for (input, expected) in [("<a/>", "<a></a>"), ("<b c='d'>e</b>", r#"<b c="d">e</b>"#)] {
+            canonicalize_into_vec(input, Default::default(), &mut out).unwrap();
+            assert_eq!(out, expected.as_bytes());
+            // not reallocated
+            assert_eq!(out.as_ptr(), buffer);
+            assert_eq!(out.capacity(), 1024);
+        }
+
+        assert!(canonicalize_into_vec("<a>", Default::default(), &mut out).is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn checked() {
+        let input = include_str!("samples/canonical_exclusive/1_input.xml");
+        for mode in CanonicalizationMode::ALL {
+            for keep_comments in [true, false] {
+                let options = CanonicalizationOptions {
+                    mode,
+                    keep_comments,
+                    ..Default::default()
+                };
+                assert_eq!(
+                    canonicalize_checked(input, options.clone()).unwrap(),
+                    canonicalize_xml(input, options).unwrap()
+                );
+            }
+        }
+
+        let mut options = CanonicalizationOptions::default();
+        options.non_conformant.comment_crlf = true;
+        assert!(matches!(
+            canonicalize_checked(input, options),
+            Err(CanonicalizationError::InvalidOptions(_))
+        ));
+        assert!(matches!(
+            canonicalize_checked("<a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+
+        assert_eq!(first_difference("<a></a>", "<a></a>"), None);
+        assert_eq!(first_difference("<a></a>", "<b></b>"), Some(1));
+        assert_eq!(first_difference("<a></a>", "<a></a>\n"), Some(7));
+        assert_eq!(first_difference("", "x"), Some(0));
+    }
+
+    #[test]
+    fn fingerprint() {
+        let fingerprint =
+            |document: &str| structural_fingerprint(document, Default::default()).unwrap();
+        let base =
+            fingerprint(r#"<a xmlns:x="urn:x"><x:b c="1">text</x:b><!-- comment --><d/></a>"#);
+
+        for same in [
+            r#"<a xmlns:x="urn:other"><x:b>other text</x:b><?pi?><d e="f"/></a>"#,
+            "<!DOCTYPE a><a xmlns:x='urn:x'>\n<x:b>\n</x:b><d></d></a>",
+        ] {
+            assert_eq!(fingerprint(same), base, "{same}");
+        }
+        for different in [
+            r#"<a xmlns:y="urn:x"><y:b/><d/></a>"#,
+            r#"<a xmlns:x="urn:x"><x:b><d/></x:b></a>"#,
+            r#"<a xmlns:x="urn:x"><d/><x:b/></a>"#,
+            r#"<a xmlns:x="urn:x"><x:b/></a>"#,
+        ] {
+            assert_ne!(fingerprint(different), base, "{different}");
+        }
+
+        assert_eq!(fingerprint("<a/>"), fnv1a(b"<a</a"));
+        assert!(matches!(
+            structural_fingerprint("<a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn hash() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+
+        // documents with the same canonical form have the same hash
+        let (first, first_hash) =
+            canonicalize_with_hash("<a b='1'  c='2'/>", Default::default()).unwrap();
+        let (second, second_hash) =
+            canonicalize_with_hash(r#"<a c="2" b="1"></a>"#, Default::default()).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first_hash, second_hash);
+        assert_eq!(first_hash, fnv1a(first.as_bytes()));
+
+        let (_, other_hash) = canonicalize_with_hash("<a b='1'/>", Default::default()).unwrap();
+        assert_ne!(other_hash, first_hash);
+    }
+
+    #[test]
+    fn canonical_length_matches() {
         let input = include_str!("samples/canonical_1_1/3_1_input.xml");
-        let expected = include_str!("samples/canonical_1_1/3_1_output.xml");
 
-        let canonicalized = canonicalize_xml(
-            input,
-            CanonicalizationOptions {
-                mode: CanonicalizationMode::Canonical1_1,
-                keep_comments: true,
-                inclusive_ns_prefixes: vec![],
-            },
+        for mode in CanonicalizationMode::ALL {
+            for keep_comments in [true, false] {
+                let options = CanonicalizationOptions {
+                    mode,
+                    keep_comments,
+                    ..Default::default()
+                };
+                assert_eq!(
+                    canonical_length(input, options.clone()).unwrap(),
+                    canonicalize_xml(input, options).unwrap().len()
+                );
+            }
+        }
+
+        // post-processing changes the length
+        let input = r#"<a xml:lang="en"/>"#;
+        let mut options = CanonicalizationOptions::default();
+        options.non_conformant.declare_xml_namespace = true;
+        assert_eq!(
+            canonical_length(input, options.clone()).unwrap(),
+            canonicalize_xml(input, options).unwrap().len()
+        );
+
+        // counting still applies the output limit
+        let options = CanonicalizationOptions {
+            max_output_bytes: Some(8),
+            ..Default::default()
+        };
+        assert_eq!(canonical_length("<a></a>", options.clone()), Ok(7));
+        assert_eq!(
+            canonical_length("<ab></ab>", options),
+            Err(CanonicalizationError::OutputTooLarge { limit: 8 })
+        );
+
+        assert!(matches!(
+            canonical_length("<a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn reporting_comments() {
+        for keep_comments in [true, false] {
+            let options = CanonicalizationOptions {
+                keep_comments,
+                ..Default::default()
+            };
+
+            let (canonicalized, had_comments) =
+                canonicalize_reporting_comments("<a><b>text</b><?pi?></a>", options.clone())
+                    .unwrap();
+            assert_eq!(canonicalized, "<a><b>text</b><?pi?></a>");
+            assert!(!had_comments);
+
+            for input in ["<a><b><!-- c --></b></a>", "<!-- c --><a><b></b></a>"] {
+                let (canonicalized, had_comments) =
+                    canonicalize_reporting_comments(input, options.clone()).unwrap();
+                assert_eq!(
+                    canonicalized,
+                    canonicalize_xml(input, options.clone()).unwrap()
+                );
+                assert!(had_comments);
+            }
+        }
+    }
+
+    #[test]
+    fn dtd_validated() {
+        let canonicalized = canonicalize_dtd_validated(
+            include_str!("samples/dtd_validation/valid_input.xml"),
+            Default::default(),
         )
         .unwrap();
-        assert_eq!(canonicalized, expected)
+        assert_eq!(
+            canonicalized,
+            include_str!("samples/dtd_validation/valid_output.xml")
+        );
+
+        let Err(CanonicalizationError::DtdInvalid(diagnostics)) = canonicalize_dtd_validated(
+            include_str!("samples/dtd_validation/invalid_input.xml"),
+            Default::default(),
+        ) else {
+            panic!("invalid document accepted");
+        };
+        let lines: Vec<Option<u32>> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.line)
+            .collect();
+        assert_eq!(lines, [Some(9), Some(10), Some(11)]);
+        assert!(diagnostics[1].message.contains("note"), "{diagnostics:?}");
+
+        // no DTD to validate against
+        assert!(matches!(
+            canonicalize_dtd_validated("<a/>", Default::default()),
+            Err(CanonicalizationError::DtdInvalid(diagnostics)) if diagnostics.len() == 1
+        ));
+        assert!(matches!(
+            canonicalize_dtd_validated("<a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
     }
 
+    #[cfg(feature = "serde_json")]
     #[test]
-    fn canonical_1_1_example_3_2() {
-        let input = include_str!("samples/canonical_1_1/3_2_input.xml");
-        let expected = include_str!("samples/canonical_1_1/3_2_output.xml");
+    fn json_escaped() {
+        let input = "<a b='\"'>\t\"\\&#xD;<!-- \u{2028} --></a>";
+        let options = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
 
-        let canonicalized = canonicalize_xml(
-            input,
-            CanonicalizationOptions {
-                mode: CanonicalizationMode::Canonical1_1,
-                keep_comments: true,
-                inclusive_ns_prefixes: vec![],
-            },
-        )
+        let escaped = canonicalize_json_escaped(input, options.clone()).unwrap();
+        assert!(!escaped.contains(['\t', '\n']), "{escaped}");
+        assert_eq!(
+            serde_json::from_str::<String>(&escaped).unwrap(),
+            canonicalize_xml(input, options).unwrap()
+        );
+
+        assert!(matches!(
+            canonicalize_json_escaped("<a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn attributes() {
+        let document = r#"<r xml:lang="en" xmlns:z="urn:a" xmlns:a="urn:z"><e z:x="1" a:x="2" xmlns="urn:d" c="&#xA;" b="&lt;&amp;>"/></r>"#;
+        let attributes = |mode| {
+            canonical_attributes(
+                document,
+                "urn:d",
+                "e",
+                0,
+                CanonicalizationOptions {
+                    mode,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        // qualified attributes are sorted by namespace URI, not prefix
+        let expected = [
+            ("b", "&lt;&amp;>"),
+            ("c", "&#xA;"),
+            ("z:x", "1"),
+            ("a:x", "2"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        assert_eq!(
+            attributes(CanonicalizationMode::ExclusiveCanonical1_0),
+            expected
+        );
+
+        // the xml namespace URI sorts before the others
+        let mut inherited = expected.to_vec();
+        inherited.insert(2, ("xml:lang".to_owned(), "en".to_owned()));
+        assert_eq!(attributes(CanonicalizationMode::Canonical1_0), inherited);
+
+        assert_eq!(
+            canonical_attributes("<a/>", "", "a", 0, Default::default()),
+            Ok(vec![])
+        );
+        assert_eq!(
+            canonical_attributes("<a/>", "", "a", 1, Default::default()),
+            Err(CanonicalizationError::ElementIndexOutOfRange { index: 1, count: 1 })
+        );
+    }
+
+    #[test]
+    fn transform() {
+        let input = r#"<a xmlns:t="urn:t" Id="a"><timestamp><timestamp/></timestamp><t:timestamp/><b Id="b" t:Id="c">text</b></a>"#;
+
+        let mut removed = (0, 0);
+        let canonicalized = canonicalize_with_transform(input, Default::default(), |document| {
+            // nested matches are counted, but only freed once
+            removed = (
+                document.remove_elements("", "timestamp"),
+                document.remove_attributes("", "Id"),
+            );
+        })
         .unwrap();
+        assert_eq!(removed, (2, 2));
+        assert_eq!(
+            canonicalized,
+            r#"<a><t:timestamp xmlns:t="urn:t"></t:timestamp><b xmlns:t="urn:t" t:Id="c">text</b></a>"#
+        );
 
-        // for some reason, we get a stray \n at end of file :/
-        assert_eq!(canonicalized, expected.trim())
+        // the document element stays
+        let canonicalized =
+            canonicalize_with_transform("<a><a/></a>", Default::default(), |document| {
+                assert_eq!(document.remove_elements("", "a"), 1);
+            })
+            .unwrap();
+        assert_eq!(canonicalized, "<a></a>");
+
+        assert!(matches!(
+            canonicalize_with_transform("<a>", Default::default(), |_| unreachable!()),
+            Err(CanonicalizationError::Parse(_))
+        ));
     }
 
     #[test]
-    fn canonical_exclusive_example_1() {
-        let input = include_str!("samples/canonical_exclusive/1_input.xml");
-        let expected = include_str!("samples/canonical_exclusive/1_output.xml");
+    fn max_depth() {
+        let input = "<a>\n<b><c/></b>\n<d><e>\n<f/></e></d></a>";
+        let options = |max_depth| CanonicalizationOptions {
+            max_depth,
+            ..Default::default()
+        };
 
-        let canonicalized = canonicalize_xml(
-            input,
-            CanonicalizationOptions {
-                mode: CanonicalizationMode::ExclusiveCanonical1_0,
+        for max_depth in [None, Some(4), Some(100)] {
+            assert!(canonicalize_xml(input, options(max_depth)).is_ok());
+        }
+        assert_eq!(
+            canonicalize_xml(input, options(Some(3))),
+            Err(CanonicalizationError::MaxDepthExceeded {
+                limit: 3,
+                line: Some(4)
+            })
+        );
+        assert_eq!(
+            canonicalize_xml(input, options(Some(2))),
+            Err(CanonicalizationError::MaxDepthExceeded {
+                limit: 2,
+                line: Some(2)
+            })
+        );
+        assert_eq!(
+            CanonicalizationError::MaxDepthExceeded {
+                limit: 2,
+                line: Some(2)
+            }
+            .to_string(),
+            "elements nested more than 2 levels deep on line 2"
+        );
+
+        // deeper than libxml2 would ever parse
+        let deep = format!("{}{}", "<a>".repeat(200), "</a>".repeat(200));
+        assert_eq!(
+            canonicalize_xml(&deep, options(Some(199))),
+            Err(CanonicalizationError::MaxDepthExceeded {
+                limit: 199,
+                line: Some(1)
+            })
+        );
+        assert!(canonicalize_xml(&deep, options(Some(200))).is_ok());
+    }
+
+    #[test]
+    fn injected_failures() {
+        inject::fail_next(inject::Failure::Parse);
+        let Err(CanonicalizationError::Parse(error)) = canonicalize_xml("<a/>", Default::default())
+        else {
+            panic!("parsing didn't fail");
+        };
+        assert_eq!(error.line, Some(1));
+        // only the next one fails
+        assert_eq!(
+            canonicalize_xml("<a/>", Default::default()),
+            Ok("<a></a>".to_owned())
+        );
+
+        // with and without a visibility callback
+        for max_output_bytes in [None, Some(100)] {
+            let options = CanonicalizationOptions {
+                max_output_bytes,
+                ..Default::default()
+            };
+            inject::fail_next(inject::Failure::Canonicalization);
+            let error = canonicalize_xml("<a/>", options.clone()).unwrap_err();
+            let CanonicalizationError::Canonicalization(code) = error else {
+                panic!("unexpected error {error:?}");
+            };
+            assert_eq!(code.code(), -1);
+            assert!(code.message().unwrap().contains("invalid mode"), "{code}");
+
+            assert_eq!(canonicalize_xml("<a/>", options), Ok("<a></a>".to_owned()));
+        }
+    }
+
+    #[test]
+    fn collection() {
+        let documents = ["<!-- a -->\n<a/>", "<b/><?pi b?>", "<c/>"];
+        let options = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            canonicalize_collection(documents, options.clone()).unwrap(),
+            "<!-- a -->\n<a></a><b></b>\n<?pi b?><c></c>"
+        );
+        assert_eq!(
+            canonicalize_collection(documents.map(str::to_owned), options.clone()).unwrap(),
+            documents
+                .iter()
+                .map(|document| canonicalize_xml(document, options.clone()).unwrap())
+                .collect::<String>()
+        );
+        assert_eq!(
+            canonicalize_collection([] as [&str; 0], options.clone()),
+            Ok(String::new())
+        );
+        assert!(matches!(
+            canonicalize_collection(["<a/>", "<b>"], options),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn validate_options() {
+        for mode in CanonicalizationMode::ALL {
+            let options = CanonicalizationOptions {
+                mode,
+                keep_comments_under: Some("//ds:Object".to_owned()),
+                xpath_namespaces: BTreeMap::from([("ds".to_owned(), DSIG_NAMESPACE.to_owned())]),
+                max_input_bytes: Some(1),
+                ..Default::default()
+            };
+            assert_eq!(options.validate(), Ok(()));
+        }
+        let exclusive = CanonicalizationOptions {
+            inclusive_ns_prefixes: vec!["#default".to_owned(), "ds".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(exclusive.validate(), Ok(()));
+
+        let invalid = |options: CanonicalizationOptions| options.validate().unwrap_err();
+        assert!(matches!(
+            invalid(CanonicalizationOptions {
+                mode: CanonicalizationMode::Canonical1_0,
+                ..exclusive.clone()
+            }),
+            CanonicalizationError::InvalidOptions(message) if message.contains("inclusive_ns_prefixes")
+        ));
+        for prefix in ["", "a:b", "x y", "#other", "a\0"] {
+            assert_eq!(
+                invalid(CanonicalizationOptions {
+                    inclusive_ns_prefixes: vec![prefix.to_owned()],
+                    ..Default::default()
+                }),
+                CanonicalizationError::InvalidNamespacePrefix(prefix.to_owned())
+            );
+        }
+        assert_eq!(
+            invalid(CanonicalizationOptions {
+                xpath_namespaces: BTreeMap::from([("".to_owned(), "urn:x".to_owned())]),
+                ..Default::default()
+            }),
+            CanonicalizationError::InvalidNamespacePrefix(String::new())
+        );
+        assert_eq!(
+            invalid(CanonicalizationOptions {
+                keep_comments_under: Some("//a[".to_owned()),
+                ..Default::default()
+            }),
+            CanonicalizationError::InvalidXPath {
+                expression: "//a[".to_owned(),
+                message: Some("Invalid expression".to_owned()),
+            }
+        );
+        assert_eq!(
+            invalid(CanonicalizationOptions {
+                max_output_bytes: Some(0),
+                ..Default::default()
+            }),
+            CanonicalizationError::InvalidOptions(
+                "max_output_bytes is 0, which rejects every document".to_owned()
+            )
+        );
+
+        // canonicalizing only checks what it can't work with
+        let other_mode = CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_1,
+            ..exclusive
+        };
+        assert_eq!(
+            canonicalize_xml("<a/>", other_mode),
+            Ok("<a></a>".to_owned())
+        );
+        let nul = CanonicalizationOptions {
+            inclusive_ns_prefixes: vec!["a\0".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(
+            canonicalize_xml("<a/>", nul),
+            Err(CanonicalizationError::InvalidNamespacePrefix(
+                "a\0".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn canonicalization_method_xml() {
+        let method = |options: CanonicalizationOptions| options.canonicalization_method_xml();
+
+        assert_eq!(
+            method(CanonicalizationOptions {
+                mode: CanonicalizationMode::Canonical1_1,
                 keep_comments: true,
-                inclusive_ns_prefixes: vec![],
+                ..Default::default()
+            })
+            .unwrap(),
+            r#"<ds:CanonicalizationMethod Algorithm="http://www.w3.org/2006/12/xml-c14n11#WithComments"></ds:CanonicalizationMethod>"#
+        );
+        assert_eq!(
+            method(CanonicalizationOptions {
+                inclusive_ns_prefixes: vec!["ds".to_owned()],
+                ..Default::default()
+            })
+            .unwrap(),
+            r#"<ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"><ec:InclusiveNamespaces xmlns:ec="http://www.w3.org/2001/10/xml-exc-c14n#" PrefixList="ds"></ec:InclusiveNamespaces></ds:CanonicalizationMethod>"#
+        );
+
+        // embedded in a signature, the element is its own canonical form
+        let signed_info = format!(
+            r#"<ds:SignedInfo xmlns:ds="{DSIG_NAMESPACE}">{}</ds:SignedInfo>"#,
+            method(CanonicalizationOptions::default()).unwrap()
+        );
+        assert_eq!(
+            canonicalize_xml(&signed_info, Default::default()).unwrap(),
+            signed_info
+        );
+
+        for options in [
+            CanonicalizationOptions {
+                mode: CanonicalizationMode::Canonical1_0,
+                inclusive_ns_prefixes: vec!["ds".to_owned()],
+                ..Default::default()
             },
-        )
-        .unwrap();
+            CanonicalizationOptions {
+                keep_comments_under: Some("//a".to_owned()),
+                ..Default::default()
+            },
+            CanonicalizationOptions {
+                non_conformant: NonConformantOptions {
+                    comment_crlf: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ] {
+            assert!(
+                matches!(
+                    method(options.clone()),
+                    Err(CanonicalizationError::InvalidOptions(_))
+                ),
+                "{options:?}"
+            );
+        }
+        assert_eq!(
+            method(CanonicalizationOptions {
+                inclusive_ns_prefixes: vec!["a\"b".to_owned()],
+                ..Default::default()
+            }),
+            Err(CanonicalizationError::InvalidNamespacePrefix(
+                "a\"b".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn algorithm_uris() {
+        for mode in CanonicalizationMode::ALL {
+            for keep_comments in [true, false] {
+                let options = CanonicalizationOptions {
+                    mode,
+                    keep_comments,
+                    ..Default::default()
+                };
+                let uri = options.algorithm_uri();
+                assert_eq!(uri.ends_with("#WithComments"), keep_comments);
+                assert_eq!(
+                    CanonicalizationOptions::from_algorithm_uri(uri).unwrap(),
+                    options
+                );
+            }
+        }
 
-        // for some reason, we get a stray \n at end of file :/
-        assert_eq!(canonicalized, expected.trim())
+        assert_eq!(
+            CanonicalizationOptions::default().algorithm_uri(),
+            "http://www.w3.org/2001/10/xml-exc-c14n#"
+        );
+        for unknown in [
+            "http://www.w3.org/2000/09/xmldsig#sha1",
+            "http://www.w3.org/2001/10/xml-exc-c14n",
+            "",
+        ] {
+            assert_eq!(
+                CanonicalizationOptions::from_algorithm_uri(unknown),
+                Err(CanonicalizationError::UnknownAlgorithm(unknown.to_owned()))
+            );
+        }
+        assert_eq!(
+            CanonicalizationOptions::from_algorithm_uri("urn:nope")
+                .unwrap_err()
+                .to_string(),
+            "unknown canonicalization algorithm `urn:nope`"
+        );
     }
 
     #[test]
-    fn canonical_exclusive_example_2() {
-        let input = include_str!("samples/canonical_exclusive/2_input.xml");
-        let expected = include_str!("samples/canonical_exclusive/2_output.xml");
+    fn coalesce_text() {
+        let input = r#"<!DOCTYPE a [
+<!ENTITY mid "middle">
+<!ENTITY nested "&mid; &amp; &#x41;">
+<!ENTITY markup "<b c='&mid;'>&mid;</b>">
+]>
+<a>start &mid; end<e>&nested;&nested;</e>&markup;</a>"#;
 
-        let canonicalized = canonicalize_xml(
-            input,
-            CanonicalizationOptions {
-                mode: CanonicalizationMode::ExclusiveCanonical1_0,
+        // libxml2 can't canonicalize entity references itself
+        assert!(matches!(
+            canonicalize_xml(input, Default::default()),
+            Err(CanonicalizationError::UnexpandedEntityReference { .. })
+        ));
+
+        for mode in CanonicalizationMode::ALL {
+            let options = CanonicalizationOptions {
+                mode,
+                coalesce_text: true,
+                ..Default::default()
+            };
+            assert_eq!(
+                canonicalize_xml(input, options).unwrap(),
+                r#"<a>start middle end<e>middle &amp; Amiddle &amp; A</e><b c="middle">middle</b></a>"#
+            );
+        }
+
+        // a document parsed without the option is expanded in a copy, and left alone itself
+        let document = parse(input).unwrap();
+        let options = CanonicalizationOptions {
+            coalesce_text: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            document.canonicalize(options.clone()),
+            canonicalize_xml(input, options.clone())
+        );
+        unsafe {
+            let root = document.root_element();
+            let text = (*root).children;
+            assert_eq!(
+                CStr::from_ptr((*text).content as *const _).to_str(),
+                Ok("start ")
+            );
+            assert_eq!((*(*text).next).type_, xmlElementType_XML_ENTITY_REF_NODE);
+
+            // a subset can't be carried over into the copy
+            assert!(matches!(
+                canonicalize_subtree((*root).doc, root, options),
+                Err(CanonicalizationError::InvalidOptions(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn subtree_by_pointer() {
+        let input = r#"<a xmlns="urn:a" xml:lang="en"><b>one<c/></b>two</a>"#;
+        let document = parse(input).unwrap();
+        let root = document.root_element();
+
+        unsafe {
+            let b = (*root).children;
+            let doc = (*root).doc;
+            assert_eq!(
+                canonicalize_subtree(doc, b, Default::default()),
+                canonicalize_nth_element(input, "urn:a", "b", 0, Default::default())
+            );
+            assert_eq!(
+                canonicalize_subtree(doc, root, Default::default()).unwrap(),
+                canonicalize_xml(input, Default::default()).unwrap()
+            );
+
+            // any node works, not just elements
+            let text = (*b).next;
+            assert_eq!(
+                canonicalize_subtree(doc, text, Default::default()).unwrap(),
+                "two"
+            );
+        }
+    }
+
+    #[test]
+    fn no_byte_order_mark() {
+        // a BOM in front of the input is not part of the document
+        let canonicalized =
+            canonicalize_xml("\u{feff}<a>\u{feff}</a>", Default::default()).unwrap();
+        assert_eq!(canonicalized, "<a>\u{feff}</a>");
+        let mut utf8 = b"\xef\xbb\xbf".to_vec();
+        utf8.extend_from_slice(b"<?xml version='1.0' encoding='UTF-8'?><!-- c --><a/>");
+        for keep_comments in [true, false] {
+            let options = CanonicalizationOptions {
+                keep_comments,
+                ..Default::default()
+            };
+            let canonicalized = canonicalize_xml_bytes(&utf8, None, options).unwrap();
+            assert!(canonicalized.starts_with('<'), "{canonicalized:?}");
+        }
+
+        // a subset starting with a text node starts with its content, which is valid canonical XML for that subset
+        let document = parse("<a>\u{feff}signed</a><!-- c -->").unwrap();
+        unsafe {
+            let root = document.root_element();
+            let text = (*root).children;
+            assert_eq!(
+                canonicalize_subtree((*root).doc, text, Default::default()),
+                Ok("\u{feff}signed".to_owned())
+            );
+        }
+    }
+
+    #[test]
+    fn verify_output() {
+        let input = include_str!("samples/canonical_1_1/3_1_input.xml");
+        for mode in CanonicalizationMode::ALL {
+            let options = CanonicalizationOptions {
+                mode,
                 keep_comments: true,
-                inclusive_ns_prefixes: ["stay1".to_string(), "stay2".to_string()].to_vec(),
-            },
+                verify_output: true,
+                ..Default::default()
+            };
+            assert_eq!(
+                canonicalize_xml(input, options.clone()),
+                canonicalize_xml(
+                    input,
+                    CanonicalizationOptions {
+                        verify_output: false,
+                        ..options
+                    }
+                )
+            );
+        }
+
+        // deliberately non-canonical output isn't rejected
+        let mut options = CanonicalizationOptions {
+            verify_output: true,
+            ..Default::default()
+        };
+        options.non_conformant.namespace_document_order = true;
+        assert!(canonicalize_xml(
+            r#"<a xmlns:z="urn:z" xmlns:y="urn:y" z:b="" y:c=""/>"#,
+            options
         )
-        .unwrap();
+        .is_ok());
+    }
+
+    #[test]
+    fn verify_inclusive_prefixes() {
+        let input =
+            r#"<a xmlns="urn:a" xmlns:ds="urn:ds" xmlns:x="urn:x"><b x:c="1"><ds:d/></b></a>"#;
+        let options = |prefixes: &[&str]| CanonicalizationOptions {
+            inclusive_ns_prefixes: prefixes.iter().map(|&prefix| prefix.to_owned()).collect(),
+            verify_inclusive_prefixes: true,
+            ..Default::default()
+        };
 
-        // for some reason, we get a stray \n at end of file :/
-        assert_eq!(canonicalized, expected.trim())
+        assert_eq!(
+            canonicalize_nth_element(input, "urn:a", "b", 0, options(&["#default", "ds"])).unwrap(),
+            r#"<b xmlns="urn:a" xmlns:ds="urn:ds" xmlns:x="urn:x" x:c="1"><ds:d></ds:d></b>"#
+        );
+        // declared on an element inside the subset rather than on its apex
+        assert!(canonicalize_xml(input, options(&["ds", "x"])).is_ok());
+
+        assert_eq!(
+            canonicalize_xml(input, options(&["ds", "dsig"])),
+            Err(CanonicalizationError::InclusivePrefixNotRendered(
+                "dsig".to_owned()
+            ))
+        );
+        assert_eq!(
+            canonicalize_raw("<a/>", options(&["#default"])).map(|_| ()),
+            Err(CanonicalizationError::InclusivePrefixNotRendered(
+                "#default".to_owned()
+            ))
+        );
+
+        // typos go unnoticed without the option
+        let unchecked = CanonicalizationOptions {
+            verify_inclusive_prefixes: false,
+            ..options(&["dsig"])
+        };
+        assert!(canonicalize_xml(input, unchecked).is_ok());
     }
 
     #[test]
-    fn invalid_xml() {
-        let input = "<invalid xml";
-        let canonicalized = canonicalize_xml(
-            input,
-            CanonicalizationOptions {
-                mode: CanonicalizationMode::Canonical1_0,
-                keep_comments: false,
-                inclusive_ns_prefixes: vec![],
-            },
+    fn empty_output() {
+        let document = parse("<a><!-- c --><b/></a>").unwrap();
+
+        unsafe {
+            // the only node in the subset is a comment, and comments are dropped
+            let root = document.root_element();
+            let comment = (*root).children;
+            for mode in CanonicalizationMode::ALL {
+                let options = CanonicalizationOptions {
+                    mode,
+                    ..Default::default()
+                };
+                assert_eq!(
+                    canonicalize_subtree((*root).doc, comment, options),
+                    Ok(String::new())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn strip_xsi_attributes() {
+        let input = r#"<a xmlns="urn:a" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="urn:a a.xsd"><b xmlns:i="http://www.w3.org/2001/XMLSchema-instance" i:nil="true" c="d"/></a>"#;
+
+        for mode in CanonicalizationMode::ALL {
+            let options = CanonicalizationOptions {
+                mode,
+                ..Default::default()
+            };
+            // retained by default
+            assert!(canonicalize_xml(input, options.clone())
+                .unwrap()
+                .contains("xsi:schemaLocation"));
+
+            let options = CanonicalizationOptions {
+                strip_xsi_attributes: true,
+                ..options
+            };
+            assert_eq!(
+                canonicalize_xml(input, options).unwrap(),
+                r#"<a xmlns="urn:a"><b c="d"></b></a>"#,
+                "{mode:?}"
+            );
+        }
+
+        // an element in the namespace keeps its declaration
+        let input = r#"<xsi:a xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:b="c"/>"#;
+        let options = CanonicalizationOptions {
+            strip_xsi_attributes: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            canonicalize_xml(input, options).unwrap(),
+            r#"<xsi:a xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"></xsi:a>"#
+        );
+
+        // the document itself is left alone
+        let document =
+            parse(r#"<a xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:nil="true"/>"#)
+                .unwrap();
+        let options = CanonicalizationOptions {
+            strip_xsi_attributes: true,
+            ..Default::default()
+        };
+        assert_eq!(document.canonicalize(options), Ok("<a></a>".to_owned()));
+        assert_eq!(
+            document.canonicalize(CanonicalizationOptions::default()),
+            Ok(
+                r#"<a xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:nil="true"></a>"#
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn strip_processing_instructions() {
+        let input = r#"<?xml-stylesheet href="a.xsl" type="text/xsl"?><?keep before?><a><?xml-stylesheet inner?><?xml-stylesheet-other x?>text</a><?keep after?>"#;
+
+        for mode in CanonicalizationMode::ALL {
+            let options = CanonicalizationOptions {
+                mode,
+                ..Default::default()
+            };
+            // retained by default
+            assert!(canonicalize_xml(input, options.clone())
+                .unwrap()
+                .starts_with(
+                    "<?xml-stylesheet href=\"a.xsl\" type=\"text/xsl\"?>\n<?keep before?>"
+                ));
+
+            let options = CanonicalizationOptions {
+                strip_processing_instructions: vec!["xml-stylesheet".to_owned()],
+                ..options
+            };
+            assert_eq!(
+                canonicalize_xml(input, options).unwrap(),
+                "<?keep before?>\n<a><?xml-stylesheet-other x?>text</a>\n<?keep after?>",
+                "{mode:?}"
+            );
+        }
+
+        // the root of a subset is kept
+        let document = parse("<a><?strip me?><?strip too?></a>").unwrap();
+        let options = CanonicalizationOptions {
+            strip_processing_instructions: vec!["strip".to_owned()],
+            ..Default::default()
+        };
+        unsafe {
+            let root = document.root_element();
+            let pi = (*root).children;
+            // libxml2 outputs a PI without a visible parent like one outside the document element
+            assert_eq!(
+                canonicalize_subtree((*root).doc, pi, options.clone()),
+                Ok("<?strip me?>\n".to_owned())
+            );
+        }
+        // neither is the root of a subset here, and the first call left both in the document
+        assert_eq!(document.canonicalize(options), Ok("<a></a>".to_owned()));
+        assert_eq!(
+            document.canonicalize(CanonicalizationOptions::default()),
+            Ok("<a><?strip me?><?strip too?></a>".to_owned())
         );
-        assert!(canonicalized.is_err())
     }
 
     #[test]
     fn display_error() {
-        let formatted = format!("{}", CanonicalizationErrorCode(-1));
+        let formatted = format!("{}", CanonicalizationErrorCode::new(-1));
         let expected = "canonicalization error (-1)";
         assert_eq!(formatted, expected);
+
+        let with_message =
+            CanonicalizationErrorCode::new(-1).with_message(Some("something broke".to_owned()));
+        assert_eq!(
+            with_message.to_string(),
+            "canonicalization error (-1): something broke"
+        );
     }
 }
@@ -0,0 +1,237 @@
+//! Which namespace declarations made it into canonical output, for debugging
+
+use crate::document::Document;
+use crate::escape::unescape_attribute_value;
+use crate::tokens::start_tags;
+use crate::{tree, CanonicalizationError, CanonicalizationOptions};
+use std::collections::BTreeSet;
+
+/// Namespace declarations of a document, split by whether its canonical form has them, see [canonicalize_with_namespaces]
+///
+/// Declarations are `(prefix, URI)` pairs, with an empty prefix for the default namespace,
+/// and an empty URI for an undeclaration like `xmlns=""`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct NamespaceReport {
+    /// Declarations in the output (each only once, even if rendered on several elements)
+    pub rendered: BTreeSet<(String, String)>,
+    /// Declarations in the source document that the output has nowhere
+    pub omitted: BTreeSet<(String, String)>,
+}
+
+impl NamespaceReport {
+    /// How many distinct declarations canonicalization pruned, i.e. the size of [omitted](Self::omitted)
+    ///
+    /// In [ExclusiveCanonical1_0](crate::CanonicalizationMode::ExclusiveCanonical1_0), a non-zero count means the document
+    /// declares namespaces the output doesn't use, which is why its canonical form differs from what a tool that keeps
+    /// every declaration produces. The inclusive modes only prune declarations outside the canonicalized subset, so for a
+    /// whole document the count is 0 there. Redundant redeclarations (the same prefix and URI again) don't count.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use xml_c14n::{canonicalize_with_namespaces, CanonicalizationMode, CanonicalizationOptions};
+    ///
+    /// let document = r#"<a xmlns:used="urn:used" xmlns:unused="urn:unused"><used:b/></a>"#;
+    /// let pruned = |mode| {
+    ///     let options = CanonicalizationOptions { mode, ..Default::default() };
+    ///     canonicalize_with_namespaces(document, options).unwrap().1.pruned_count()
+    /// };
+    ///
+    /// assert_eq!(pruned(CanonicalizationMode::ExclusiveCanonical1_0), 1);
+    /// assert_eq!(pruned(CanonicalizationMode::Canonical1_0), 0);
+    /// ```
+    pub fn pruned_count(&self) -> usize {
+        self.omitted.len()
+    }
+}
+
+/// Parse specified XML document, canonicalize it, and report which of its namespace declarations the output has
+///
+/// Meant for diagnosing namespace problems, e.g. a verifier that fails because exclusive canonicalization left out a
+/// prefix only used in an attribute value (which would need [inclusive_ns_prefixes](CanonicalizationOptions::inclusive_ns_prefixes)).
+/// Declarations are compared as pairs, so a prefix that's rendered with a different URI than some declaration of it
+/// in the source counts as omitted for that one.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_with_namespaces, CanonicalizationOptions};
+///
+/// let document = r#"<a xmlns:used="urn:used" xmlns:unused="urn:unused"><used:b/></a>"#;
+/// let (canonicalized, namespaces) = canonicalize_with_namespaces(document, CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, r#"<a><used:b xmlns:used="urn:used"></used:b></a>"#);
+/// assert!(namespaces.rendered.contains(&("used".to_owned(), "urn:used".to_owned())));
+/// assert!(namespaces.omitted.contains(&("unused".to_owned(), "urn:unused".to_owned())));
+/// ```
+pub fn canonicalize_with_namespaces(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<(String, NamespaceReport), CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+    namespace_report(&document, options)
+}
+
+/// Canonicalize `document`, and report which of its namespace declarations the output has
+pub(crate) fn namespace_report(
+    document: &Document,
+    options: CanonicalizationOptions,
+) -> Result<(String, NamespaceReport), CanonicalizationError> {
+    // SAFETY: the elements are valid for as long as the document is, and only read before canonicalizing
+    let declared: BTreeSet<(String, String)> = unsafe {
+        tree::elements(document.root_element())
+            .into_iter()
+            .flat_map(|element| tree::declared_namespaces(element))
+            .collect()
+    };
+
+    let canonical = document.canonicalize(options)?;
+    let rendered: BTreeSet<(String, String)> = start_tags(&canonical)
+        .iter()
+        .flat_map(|tag| &tag.attributes)
+        .filter_map(|attribute| {
+            let prefix = attribute.declared_prefix()?;
+            let uri = unescape_attribute_value(attribute.value(&canonical));
+            Some((prefix.to_owned(), uri))
+        })
+        .collect();
+    let omitted = declared.difference(&rendered).cloned().collect();
+
+    Ok((canonical, NamespaceReport { rendered, omitted }))
+}
+
+/// Parse specified XML document, canonicalize it, and count the namespace declarations in the output
+///
+/// Every `xmlns` and `xmlns:prefix` attribute counts, so a declaration that exclusive canonicalization renders on several
+/// elements counts once for each of them. That's what makes the output large, for the distinct declarations see
+/// [canonicalize_with_namespaces].
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_with_namespace_count, CanonicalizationOptions};
+///
+/// let document = r#"<a xmlns:x="urn:x"><x:b/><x:c/></a>"#;
+/// let (canonicalized, count) = canonicalize_with_namespace_count(document, CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, r#"<a><x:b xmlns:x="urn:x"></x:b><x:c xmlns:x="urn:x"></x:c></a>"#);
+/// assert_eq!(count, 2);
+/// ```
+pub fn canonicalize_with_namespace_count(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<(String, usize), CanonicalizationError> {
+    let canonical = crate::canonicalize_xml(document, options)?;
+    let count = start_tags(&canonical)
+        .iter()
+        .flat_map(|tag| &tag.attributes)
+        .filter(|attribute| attribute.declared_prefix().is_some())
+        .count();
+    Ok((canonical, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CanonicalizationMode;
+
+    fn pairs(pairs: &[(&str, &str)]) -> BTreeSet<(String, String)> {
+        pairs
+            .iter()
+            .map(|&(prefix, uri)| (prefix.to_owned(), uri.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn namespace_count() {
+        let document = r#"<a xmlns="urn:a" xmlns:x="urn:x" xmlns:unused="urn:unused"><x:b xmlns=""><c x:d="1"/></x:b><x:e/></a>"#;
+        let count = |mode| {
+            let options = CanonicalizationOptions {
+                mode,
+                ..Default::default()
+            };
+            canonicalize_with_namespace_count(document, options)
+                .unwrap()
+                .1
+        };
+
+        // xmlns="urn:a" on a, xmlns:x on a, xmlns:unused on a, xmlns="" on x:b
+        assert_eq!(count(CanonicalizationMode::Canonical1_0), 4);
+        // xmlns="urn:a" on a, xmlns:x on x:b and again on x:e, xmlns="" on c
+        assert_eq!(count(CanonicalizationMode::ExclusiveCanonical1_0), 4);
+
+        assert_eq!(
+            canonicalize_with_namespace_count("<a b='xmlns:c'/>", Default::default()).unwrap(),
+            (r#"<a b="xmlns:c"></a>"#.to_owned(), 0)
+        );
+    }
+
+    #[test]
+    fn redeclarations_are_not_pruned() {
+        let document = r#"<a xmlns:x="urn:x"><b xmlns:x="urn:x"><x:c/></b></a>"#;
+        for mode in CanonicalizationMode::ALL {
+            let options = CanonicalizationOptions {
+                mode,
+                ..Default::default()
+            };
+            let (_, report) = canonicalize_with_namespaces(document, options).unwrap();
+            assert_eq!(report.pruned_count(), 0, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn rendered_and_omitted() {
+        let document = r#"<a xmlns="urn:default" xmlns:x="urn:x" xmlns:y="urn:y"><b xmlns="" y:c="1"/><x:d xmlns:x="urn:x2"/></a>"#;
+        let report = |mode| {
+            canonicalize_with_namespaces(
+                document,
+                CanonicalizationOptions {
+                    mode,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .1
+        };
+
+        assert_eq!(
+            report(CanonicalizationMode::ExclusiveCanonical1_0),
+            NamespaceReport {
+                // the undeclaration is needed, as the default namespace is rendered on the parent
+                rendered: pairs(&[
+                    ("", ""),
+                    ("", "urn:default"),
+                    ("x", "urn:x2"),
+                    ("y", "urn:y")
+                ]),
+                omitted: pairs(&[("x", "urn:x")]),
+            }
+        );
+        assert_eq!(
+            report(CanonicalizationMode::Canonical1_1),
+            NamespaceReport {
+                rendered: pairs(&[
+                    ("", ""),
+                    ("", "urn:default"),
+                    ("x", "urn:x"),
+                    ("x", "urn:x2"),
+                    ("y", "urn:y")
+                ]),
+                omitted: pairs(&[]),
+            }
+        );
+
+        assert_eq!(
+            report(CanonicalizationMode::ExclusiveCanonical1_0).pruned_count(),
+            1
+        );
+        assert_eq!(report(CanonicalizationMode::Canonical1_1).pruned_count(), 0);
+
+        assert!(matches!(
+            canonicalize_with_namespaces("<a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,397 @@
+//! Deliberate deviations from the spec, for interoperating with broken peers
+//!
+//! These are implemented as post-processing of conformant canonical output.
+
+use crate::tokens::{comment_ranges, start_tags};
+use crate::tree;
+use libxml::bindings::xmlNodePtr;
+#[cfg(feature = "attribute-order")]
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// Options that make the output deviate from the canonicalization spec
+///
+/// **Output produced with any of these enabled is not canonical XML.**
+/// They exist only to diagnose and work around peers that reject conformant output, never use them for new systems.
+/// Everything is off by default.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct NonConformantOptions {
+    /// Render namespace declarations in the order they appear in the source document, instead of sorted by prefix
+    ///
+    /// Declarations that don't appear on the same element in the source (e.g. ones that exclusive canonicalization moved
+    /// to where they're used) come last, in spec order.
+    pub namespace_document_order: bool,
+    /// Render `xmlns:xml="http://www.w3.org/XML/1998/namespace"` on the topmost output elements that use `xml:*` attributes
+    ///
+    /// The `xml` prefix is bound by definition and its declaration is never rendered by any of the specs, including on
+    /// subtrees that inherit `xml:lang` or `xml:space` from their ancestors. Some verifiers expect the declaration anyway.
+    pub declare_xml_namespace: bool,
+    /// Render line breaks inside comments as `\r\n` instead of `\n`
+    ///
+    /// Only matters if comments are kept. Canonical XML normalizes every line break to `\n`, everywhere, but some legacy
+    /// verifiers compute their digests over comments with Windows line endings. Text, attribute values and processing
+    /// instructions keep `\n`.
+    pub comment_crlf: bool,
+    /// Order the attributes of each element with this comparator instead of by namespace URI and local name, enabled by
+    /// the `attribute-order` feature
+    ///
+    /// **The output is not canonical XML**, and a conformant verifier computes a different digest over the same content.
+    /// Only for a legacy peer that can't be fixed, e.g. one that sorts by qualified name (so by prefix rather than
+    /// namespace URI): pass `AttributeOrder(|left, right| left.cmp(right))` for that. Namespace declarations stay first,
+    /// in canonical order.
+    #[cfg(feature = "attribute-order")]
+    pub attribute_order: Option<AttributeOrder>,
+}
+
+/// Comparator for [NonConformantOptions::attribute_order]
+///
+/// It gets the qualified names of two attributes of the same element. Attributes it considers equal keep their canonical
+/// order. Comparators compare (and hash) by address, so two closures with the same body may or may not be equal.
+#[cfg(feature = "attribute-order")]
+#[derive(Copy, Clone)]
+pub struct AttributeOrder(pub fn(&str, &str) -> Ordering);
+
+#[cfg(feature = "attribute-order")]
+impl AttributeOrder {
+    fn address(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[cfg(feature = "attribute-order")]
+impl PartialEq for AttributeOrder {
+    fn eq(&self, other: &Self) -> bool {
+        self.address() == other.address()
+    }
+}
+
+#[cfg(feature = "attribute-order")]
+impl Eq for AttributeOrder {}
+
+#[cfg(feature = "attribute-order")]
+impl PartialOrd for AttributeOrder {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(feature = "attribute-order")]
+impl Ord for AttributeOrder {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.address().cmp(&other.address())
+    }
+}
+
+#[cfg(feature = "attribute-order")]
+impl std::hash::Hash for AttributeOrder {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.address().hash(state)
+    }
+}
+
+#[cfg(feature = "attribute-order")]
+impl std::fmt::Debug for AttributeOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AttributeOrder({:#x})", self.address())
+    }
+}
+
+impl NonConformantOptions {
+    /// Rewrite conformant canonical output according to the options
+    ///
+    /// `output_elements` gives the elements that `canonical` contains, in document order. It's only called if needed.
+    ///
+    /// # Safety
+    ///
+    /// The elements must be valid, and `canonical` must be the canonical form of (a subset of) their document
+    pub(crate) unsafe fn apply(
+        &self,
+        output_elements: impl FnOnce() -> Vec<xmlNodePtr>,
+        canonical: String,
+    ) -> String {
+        let mut canonical = canonical;
+        if self.namespace_document_order || self.declare_xml_namespace {
+            let elements = output_elements();
+            if self.namespace_document_order {
+                canonical = namespaces_in_document_order(&elements, &canonical);
+            }
+            if self.declare_xml_namespace {
+                canonical = declare_xml_namespace(&elements, &canonical);
+            }
+        }
+        if self.comment_crlf {
+            canonical = comment_crlf(&canonical);
+        }
+        #[cfg(feature = "attribute-order")]
+        if let Some(AttributeOrder(compare)) = self.attribute_order {
+            canonical = attribute_order(&canonical, compare);
+        }
+        canonical
+    }
+}
+
+/// See [NonConformantOptions::namespace_document_order]
+unsafe fn namespaces_in_document_order(elements: &[xmlNodePtr], canonical: &str) -> String {
+    let mut output = String::with_capacity(canonical.len());
+    let mut copied_up_to = 0;
+
+    // every element ends up in the output, and the output is in document order, so the start tags correspond to elements
+    for (tag, &element) in start_tags(canonical).iter().zip(elements) {
+        // namespace declarations always come first in canonical output
+        let declarations: Vec<_> = tag
+            .attributes
+            .iter()
+            .take_while(|attribute| attribute.declared_prefix().is_some())
+            .collect();
+        let (Some(first), Some(last)) = (declarations.first(), declarations.last()) else {
+            continue;
+        };
+
+        let source_order = tree::declared_prefixes(element);
+        let mut reordered = declarations.clone();
+        // stable sort, so declarations not found in the source keep their relative order
+        reordered.sort_by_key(|attribute| {
+            let prefix = attribute.declared_prefix().unwrap();
+            source_order
+                .iter()
+                .position(|source_prefix| source_prefix == prefix)
+                .unwrap_or(usize::MAX)
+        });
+
+        output.push_str(&canonical[copied_up_to..first.range.start]);
+        for attribute in reordered {
+            output.push_str(&canonical[attribute.range.clone()]);
+        }
+        copied_up_to = last.range.end;
+    }
+
+    output.push_str(&canonical[copied_up_to..]);
+    output
+}
+
+/// See [NonConformantOptions::declare_xml_namespace]
+unsafe fn declare_xml_namespace(elements: &[xmlNodePtr], canonical: &str) -> String {
+    const DECLARATION: &str = r#" xmlns:xml="http://www.w3.org/XML/1998/namespace""#;
+
+    let mut output = String::with_capacity(canonical.len());
+    let mut copied_up_to = 0;
+    let mut declared_on = HashSet::new();
+
+    for (tag, &element) in start_tags(canonical).iter().zip(elements) {
+        let uses_xml = tag
+            .attributes
+            .iter()
+            .any(|attribute| attribute.name.starts_with("xml:"));
+        let inherited = tree::ancestors_or_self((*element).parent)
+            .any(|ancestor| declared_on.contains(&ancestor));
+        if !uses_xml || inherited {
+            continue;
+        }
+        declared_on.insert(element);
+
+        // declarations come first, sorted by prefix, and the default namespace (without one) comes before all others
+        let position = tag
+            .attributes
+            .iter()
+            .find(|attribute| {
+                attribute
+                    .declared_prefix()
+                    .is_none_or(|prefix| prefix > "xml")
+            })
+            .map_or(tag.attributes_end, |attribute| attribute.range.start);
+
+        output.push_str(&canonical[copied_up_to..position]);
+        output.push_str(DECLARATION);
+        copied_up_to = position;
+    }
+
+    output.push_str(&canonical[copied_up_to..]);
+    output
+}
+
+/// See [NonConformantOptions::attribute_order]
+#[cfg(feature = "attribute-order")]
+fn attribute_order(canonical: &str, compare: fn(&str, &str) -> Ordering) -> String {
+    let mut output = String::with_capacity(canonical.len());
+    let mut copied_up_to = 0;
+
+    for tag in start_tags(canonical) {
+        // namespace declarations always come first in canonical output
+        let attributes: Vec<_> = tag
+            .attributes
+            .iter()
+            .skip_while(|attribute| attribute.declared_prefix().is_some())
+            .collect();
+        let (Some(first), Some(last)) = (attributes.first(), attributes.last()) else {
+            continue;
+        };
+
+        let mut reordered = attributes.clone();
+        reordered.sort_by(|left, right| compare(left.name, right.name));
+
+        output.push_str(&canonical[copied_up_to..first.range.start]);
+        for attribute in reordered {
+            output.push_str(&canonical[attribute.range.clone()]);
+        }
+        copied_up_to = last.range.end;
+    }
+
+    output.push_str(&canonical[copied_up_to..]);
+    output
+}
+
+/// See [NonConformantOptions::comment_crlf]
+fn comment_crlf(canonical: &str) -> String {
+    let mut output = String::with_capacity(canonical.len());
+    let mut copied_up_to = 0;
+
+    for range in comment_ranges(canonical) {
+        output.push_str(&canonical[copied_up_to..range.start]);
+        output.push_str(&canonical[range.clone()].replace('\n', "\r\n"));
+        copied_up_to = range.end;
+    }
+
+    output.push_str(&canonical[copied_up_to..]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        canonicalize_nth_element, canonicalize_xml, CanonicalizationMode, CanonicalizationOptions,
+    };
+
+    #[test]
+    fn namespace_document_order() {
+        let input = r#"<a xmlns:z="urn:z" xmlns="urn:default" xmlns:b="urn:b"><z:c xmlns:y="urn:y" xmlns:x="urn:x"/></a>"#;
+
+        for mode in [
+            CanonicalizationMode::Canonical1_0,
+            CanonicalizationMode::Canonical1_1,
+        ] {
+            let mut options = CanonicalizationOptions {
+                mode,
+                ..Default::default()
+            };
+
+            let conformant = canonicalize_xml(input, options.clone()).unwrap();
+            assert_eq!(
+                conformant,
+                r#"<a xmlns="urn:default" xmlns:b="urn:b" xmlns:z="urn:z"><z:c xmlns:x="urn:x" xmlns:y="urn:y"></z:c></a>"#
+            );
+
+            options.non_conformant.namespace_document_order = true;
+            let reordered = canonicalize_xml(input, options).unwrap();
+            assert_eq!(
+                reordered,
+                r#"<a xmlns:z="urn:z" xmlns="urn:default" xmlns:b="urn:b"><z:c xmlns:y="urn:y" xmlns:x="urn:x"></z:c></a>"#
+            );
+        }
+    }
+
+    #[test]
+    fn namespace_document_order_exclusive() {
+        // exclusive canonicalization moves both declarations to where they're used
+        let input = r#"<a xmlns:z="urn:z" xmlns:b="urn:b"><z:c b:d="1"/></a>"#;
+        let mut options = CanonicalizationOptions::default();
+        options.non_conformant.namespace_document_order = true;
+
+        let reordered = canonicalize_xml(input, options).unwrap();
+        assert_eq!(
+            reordered,
+            r#"<a><z:c xmlns:b="urn:b" xmlns:z="urn:z" b:d="1"></z:c></a>"#
+        );
+    }
+
+    #[test]
+    fn xml_namespace_per_spec() {
+        let input = r#"<a xml:lang="en" xmlns:x="urn:x"><x:b xml:space="preserve"><c/></x:b></a>"#;
+
+        // inclusive subsets inherit xml:* attributes, but the xml namespace is never declared
+        let options = CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_0,
+            ..Default::default()
+        };
+        let canonicalized = canonicalize_nth_element(input, "urn:x", "b", 0, options).unwrap();
+        assert_eq!(
+            canonicalized,
+            r#"<x:b xmlns:x="urn:x" xml:lang="en" xml:space="preserve"><c></c></x:b>"#
+        );
+
+        // exclusive subsets don't inherit them
+        let canonicalized =
+            canonicalize_nth_element(input, "urn:x", "b", 0, CanonicalizationOptions::default())
+                .unwrap();
+        assert_eq!(
+            canonicalized,
+            r#"<x:b xmlns:x="urn:x" xml:space="preserve"><c></c></x:b>"#
+        );
+    }
+
+    #[test]
+    fn declare_xml_namespace() {
+        let input = r#"<a xmlns="urn:a" xmlns:z="urn:z" xmlns:b="urn:b"><b:c xml:lang="en"><d xml:space="preserve"/></b:c><e xml:lang="lv"/><f/></a>"#;
+        let mut options = CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_1,
+            ..Default::default()
+        };
+        options.non_conformant.declare_xml_namespace = true;
+
+        let canonicalized = canonicalize_xml(input, options.clone()).unwrap();
+        assert_eq!(
+            canonicalized,
+            r#"<a xmlns="urn:a" xmlns:b="urn:b" xmlns:z="urn:z"><b:c xmlns:xml="http://www.w3.org/XML/1998/namespace" xml:lang="en"><d xml:space="preserve"></d></b:c><e xmlns:xml="http://www.w3.org/XML/1998/namespace" xml:lang="lv"></e><f></f></a>"#
+        );
+
+        // the subset's apex inherits xml:lang, so it gets the declaration, sorted among the others
+        let canonicalized = canonicalize_nth_element(input, "urn:b", "c", 0, options).unwrap();
+        assert_eq!(
+            canonicalized,
+            r#"<b:c xmlns="urn:a" xmlns:b="urn:b" xmlns:xml="http://www.w3.org/XML/1998/namespace" xmlns:z="urn:z" xml:lang="en"><d xml:space="preserve"></d></b:c>"#
+        );
+    }
+
+    #[test]
+    fn comment_crlf() {
+        let input = "<!--before\n--><a>\n<!--one\r\ntwo\nthree--><?pi <!--\n?><b c='&#10;'/></a>";
+        let mut options = CanonicalizationOptions {
+            keep_comments: true,
+            ..Default::default()
+        };
+        options.non_conformant.comment_crlf = true;
+
+        let canonicalized = canonicalize_xml(input, options.clone()).unwrap();
+        assert_eq!(
+            canonicalized,
+            "<!--before\r\n-->\n<a>\n<!--one\r\ntwo\r\nthree--><?pi <!--\n?><b c=\"&#xA;\"></b></a>"
+        );
+
+        options.keep_comments = false;
+        let canonicalized = canonicalize_xml(input, options).unwrap();
+        assert_eq!(canonicalized, "<a>\n<?pi <!--\n?><b c=\"&#xA;\"></b></a>");
+    }
+
+    #[cfg(feature = "attribute-order")]
+    #[test]
+    fn attribute_order() {
+        use super::AttributeOrder;
+
+        let input = r#"<a xmlns:z="urn:a" xmlns:b="urn:z" z:x="1" b:y="2" c="3" xmlns="urn:default"><d b:y="4" z:x="5"/></a>"#;
+
+        // by namespace URI, unqualified ones first
+        let conformant = canonicalize_xml(input, CanonicalizationOptions::default()).unwrap();
+        assert_eq!(
+            conformant,
+            r#"<a xmlns="urn:default" xmlns:b="urn:z" xmlns:z="urn:a" c="3" z:x="1" b:y="2"><d z:x="5" b:y="4"></d></a>"#
+        );
+
+        let mut options = CanonicalizationOptions::default();
+        options.non_conformant.attribute_order =
+            Some(AttributeOrder(|left, right| left.cmp(right)));
+        let by_prefix = canonicalize_xml(input, options).unwrap();
+        assert_eq!(
+            by_prefix,
+            r#"<a xmlns="urn:default" xmlns:b="urn:z" xmlns:z="urn:a" b:y="2" c="3" z:x="1"><d b:y="4" z:x="5"></d></a>"#
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Ownership of strings allocated by libxml2
+
+use libxml::bindings::{xmlChar, xmlFree};
+use std::ffi::{c_void, CStr};
+use std::str::Utf8Error;
+
+/// Output of libxml2 (e.g. canonicalization output), as a null-terminated string it allocated or collected from its output
+/// callbacks, knowing its length
+///
+/// libxml2 may leave its output pointer null even when it reports success (e.g. if nothing was output),
+/// which is treated as an empty string. Strings libxml2 allocated are freed with `xmlFree` on drop.
+pub(crate) enum XmlOutput {
+    Allocated {
+        ptr: *mut xmlChar,
+        len: usize,
+    },
+    /// Always ends in a null terminator
+    Collected(Vec<u8>),
+}
+
+impl XmlOutput {
+    /// Take ownership of a string allocated by libxml2
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null, or point to a valid null-terminated string allocated by libxml2, that must not be freed by anyone else
+    pub(crate) unsafe fn from_raw(ptr: *mut xmlChar) -> Self {
+        let len = if ptr.is_null() {
+            0
+        } else {
+            CStr::from_ptr(ptr as *const _).to_bytes().len()
+        };
+        XmlOutput::Allocated { ptr, len }
+    }
+
+    /// Take ownership of a string of `len` bytes allocated by libxml2, which reported its length
+    ///
+    /// # Safety
+    ///
+    /// The same as for [from_raw](Self::from_raw), and `len` must be the string's length without the null terminator
+    pub(crate) unsafe fn from_raw_parts(ptr: *mut xmlChar, len: usize) -> Self {
+        XmlOutput::Allocated {
+            ptr,
+            len: if ptr.is_null() { 0 } else { len },
+        }
+    }
+
+    /// Take ownership of output collected from libxml2's output callbacks, adding the null terminator
+    pub(crate) fn from_bytes(mut bytes: Vec<u8>) -> Self {
+        bytes.push(0);
+        XmlOutput::Collected(bytes)
+    }
+
+    /// Pointer to the null-terminated contents, null if libxml2 didn't output anything
+    fn as_ptr(&self) -> *const xmlChar {
+        match self {
+            XmlOutput::Allocated { ptr, .. } => *ptr,
+            XmlOutput::Collected(bytes) => bytes.as_ptr(),
+        }
+    }
+
+    /// Contents, without the null terminator
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        match self {
+            XmlOutput::Allocated { ptr, .. } if ptr.is_null() => &[],
+            // SAFETY: guaranteed by the contracts of from_raw and from_raw_parts
+            XmlOutput::Allocated { ptr, len } => unsafe { std::slice::from_raw_parts(*ptr, *len) },
+            XmlOutput::Collected(bytes) => &bytes[..bytes.len() - 1],
+        }
+    }
+
+    /// Copy contents into a [String]
+    pub(crate) fn to_string(&self) -> Result<String, Utf8Error> {
+        std::str::from_utf8(self.as_bytes()).map(str::to_owned)
+    }
+}
+
+/// Canonical output in a buffer libxml2 allocated or filled, see [canonicalize_raw](crate::canonicalize_raw)
+///
+/// The buffer is owned by this handle and freed when it's dropped, pointers from [as_ptr](Self::as_ptr) must not be
+/// freed by anyone else, nor used after the handle is dropped.
+pub struct RawCanonicalOutput(XmlOutput);
+
+impl RawCanonicalOutput {
+    pub(crate) fn new(output: XmlOutput) -> Self {
+        RawCanonicalOutput(output)
+    }
+
+    /// Pointer to the null-terminated UTF-8 output, never null (even if the output is empty)
+    pub fn as_ptr(&self) -> *const xmlChar {
+        let ptr = self.0.as_ptr();
+        if ptr.is_null() {
+            c"".as_ptr() as *const xmlChar
+        } else {
+            ptr
+        }
+    }
+
+    /// Length of the output in bytes, without the null terminator
+    pub fn len(&self) -> usize {
+        self.0.as_bytes().len()
+    }
+
+    /// Whether the output is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The output, without the null terminator
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl Drop for XmlOutput {
+    fn drop(&mut self) {
+        // SAFETY: we own the string, and it was allocated by libxml2, so it must be freed with its free function
+        unsafe {
+            if let (XmlOutput::Allocated { ptr, .. }, Some(free)) = (self, xmlFree) {
+                if !ptr.is_null() {
+                    free(*ptr as *mut c_void);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr::null_mut;
+
+    #[test]
+    fn null_is_empty() {
+        let output = unsafe { XmlOutput::from_raw(null_mut()) };
+        assert_eq!(output.as_bytes(), b"");
+        assert_eq!(output.to_string(), Ok(String::new()));
+
+        let raw = RawCanonicalOutput::new(output);
+        assert!(raw.is_empty());
+        assert!(!raw.as_ptr().is_null());
+        assert_eq!(unsafe { *raw.as_ptr() }, 0);
+    }
+
+    #[test]
+    fn collected() {
+        let raw = RawCanonicalOutput::new(XmlOutput::from_bytes(b"<a></a>".to_vec()));
+        assert_eq!(raw.len(), 7);
+        assert_eq!(raw.as_bytes(), b"<a></a>");
+        assert_eq!(
+            unsafe { CStr::from_ptr(raw.as_ptr() as *const _) },
+            c"<a></a>"
+        );
+    }
+}
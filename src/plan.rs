@@ -0,0 +1,116 @@
+//! Explaining what canonicalization does to a document, for previews
+
+use crate::document::Document;
+use crate::namespaces::namespace_report;
+use crate::tokens::comment_ranges;
+use crate::{tree, CanonicalizationError, CanonicalizationOptions};
+use libxml::bindings::xmlNodePtr;
+use std::collections::BTreeSet;
+
+/// What canonicalizing a document changes, see [describe]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct CanonicalizationPlan {
+    /// Comments in the document, including outside the document element
+    pub comments: usize,
+    /// How many of the [comments](Self::comments) the canonical form keeps, all or none of them unless
+    /// [keep_comments_under](CanonicalizationOptions::keep_comments_under) is set
+    pub comments_kept: usize,
+    /// Namespace declarations in the document that the canonical form has nowhere, as `(prefix, URI)` pairs like in
+    /// [NamespaceReport::omitted](crate::NamespaceReport::omitted)
+    pub pruned_namespaces: BTreeSet<(String, String)>,
+    /// Whether the document has a DOCTYPE, which canonical XML never has
+    pub doctype_removed: bool,
+    /// Encoding of the canonical form, which is always UTF-8
+    pub output_encoding: &'static str,
+}
+
+/// Parse specified XML document and report what canonicalizing it with `options` would change, without returning the
+/// canonical form
+///
+/// For previews, e.g. showing in a signing UI what a signature will and won't cover. The document is canonicalized to
+/// find out which namespace declarations and comments remain, so this takes as long as [canonicalize_xml](crate::canonicalize_xml),
+/// and fails in the same cases.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{describe, CanonicalizationOptions};
+///
+/// let document = r#"<!DOCTYPE a><a xmlns:unused="urn:unused"><!-- note --><b/></a>"#;
+/// let plan = describe(document, CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!((plan.comments, plan.comments_kept), (1, 0));
+/// assert!(plan.pruned_namespaces.contains(&("unused".to_owned(), "urn:unused".to_owned())));
+/// assert!(plan.doctype_removed);
+/// assert_eq!(plan.output_encoding, "UTF-8");
+/// ```
+pub fn describe(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<CanonicalizationPlan, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+
+    // SAFETY: the document is valid, and only read before canonicalizing
+    let (comments, doctype_removed) = unsafe {
+        let pointer = document.as_ptr();
+        (
+            tree::comments(pointer as xmlNodePtr).len(),
+            !(*pointer).intSubset.is_null(),
+        )
+    };
+    let (canonical, namespaces) = namespace_report(&document, options)?;
+
+    Ok(CanonicalizationPlan {
+        comments,
+        comments_kept: comment_ranges(&canonical).len(),
+        pruned_namespaces: namespaces.omitted,
+        doctype_removed,
+        output_encoding: "UTF-8",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CanonicalizationMode;
+
+    #[test]
+    fn describe_document() {
+        let input = r#"<?xml version="1.0"?>
+<!DOCTYPE a [<!ATTLIST a b CDATA "c">]>
+<!-- before -->
+<a xmlns:x="urn:x" xmlns:y="urn:y"><x:b><!-- inside --></x:b></a>"#;
+
+        let plan = describe(input, Default::default()).unwrap();
+        assert_eq!(
+            plan,
+            CanonicalizationPlan {
+                comments: 2,
+                comments_kept: 0,
+                pruned_namespaces: BTreeSet::from([("y".to_owned(), "urn:y".to_owned())]),
+                doctype_removed: true,
+                output_encoding: "UTF-8",
+            }
+        );
+
+        let options = CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_1,
+            keep_comments_under: Some("//x:b".to_owned()),
+            xpath_namespaces: [("x".to_owned(), "urn:x".to_owned())].into(),
+            ..Default::default()
+        };
+        let plan = describe(input, options).unwrap();
+        assert_eq!((plan.comments, plan.comments_kept), (2, 1));
+        assert!(plan.pruned_namespaces.is_empty());
+
+        let plan = describe("<a/>", Default::default()).unwrap();
+        assert_eq!((plan.comments, plan.doctype_removed), (0, false));
+
+        assert!(matches!(
+            describe("<a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,334 @@
+//! Canonicalizing documents that arrive in chunks
+
+use crate::diagnostics::{self, Diagnostic};
+use crate::document::{self, Document};
+use crate::{CanonicalizationError, CanonicalizationOptions, ParseError};
+use libxml::bindings::{
+    xmlCreatePushParserCtxt, xmlCtxtUseOptions, xmlDocGetRootElement, xmlFreeDoc,
+    xmlFreeParserCtxt, xmlParseChunk, xmlParserCtxtPtr, xmlParserErrors_XML_ERR_DOCUMENT_EMPTY,
+};
+use std::ffi::{c_char, c_int};
+use std::marker::PhantomData;
+use std::ptr::{null, null_mut};
+
+/// libxml2 detects the encoding from the first chunk, which needs at least this many bytes to see a BOM or `<?xm`
+const ENCODING_DETECTION_BYTES: usize = 4;
+
+/// Canonicalizes a document fed to it in chunks, e.g. as it's read from a socket
+///
+/// The document is parsed incrementally with libxml2's push parser, so the input never needs to be held in one buffer.
+/// The parsed tree is still built in memory, and the canonical form is produced by [finish](PushCanonicalizer::finish).
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{CanonicalizationOptions, PushCanonicalizer};
+///
+/// let mut canonicalizer = PushCanonicalizer::new(CanonicalizationOptions::default());
+/// for chunk in ["<a b='1'", "><c/", "></a>"] {
+///     canonicalizer.feed(chunk.as_bytes()).unwrap();
+/// }
+///
+/// assert_eq!(canonicalizer.finish().unwrap(), r#"<a b="1"><c></c></a>"#);
+/// ```
+pub struct PushCanonicalizer {
+    options: CanonicalizationOptions,
+    /// Created once enough input arrived for encoding detection
+    context: Option<xmlParserCtxtPtr>,
+    /// Input held back until the context is created
+    pending: Vec<u8>,
+    /// Total input bytes so far, for [max_input_bytes](CanonicalizationOptions::max_input_bytes)
+    fed: usize,
+    /// Looks for NUL characters in what's passed to the parser, once the encoding is detected
+    nul_scanner: NulScanner,
+    /// Everything libxml2 reported while parsing so far
+    diagnostics: Vec<Diagnostic>,
+    /// The parser context must stay on the thread that created it
+    thread: PhantomData<*const ()>,
+}
+
+impl PushCanonicalizer {
+    /// Prepare to canonicalize one document with `options`
+    pub fn new(options: CanonicalizationOptions) -> Self {
+        PushCanonicalizer {
+            options,
+            context: None,
+            pending: vec![],
+            fed: 0,
+            nul_scanner: NulScanner::default(),
+            diagnostics: vec![],
+            thread: PhantomData,
+        }
+    }
+
+    /// Parse the next chunk of the document
+    ///
+    /// Chunks can be split anywhere, even inside characters that take multiple bytes.
+    /// Errors as soon as the input is known to be malformed or too large, after which the document can't be finished.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), CanonicalizationError> {
+        self.fed += chunk.len();
+        self.options.check_input_size(self.fed)?;
+
+        if self.context.is_some() {
+            return self.feed_context(chunk, false);
+        }
+
+        self.pending.extend_from_slice(chunk);
+        if self.pending.len() < ENCODING_DETECTION_BYTES {
+            return Ok(());
+        }
+        self.start()
+    }
+
+    /// Parse the end of the document and canonicalize it
+    pub fn finish(mut self) -> Result<String, CanonicalizationError> {
+        if self.context.is_none() {
+            if self.pending.is_empty() {
+                return Err(CanonicalizationError::EmptyDocument);
+            }
+            // short documents, e.g. `<a/>`, never reach the detection threshold
+            self.start()?;
+        }
+        self.feed_context(&[], true)?;
+
+        let context = self.context.unwrap();
+        // SAFETY: we own the context and take the document out of it, so it's freed exactly once
+        let document = unsafe {
+            let document = (*context).myDoc;
+            (*context).myDoc = null_mut();
+            if document.is_null() {
+                return Err(CanonicalizationError::EmptyDocument);
+            }
+            Document::from_raw(document)
+        };
+        document
+            .coalesced_for(&self.options)
+            .canonicalize(self.options.clone())
+    }
+
+    /// Create the parser context and pass it the input held back so far
+    fn start(&mut self) -> Result<(), CanonicalizationError> {
+        self.context = Some(self.create_context()?);
+        self.nul_scanner.width = document::code_unit_width(&self.pending, None);
+        let pending = std::mem::take(&mut self.pending);
+        self.feed_context(&pending, false)
+    }
+
+    /// Create the push parser context from the options
+    fn create_context(&self) -> Result<xmlParserCtxtPtr, CanonicalizationError> {
+        libxml::init_parser();
+        // SAFETY: a null SAX handler means building a tree, and the chunk is passed later
+        unsafe {
+            let context = xmlCreatePushParserCtxt(null_mut(), null_mut(), null(), 0, null());
+            if context.is_null() {
+                return Err(ParseError::new("could not create a parser context").into());
+            }
+            xmlCtxtUseOptions(context, self.options.parse_options());
+            Ok(context)
+        }
+    }
+
+    /// Pass `chunk` to the parser, failing if the document turned out to be malformed
+    fn feed_context(&mut self, chunk: &[u8], terminate: bool) -> Result<(), CanonicalizationError> {
+        let context = self.context.unwrap();
+        if let Some(position) = self.nul_scanner.scan(chunk) {
+            return Err(ParseError::nul_character(position).into());
+        }
+
+        // SAFETY: the context is valid, and libxml2 copies what it needs from the chunk
+        unsafe {
+            let ((), diagnostics) = diagnostics::collect_diagnostics(|| {
+                let mut chunks = chunk.chunks(c_int::MAX as usize).peekable();
+                if chunks.peek().is_none() {
+                    xmlParseChunk(context, null(), 0, c_int::from(terminate));
+                }
+                while let Some(part) = chunks.next() {
+                    let last = chunks.peek().is_none();
+                    xmlParseChunk(
+                        context,
+                        part.as_ptr() as *const c_char,
+                        part.len() as c_int,
+                        c_int::from(terminate && last),
+                    );
+                }
+            });
+            self.diagnostics.extend(diagnostics);
+
+            // the same condition under which xmlCtxtReadMemory keeps the document
+            if (*context).wellFormed != 0 || (*context).recovery != 0 {
+                return Ok(());
+            }
+            let error = &(*context).lastError;
+            // unlike xmlReadMemory, the push parser reports a missing document element as extra content at the end
+            let no_element = terminate
+                && ((*context).myDoc.is_null() || xmlDocGetRootElement((*context).myDoc).is_null());
+            if no_element || error.code == xmlParserErrors_XML_ERR_DOCUMENT_EMPTY as c_int {
+                Err(CanonicalizationError::EmptyDocument)
+            } else {
+                let diagnostics = std::mem::take(&mut self.diagnostics);
+                Err(ParseError::from_xml_error(error)
+                    .with_diagnostics(diagnostics)
+                    .into())
+            }
+        }
+    }
+}
+
+/// Finds NUL characters in input that arrives in chunks, which may split code units, see [document::code_unit_width]
+#[derive(Default)]
+struct NulScanner {
+    /// Bytes per code unit, set once the encoding is detected
+    width: usize,
+    /// Bytes scanned so far
+    scanned: usize,
+    /// Whether the bytes of the code unit the last chunk ended in were all zero
+    partial_zero: bool,
+}
+
+impl NulScanner {
+    /// Byte position in the whole input of the first NUL character that ends in `chunk`
+    fn scan(&mut self, chunk: &[u8]) -> Option<usize> {
+        let width = self.width.max(1);
+        let mut position = None;
+        for (offset, &byte) in chunk.iter().enumerate() {
+            let index = self.scanned + offset;
+            let zero = byte == 0 && (index.is_multiple_of(width) || self.partial_zero);
+            self.partial_zero = zero;
+            if zero && index % width == width - 1 {
+                position = Some(index + 1 - width);
+                break;
+            }
+        }
+        self.scanned += chunk.len();
+        position
+    }
+}
+
+impl Drop for PushCanonicalizer {
+    fn drop(&mut self) {
+        if let Some(context) = self.context {
+            // SAFETY: we own the context, and the document if it wasn't taken out of it
+            unsafe {
+                if !(*context).myDoc.is_null() {
+                    xmlFreeDoc((*context).myDoc);
+                }
+                xmlFreeParserCtxt(context);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{canonicalize_xml, canonicalize_xml_bytes, CanonicalizationMode};
+
+    fn push(
+        chunks: &[&[u8]],
+        options: CanonicalizationOptions,
+    ) -> Result<String, CanonicalizationError> {
+        let mut canonicalizer = PushCanonicalizer::new(options);
+        for chunk in chunks {
+            canonicalizer.feed(chunk)?;
+        }
+        canonicalizer.finish()
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let input = include_str!("samples/canonical_1_1/3_1_input.xml");
+        let chunks: Vec<&[u8]> = input.as_bytes().chunks(1).collect();
+
+        for mode in CanonicalizationMode::ALL {
+            let options = CanonicalizationOptions {
+                mode,
+                keep_comments: true,
+                ..Default::default()
+            };
+            assert_eq!(
+                push(&chunks, options.clone()),
+                canonicalize_xml(input, options)
+            );
+        }
+    }
+
+    #[test]
+    fn short_and_utf16() {
+        assert_eq!(push(&[b"<a/>"], Default::default()).unwrap(), "<a></a>");
+        assert_eq!(
+            push(&[b"<a", b"/>"], Default::default()).unwrap(),
+            "<a></a>"
+        );
+
+        let utf16: Vec<u8> = "\u{feff}<a>ā</a>"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let chunks: Vec<&[u8]> = utf16.chunks(3).collect();
+        assert_eq!(
+            push(&chunks, Default::default()),
+            canonicalize_xml_bytes(&utf16, None, Default::default())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            push(&[b"<a>", b"</b>"], Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+        assert!(matches!(
+            push(&[b"<a>", b"<b>"], Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+        assert_eq!(
+            push(&[], Default::default()),
+            Err(CanonicalizationError::EmptyDocument)
+        );
+        assert_eq!(
+            push(&[b"  ", b"<!-- c -->"], Default::default()),
+            Err(CanonicalizationError::EmptyDocument)
+        );
+
+        let options = CanonicalizationOptions {
+            max_input_bytes: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(
+            push(&[b"<a>", b"</a>"], options),
+            Err(CanonicalizationError::InputTooLarge { size: 7, limit: 5 })
+        );
+
+        // everything libxml2 reported is collected, rather than printed
+        match push(&[b"<a x=1>", b"</a>"], Default::default()) {
+            Err(CanonicalizationError::Parse(error)) => {
+                assert_eq!(
+                    error.diagnostics.first().unwrap().message,
+                    "AttValue: \" or ' expected"
+                );
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn embedded_nul() {
+        assert_eq!(
+            push(&[b"<a/>", b"\0<b/>"], Default::default()),
+            Err(CanonicalizationError::Parse(ParseError::nul_character(4)))
+        );
+
+        // a NUL code unit split across chunks, and zero bytes that aren't a NUL character
+        let utf16: Vec<u8> = "\u{feff}<a>\u{100}</a>\0"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let chunks: Vec<&[u8]> = utf16.chunks(3).collect();
+        assert_eq!(
+            push(&chunks, Default::default()),
+            Err(CanonicalizationError::Parse(ParseError::nul_character(
+                utf16.len() - 2
+            )))
+        );
+    }
+}
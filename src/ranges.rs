@@ -0,0 +1,84 @@
+//! Locating elements in canonical output
+
+use crate::tokens::element_ranges;
+use crate::{canonicalize_xml, CanonicalizationError, CanonicalizationOptions};
+use std::ops::Range;
+
+/// Where an element is in canonical output, see [canonicalize_with_element_ranges]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ElementRange {
+    /// Qualified name, as in the output, e.g. `saml:Assertion`
+    pub name: String,
+    /// Byte range in the canonical output, from the `<` of the start tag to right after the end tag
+    pub range: Range<usize>,
+}
+
+/// Parse specified XML document, canonicalize it, and locate each child element of the document element in the result
+///
+/// Useful for mapping signed fragments to the bytes they occupy in the canonical form. The ranges are found in the final
+/// output, so they're correct even if the output was rewritten by [non_conformant](CanonicalizationOptions::non_conformant) options.
+/// Note that a child element's bytes are generally not its own canonical form, which (depending on the mode) has the
+/// namespace declarations of its ancestors too.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_with_element_ranges, CanonicalizationOptions};
+///
+/// let (canonicalized, ranges) =
+///     canonicalize_with_element_ranges("<a><b/>text<c><d/></c></a>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(ranges.len(), 2);
+/// assert_eq!(ranges[0].name, "b");
+/// assert_eq!(&canonicalized[ranges[0].range.clone()], "<b></b>");
+/// assert_eq!(&canonicalized[ranges[1].range.clone()], "<c><d></d></c>");
+/// ```
+pub fn canonicalize_with_element_ranges(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<(String, Vec<ElementRange>), CanonicalizationError> {
+    let canonical = canonicalize_xml(document, options)?;
+    let ranges = element_ranges(&canonical, 1)
+        .into_iter()
+        .map(|(name, range)| ElementRange {
+            name: name.to_owned(),
+            range,
+        })
+        .collect();
+    Ok((canonical, ranges))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CanonicalizationMode;
+
+    #[test]
+    fn child_ranges() {
+        let input = r#"<!-- before --><x:r xmlns:x="urn:x"><x:a b="&lt;/x:a>"><x:a/></x:a><!-- <c> --><?pi <c>?><c>&lt;/c></c></x:r>"#;
+
+        for mode in CanonicalizationMode::ALL {
+            let options = CanonicalizationOptions {
+                mode,
+                keep_comments: true,
+                ..Default::default()
+            };
+            let (canonicalized, ranges) =
+                canonicalize_with_element_ranges(input, options.clone()).unwrap();
+            assert_eq!(canonicalized, canonicalize_xml(input, options).unwrap());
+
+            let fragments: Vec<(&str, &str)> = ranges
+                .iter()
+                .map(|range| (range.name.as_str(), &canonicalized[range.range.clone()]))
+                .collect();
+            assert_eq!(
+                fragments,
+                [
+                    ("x:a", r#"<x:a b="&lt;/x:a>"><x:a></x:a></x:a>"#),
+                    ("c", "<c>&lt;/c&gt;</c>"),
+                ],
+                "{mode:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Same-document references, as used by XML Signature
+
+use crate::{
+    canonicalize_by_ids, canonicalize_xml, canonicalize_xml_bytes, CanonicalizationError,
+    CanonicalizationOptions,
+};
+
+/// What a same-document reference URI selects
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Reference<'a> {
+    /// The whole document
+    Document { comments: bool },
+    /// The element with this ID, and everything under it
+    Id { id: &'a str, comments: bool },
+}
+
+/// Parse a same-document reference URI, or return [None] if it's not one of the supported forms
+fn parse_reference(uri: &str) -> Option<Reference<'_>> {
+    if uri.is_empty() {
+        return Some(Reference::Document { comments: false });
+    }
+    let fragment = uri.strip_prefix('#')?;
+
+    let Some(expression) = fragment
+        .strip_prefix("xpointer(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    else {
+        // a bare name, e.g. `#x`
+        let is_name = !fragment.is_empty()
+            && !fragment
+                .chars()
+                .any(|c| c.is_whitespace() || "()'\"#:/".contains(c));
+        return is_name.then_some(Reference::Id {
+            id: fragment,
+            comments: false,
+        });
+    };
+
+    if expression == "/" {
+        return Some(Reference::Document { comments: true });
+    }
+    let quoted = expression.strip_prefix("id(")?.strip_suffix(')')?;
+    let id = ['\'', '"'].into_iter().find_map(|quote| {
+        quoted
+            .strip_prefix(quote)?
+            .strip_suffix(quote)
+            .filter(|id| !id.is_empty() && !id.contains(quote))
+    })?;
+    Some(Reference::Id { id, comments: true })
+}
+
+/// Parse specified XML document and canonicalize what a same-document reference URI selects, as XML Signature does for a `<Reference>`
+///
+/// Supported URIs are:
+///
+/// - `""`: the whole document, without comments
+/// - `#x`: the element with ID `x` and everything under it, without comments
+/// - `#xpointer(/)`: the whole document, with comments
+/// - `#xpointer(id('x'))` (or with double quotes): the element with ID `x` and everything under it, with comments
+///
+/// Whether comments are kept is determined by the URI, [keep_comments](CanonicalizationOptions::keep_comments) is
+/// ignored. IDs are found like in [canonicalize_by_ids], with the same errors for missing or duplicate ones. Any other
+/// URI (including other XPointer expressions) is a [CanonicalizationError::UnsupportedReference].
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_reference, CanonicalizationOptions};
+///
+/// let document = r#"<r><!-- c --><a ID="x"><!-- d --></a></r>"#;
+///
+/// assert_eq!(
+///     canonicalize_reference(document, "#x", CanonicalizationOptions::default()).unwrap(),
+///     r#"<a ID="x"></a>"#
+/// );
+/// assert_eq!(
+///     canonicalize_reference(document, "#xpointer(id('x'))", CanonicalizationOptions::default()).unwrap(),
+///     r#"<a ID="x"><!-- d --></a>"#
+/// );
+/// ```
+pub fn canonicalize_reference(
+    document: impl AsRef<str>,
+    uri: &str,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    match parse_reference(uri) {
+        Some(Reference::Document { comments }) => canonicalize_xml(
+            document,
+            CanonicalizationOptions {
+                keep_comments: comments,
+                ..options
+            },
+        ),
+        Some(Reference::Id { id, comments }) => canonicalize_by_ids(
+            document,
+            &[id],
+            CanonicalizationOptions {
+                keep_comments: comments,
+                ..options
+            },
+        ),
+        None => Err(CanonicalizationError::UnsupportedReference(uri.to_owned())),
+    }
+}
+
+/// The octets to digest for a `<Reference>`: the canonical form of `document` if `options` are given, otherwise
+/// `document` itself, unchanged
+///
+/// With [None], **no canonicalization is performed at all** and `document` doesn't even have to be XML. This is for
+/// references to binary resources, e.g. in detached signatures, so that XMLDSig code can get what to digest from one
+/// function whatever the reference points to. With options, it's the same as [canonicalize_xml_bytes] (with the encoding
+/// detected by libxml2), as bytes.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{reference_octets, CanonicalizationOptions};
+///
+/// assert_eq!(reference_octets(b"<a/>", Some(CanonicalizationOptions::default())).unwrap(), b"<a></a>");
+/// assert_eq!(reference_octets(b"<a/>", None).unwrap(), b"<a/>");
+/// assert_eq!(reference_octets(&[0xff, 0], None).unwrap(), [0xff, 0]);
+/// ```
+pub fn reference_octets(
+    document: &[u8],
+    options: Option<CanonicalizationOptions>,
+) -> Result<Vec<u8>, CanonicalizationError> {
+    match options {
+        Some(options) => canonicalize_xml_bytes(document, None, options).map(String::into_bytes),
+        None => Ok(document.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        for (uri, reference) in [
+            ("", Some(Reference::Document { comments: false })),
+            ("#xpointer(/)", Some(Reference::Document { comments: true })),
+            (
+                "#x",
+                Some(Reference::Id {
+                    id: "x",
+                    comments: false,
+                }),
+            ),
+            (
+                "#xpointer(id('x'))",
+                Some(Reference::Id {
+                    id: "x",
+                    comments: true,
+                }),
+            ),
+            (
+                "#xpointer(id(\"x.1\"))",
+                Some(Reference::Id {
+                    id: "x.1",
+                    comments: true,
+                }),
+            ),
+            ("#", None),
+            ("x", None),
+            ("http://example.com/#x", None),
+            ("#xpointer(//a)", None),
+            ("#xpointer(id('x\"))", None),
+            ("#xpointer(id(''))", None),
+            ("#xpointer(id('x'))/a", None),
+            ("#a b", None),
+        ] {
+            assert_eq!(parse_reference(uri), reference, "{uri}");
+        }
+    }
+
+    #[test]
+    fn octets() {
+        let document = "<a b='1'>\r\n</a>".as_bytes();
+        assert_eq!(reference_octets(document, None).unwrap(), document);
+        assert_eq!(
+            reference_octets(document, Some(Default::default())).unwrap(),
+            b"<a b=\"1\">\n</a>"
+        );
+        // not XML
+        assert!(reference_octets(b"%PDF-1.7", Some(Default::default())).is_err());
+    }
+
+    #[test]
+    fn canonicalize() {
+        let document = r#"<!-- c --><r><a ID="x"><!-- d --><b/></a></r>"#;
+        for keep_comments in [true, false] {
+            let options = CanonicalizationOptions {
+                keep_comments,
+                ..Default::default()
+            };
+
+            assert_eq!(
+                canonicalize_reference(document, "", options.clone()).unwrap(),
+                r#"<r><a ID="x"><b></b></a></r>"#
+            );
+            assert_eq!(
+                canonicalize_reference(document, "#xpointer(/)", options.clone()).unwrap(),
+                "<!-- c -->\n<r><a ID=\"x\"><!-- d --><b></b></a></r>"
+            );
+            assert_eq!(
+                canonicalize_reference(document, "#x", options.clone()).unwrap(),
+                r#"<a ID="x"><b></b></a>"#
+            );
+            assert_eq!(
+                canonicalize_reference(document, "#xpointer(id('x'))", options.clone()).unwrap(),
+                r#"<a ID="x"><!-- d --><b></b></a>"#
+            );
+
+            assert_eq!(
+                canonicalize_reference(document, "#y", options.clone()),
+                Err(CanonicalizationError::MissingId("y".to_owned()))
+            );
+            assert_eq!(
+                canonicalize_reference(document, "#xpointer(//a)", options),
+                Err(CanonicalizationError::UnsupportedReference(
+                    "#xpointer(//a)".to_owned()
+                ))
+            );
+        }
+    }
+}
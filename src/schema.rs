@@ -0,0 +1,230 @@
+//! Validating against an XML Schema compiled once, before canonicalizing
+
+use crate::diagnostics::{self, DiagnosticLevel};
+use crate::document::Document;
+use crate::entities::with_entity_loader;
+use crate::{CanonicalizationError, CanonicalizationOptions};
+use libxml::bindings::{
+    xmlSchemaFree, xmlSchemaFreeParserCtxt, xmlSchemaFreeValidCtxt, xmlSchemaNewMemParserCtxt,
+    xmlSchemaNewValidCtxt, xmlSchemaParse, xmlSchemaPtr, xmlSchemaValidateDoc,
+};
+use std::ffi::{c_char, c_int};
+use std::marker::PhantomData;
+
+/// A compiled XML Schema, for validating any number of documents with [canonicalize_validated]
+///
+/// Compiling a schema is much slower than validating against it, so keep one around for as long as documents are
+/// validated against it. It's freed on drop.
+///
+/// Confined to the thread that compiled it, like [Document]:
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>(_: T) {}
+///
+/// assert_send(xml_c14n::Schema::new("<xs:schema xmlns:xs='http://www.w3.org/2001/XMLSchema'/>").unwrap());
+/// ```
+pub struct Schema(xmlSchemaPtr, PhantomData<*const ()>);
+
+impl Schema {
+    /// Compile the specified XSD document
+    ///
+    /// Schemas it includes or imports are loaded from the local filesystem, resolved against the current directory
+    /// unless their location is absolute, but never over the network. Returns
+    /// [CanonicalizationError::SchemaCompilation] with libxml2's errors if it isn't a schema libxml2 can compile.
+    pub fn new(xsd: impl AsRef<str>) -> Result<Self, CanonicalizationError> {
+        let xsd = xsd.as_ref();
+        let size =
+            c_int::try_from(xsd.len()).map_err(|_| CanonicalizationError::InputTooLarge {
+                size: xsd.len(),
+                limit: c_int::MAX as usize,
+            })?;
+
+        libxml::init_parser();
+        let (schema, diagnostics) = diagnostics::collect_diagnostics(|| {
+            // a schema that can't be loaded makes compiling fail
+            let (schema, _) = with_entity_loader(local_file, || {
+                // SAFETY: the buffer outlives the parser context, which is freed before returning
+                unsafe {
+                    let parser = xmlSchemaNewMemParserCtxt(xsd.as_ptr() as *const c_char, size);
+                    if parser.is_null() {
+                        return parser as xmlSchemaPtr;
+                    }
+                    let schema = xmlSchemaParse(parser);
+                    xmlSchemaFreeParserCtxt(parser);
+                    schema
+                }
+            });
+            schema
+        });
+
+        if schema.is_null() {
+            return Err(CanonicalizationError::SchemaCompilation(diagnostics));
+        }
+        Ok(Schema(schema, PhantomData))
+    }
+}
+
+impl Drop for Schema {
+    fn drop(&mut self) {
+        // SAFETY: we own the schema, and validation contexts using it never outlive a call borrowing self
+        unsafe { xmlSchemaFree(self.0) }
+    }
+}
+
+/// Load a schema that another one includes or imports, if it's a local file
+fn local_file(system_id: &str, _public_id: Option<&str>) -> Option<Vec<u8>> {
+    let path = match system_id.strip_prefix("file://") {
+        Some(path) => path,
+        None if system_id.contains("://") => return None,
+        None => system_id,
+    };
+    std::fs::read(path).ok()
+}
+
+/// Parse specified XML document, validate it against `schema`, and canonicalize it if it's valid
+///
+/// Returns [CanonicalizationError::SchemaInvalid] with libxml2's validation errors if the document isn't valid.
+/// Validation doesn't change the document, so e.g. attributes the schema has defaults for are only in the canonical form
+/// if the document specifies them.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_validated, CanonicalizationError, CanonicalizationOptions, Schema};
+///
+/// let schema = Schema::new(
+///     r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+///         <xs:element name="a" type="xs:int"/>
+///     </xs:schema>"#,
+/// )
+/// .unwrap();
+///
+/// let canonicalized = canonicalize_validated("<a>1</a>", &schema, CanonicalizationOptions::default()).unwrap();
+/// assert_eq!(canonicalized, "<a>1</a>");
+///
+/// let invalid = canonicalize_validated("<a>one</a>", &schema, CanonicalizationOptions::default());
+/// assert!(matches!(invalid, Err(CanonicalizationError::SchemaInvalid(_))));
+/// ```
+pub fn canonicalize_validated(
+    document: impl AsRef<str>,
+    schema: &Schema,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    let parsed = Document::read_for(document.as_bytes(), None, &options)?;
+
+    let (result, diagnostics) = diagnostics::collect_diagnostics(|| {
+        // SAFETY: the schema and the document outlive the validation context, which is freed before returning
+        unsafe {
+            let validation = xmlSchemaNewValidCtxt(schema.0);
+            if validation.is_null() {
+                return -1;
+            }
+            let result = xmlSchemaValidateDoc(validation, parsed.as_ptr());
+            xmlSchemaFreeValidCtxt(validation);
+            result
+        }
+    });
+
+    if result != 0 {
+        return Err(CanonicalizationError::SchemaInvalid(
+            diagnostics
+                .into_iter()
+                .filter(|diagnostic| diagnostic.level >= DiagnosticLevel::Error)
+                .collect(),
+        ));
+    }
+    parsed.canonicalize(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XSD: &str = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+  <xs:element name="order">
+    <xs:complexType>
+      <xs:sequence>
+        <xs:element name="item" type="xs:string" maxOccurs="unbounded"/>
+      </xs:sequence>
+      <xs:attribute name="id" type="xs:positiveInteger" use="required"/>
+    </xs:complexType>
+  </xs:element>
+</xs:schema>"#;
+
+    #[test]
+    fn reused() {
+        let schema = Schema::new(XSD).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(
+                canonicalize_validated(
+                    "<order id='1'><item>a</item><item/></order>",
+                    &schema,
+                    Default::default()
+                )
+                .unwrap(),
+                r#"<order id="1"><item>a</item><item></item></order>"#
+            );
+
+            let Err(CanonicalizationError::SchemaInvalid(diagnostics)) =
+                canonicalize_validated("<order id='0'/>", &schema, Default::default())
+            else {
+                panic!("invalid document was accepted");
+            };
+            assert!(!diagnostics.is_empty());
+            assert!(diagnostics
+                .iter()
+                .all(|diagnostic| diagnostic.line == Some(1)));
+        }
+
+        assert!(matches!(
+            canonicalize_validated("<order", &schema, Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn not_a_schema() {
+        for xsd in ["<a/>", "<xs:schema", ""] {
+            assert!(
+                matches!(
+                    Schema::new(xsd),
+                    Err(CanonicalizationError::SchemaCompilation(_))
+                ),
+                "{xsd}"
+            );
+        }
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("xml_c14n_schema_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let included = dir.join("included.xsd");
+        std::fs::write(
+            &included,
+            r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema"><xs:element name="a"/></xs:schema>"#,
+        )
+        .unwrap();
+
+        let include = |location: &str| {
+            Schema::new(format!(
+                r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema"><xs:include schemaLocation="{location}"/></xs:schema>"#
+            ))
+        };
+        let schema = include(included.to_str().unwrap()).unwrap();
+        assert_eq!(
+            canonicalize_validated("<a/>", &schema, Default::default()).unwrap(),
+            "<a></a>"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // never fetched, so the included schema is missing
+        assert!(matches!(
+            include("http://127.0.0.1:9/remote.xsd"),
+            Err(CanonicalizationError::SchemaCompilation(_))
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+//! Checking that output looks like canonical XML, see [CanonicalizationOptions::verify_output](crate::CanonicalizationOptions::verify_output)
+
+use crate::tokens::start_tags;
+
+/// Check the parts of the canonical form that can be checked without the source document
+///
+/// Returns a description of the first problem found. Namespace URIs aren't known here, so qualified attributes aren't
+/// checked for order among themselves.
+pub(crate) fn check_shape(canonical: &str) -> Result<(), String> {
+    if canonical.starts_with("<?xml ") {
+        return Err("output starts with an XML declaration".to_owned());
+    }
+    if let Some(position) = canonical.find('\r') {
+        return Err(format!("unescaped carriage return at byte {position}"));
+    }
+
+    for tag in start_tags(canonical) {
+        if canonical[..tag.attributes_end].ends_with('/') {
+            return Err(format!(
+                "empty-element tag at byte {}",
+                tag.attributes_end - 1
+            ));
+        }
+
+        let position = tag.attributes.first().map_or(0, |first| first.range.start);
+        let (declarations, attributes): (Vec<_>, Vec<_>) = tag
+            .attributes
+            .iter()
+            .partition(|attribute| attribute.declared_prefix().is_some());
+        let declarations_first = tag
+            .attributes
+            .iter()
+            .take(declarations.len())
+            .all(|attribute| attribute.declared_prefix().is_some());
+        if !declarations_first {
+            return Err(format!(
+                "namespace declarations after attributes at byte {position}"
+            ));
+        }
+
+        let prefixes: Vec<&str> = declarations
+            .iter()
+            .filter_map(|declaration| declaration.declared_prefix())
+            .collect();
+        if !prefixes.windows(2).all(|pair| pair[0] < pair[1]) {
+            return Err(format!(
+                "namespace declarations not sorted by prefix at byte {position}"
+            ));
+        }
+
+        // unqualified attributes have no namespace URI, which sorts before all others
+        let names: Vec<&str> = attributes.iter().map(|attribute| attribute.name).collect();
+        let unqualified = names.iter().take_while(|name| !name.contains(':')).count();
+        if names[unqualified..].iter().any(|name| !name.contains(':'))
+            || !names[..unqualified]
+                .windows(2)
+                .all(|pair| pair[0] < pair[1])
+        {
+            return Err(format!("attributes not sorted at byte {position}"));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_shapes() {
+        for canonical in [
+            "<a></a>",
+            r#"<!-- c -->
+<a xmlns="urn:a" xmlns:b="urn:b" c="1" d="2" b:a="3" xml:lang="en">&#xD;<e></e></a>"#,
+        ] {
+            assert_eq!(check_shape(canonical), Ok(()), "{canonical}");
+        }
+    }
+
+    #[test]
+    fn problems() {
+        for (output, problem) in [
+            (
+                "<?xml version=\"1.0\"?><a></a>",
+                "output starts with an XML declaration",
+            ),
+            ("<a>\r</a>", "unescaped carriage return at byte 3"),
+            ("<a><b/></a>", "empty-element tag at byte 5"),
+            (
+                r#"<a b="1" xmlns="urn:a"></a>"#,
+                "namespace declarations after attributes at byte 2",
+            ),
+            (
+                r#"<a xmlns:z="urn:z" xmlns="urn:a"></a>"#,
+                "namespace declarations not sorted by prefix at byte 2",
+            ),
+            (r#"<a d="1" c="2"></a>"#, "attributes not sorted at byte 2"),
+            (
+                r#"<a xmlns:b="urn:b" b:c="1" d="2"></a>"#,
+                "attributes not sorted at byte 2",
+            ),
+        ] {
+            assert_eq!(check_shape(output), Err(problem.to_owned()), "{output}");
+        }
+    }
+}
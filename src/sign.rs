@@ -0,0 +1,115 @@
+//! Canonicalize-then-sign helper, enabled by the `sign` feature
+//!
+//! This is only the low-level piece of XML signatures: it signs the canonical bytes of whatever it's given.
+//! It does not build `<Signature>` elements, compute reference digests, or verify anything, so it is not an XMLDSig implementation.
+
+use crate::{canonicalize_xml, CanonicalizationError, CanonicalizationOptions};
+use thiserror::Error;
+
+/// A private key (or anything else) that can sign bytes
+///
+/// The crate doesn't depend on any crypto library, so implement this for the RSA/ECDSA key type of your choice.
+pub trait SigningKey {
+    /// Error returned when signing fails
+    type Error: std::error::Error + 'static;
+
+    /// Produce a signature over `data`
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Self::Error>;
+}
+
+/// Error returned by [sign_canonical]
+#[derive(Debug, Error)]
+pub enum SignError<E: std::error::Error + 'static> {
+    /// The document could not be canonicalized
+    #[error("failed to canonicalize document for signing")]
+    Canonicalization(#[source] CanonicalizationError),
+    /// The key failed to produce a signature
+    #[error("failed to sign canonical document")]
+    Signing(#[source] E),
+}
+
+/// Canonicalize the specified XML document and sign the canonical bytes with `key`
+///
+/// Example:
+///
+/// ```
+/// use std::convert::Infallible;
+/// use xml_c14n::{sign_canonical, CanonicalizationOptions, SigningKey};
+///
+/// /// Not a real signature, just for demonstration
+/// struct LengthKey;
+///
+/// impl SigningKey for LengthKey {
+///     type Error = Infallible;
+///
+///     fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Infallible> {
+///         Ok(data.len().to_be_bytes().to_vec())
+///     }
+/// }
+///
+/// // canonical form is "<hi></hi>", 9 bytes
+/// let signature = sign_canonical("<hi/>", CanonicalizationOptions::default(), &LengthKey).unwrap();
+/// assert_eq!(signature, 9usize.to_be_bytes());
+/// ```
+pub fn sign_canonical<K: SigningKey>(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+    key: &K,
+) -> Result<Vec<u8>, SignError<K::Error>> {
+    let document = document.as_ref();
+    let canonical = canonicalize_xml(document, options).map_err(SignError::Canonicalization)?;
+    key.sign(canonical.as_bytes()).map_err(SignError::Signing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt;
+
+    /// Records what it was asked to sign
+    struct EchoKey;
+
+    impl SigningKey for EchoKey {
+        type Error = fmt::Error;
+
+        fn sign(&self, data: &[u8]) -> Result<Vec<u8>, fmt::Error> {
+            Ok(data.to_vec())
+        }
+    }
+
+    /// Always fails
+    struct BrokenKey;
+
+    impl SigningKey for BrokenKey {
+        type Error = fmt::Error;
+
+        fn sign(&self, _data: &[u8]) -> Result<Vec<u8>, fmt::Error> {
+            Err(fmt::Error)
+        }
+    }
+
+    #[test]
+    fn signs_canonical_bytes() {
+        let signed = sign_canonical(
+            "<a  b='c' ><!-- x --></a>",
+            CanonicalizationOptions::default(),
+            &EchoKey,
+        )
+        .unwrap();
+        assert_eq!(signed, br#"<a b="c"></a>"#);
+    }
+
+    #[test]
+    fn invalid_xml() {
+        let err = sign_canonical("<invalid xml", CanonicalizationOptions::default(), &EchoKey)
+            .unwrap_err();
+        assert!(matches!(err, SignError::Canonicalization(_)));
+    }
+
+    #[test]
+    fn signing_failure() {
+        let err =
+            sign_canonical("<hi/>", CanonicalizationOptions::default(), &BrokenKey).unwrap_err();
+        assert!(matches!(err, SignError::Signing(fmt::Error)));
+    }
+}
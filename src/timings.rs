@@ -0,0 +1,79 @@
+//! Measuring where canonicalization spends its time, for profiling
+
+use crate::document::Document;
+use crate::{CanonicalizationError, CanonicalizationOptions};
+use std::time::{Duration, Instant};
+
+/// How long each phase of [canonicalize_with_timings] took
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct Timings {
+    /// Parsing the document into a tree
+    pub parse: Duration,
+    /// Canonicalizing the tree, including the options that change or check it first and any
+    /// [non_conformant](CanonicalizationOptions::non_conformant) rewriting after
+    pub canonicalize: Duration,
+}
+
+/// Parse specified XML document and canonicalize it, like [canonicalize_xml](crate::canonicalize_xml), measuring how long
+/// parsing and canonicalizing took
+///
+/// For finding out which of the two dominates for a slow document. Other functions don't measure anything.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_with_timings, CanonicalizationOptions};
+///
+/// let (canonicalized, timings) = canonicalize_with_timings("<hi/>", CanonicalizationOptions::default()).unwrap();
+///
+/// assert_eq!(canonicalized, "<hi></hi>");
+/// println!("parsing took {:?}, canonicalizing {:?}", timings.parse, timings.canonicalize);
+/// ```
+pub fn canonicalize_with_timings(
+    document: impl AsRef<str>,
+    options: CanonicalizationOptions,
+) -> Result<(String, Timings), CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+
+    let start = Instant::now();
+    let parsed = Document::read_for(document.as_bytes(), None, &options)?;
+    let parsed_at = Instant::now();
+    let canonical = parsed.canonicalize(options)?;
+    let canonicalized_at = Instant::now();
+
+    let timings = Timings {
+        parse: parsed_at - start,
+        canonicalize: canonicalized_at - parsed_at,
+    };
+    Ok((canonical, timings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonicalize_xml;
+
+    #[test]
+    fn phases() {
+        let input = format!("<r>{}</r>", "<a b='c'>text</a>".repeat(10_000));
+
+        let start = Instant::now();
+        let (canonicalized, timings) =
+            canonicalize_with_timings(&input, Default::default()).unwrap();
+        let total = start.elapsed();
+
+        assert_eq!(
+            canonicalized,
+            canonicalize_xml(&input, Default::default()).unwrap()
+        );
+        assert!(timings.parse > Duration::ZERO);
+        assert!(timings.canonicalize > Duration::ZERO);
+        assert!(timings.parse + timings.canonicalize <= total);
+
+        assert!(matches!(
+            canonicalize_with_timings("<a>", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+    }
+}
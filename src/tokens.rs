@@ -0,0 +1,193 @@
+//! Scanning canonical output
+//!
+//! Canonical XML is much more regular than XML in general (no self-closing tags, attribute values always in double quotes,
+//! `<` always escaped outside comments and processing instructions), so a tiny scanner is enough to find its start tags.
+//! This must never be used on arbitrary XML.
+
+use std::ops::Range;
+
+/// A start tag in canonical output
+pub(crate) struct StartTag<'a> {
+    /// Namespace declarations and attributes, in output order
+    pub(crate) attributes: Vec<Attribute<'a>>,
+    /// Byte position of the `>` closing the tag
+    pub(crate) attributes_end: usize,
+}
+
+/// An attribute (or namespace declaration) of a [StartTag]
+pub(crate) struct Attribute<'a> {
+    /// Qualified name, e.g. `xmlns:a` or `a:b`
+    pub(crate) name: &'a str,
+    /// Byte range in the canonical output, including the space before the name
+    pub(crate) range: Range<usize>,
+}
+
+impl Attribute<'_> {
+    /// If this is a namespace declaration, the prefix it declares (empty for the default namespace)
+    pub(crate) fn declared_prefix(&self) -> Option<&str> {
+        match self.name {
+            "xmlns" => Some(""),
+            name => name.strip_prefix("xmlns:"),
+        }
+    }
+
+    /// The value, as escaped in `canonical` (the output this attribute was found in)
+    pub(crate) fn value<'a>(&self, canonical: &'a str) -> &'a str {
+        // ` name="value"`
+        &canonical[self.range.start + self.name.len() + 3..self.range.end - 1]
+    }
+}
+
+/// Find all start tags in `canonical`, which must be the canonical form of some document
+pub(crate) fn start_tags(canonical: &str) -> Vec<StartTag<'_>> {
+    let mut tags = vec![];
+    scan(canonical, |_, tag| tags.extend(tag), |_| {});
+    tags
+}
+
+/// Byte positions of all start and end tags in `canonical`, which must be the canonical form of some document
+pub(crate) fn tag_positions(canonical: &str) -> Vec<usize> {
+    let mut positions = vec![];
+    scan(canonical, |position, _| positions.push(position), |_| {});
+    positions
+}
+
+/// Byte ranges of the text of all comments in `canonical` (between `<!--` and `-->`), which must be the canonical form of
+/// some document
+pub(crate) fn comment_ranges(canonical: &str) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    scan(canonical, |_, _| {}, |range| ranges.push(range));
+    ranges
+}
+
+/// Qualified name and byte range (from the start tag's `<` to right after the end tag) of every element at `depth`,
+/// counting the outermost elements of `canonical` as depth 0
+pub(crate) fn element_ranges(canonical: &str, depth: usize) -> Vec<(&str, Range<usize>)> {
+    let mut ranges = vec![];
+    // names and start positions of the elements enclosing the current position
+    let mut open = vec![];
+
+    for position in tag_positions(canonical) {
+        let rest = &canonical[position..];
+        if rest.starts_with("</") {
+            let Some((name, start)) = open.pop() else {
+                continue;
+            };
+            if open.len() == depth {
+                let end = position + rest.find('>').unwrap() + 1;
+                ranges.push((name, start..end));
+            }
+        } else {
+            let name_end = rest.find([' ', '>']).unwrap();
+            open.push((&rest[1..name_end], position));
+        }
+    }
+
+    ranges
+}
+
+/// Call `on_tag` with the position of each start and end tag in `canonical` (and the scanned tag, if it's a start tag),
+/// and `on_comment` with the range of each comment's text
+fn scan<'a>(
+    canonical: &'a str,
+    mut on_tag: impl FnMut(usize, Option<StartTag<'a>>),
+    mut on_comment: impl FnMut(Range<usize>),
+) {
+    let mut pos = 0;
+
+    while let Some(offset) = canonical[pos..].find('<') {
+        let start = pos + offset;
+        let rest = &canonical[start..];
+
+        let skip_past = |terminator: &str| {
+            rest.find(terminator)
+                .map_or(canonical.len(), |end| start + end + terminator.len())
+        };
+
+        pos = if rest.starts_with("<!--") {
+            let end = skip_past("-->");
+            on_comment(start + 4..end - 3);
+            end
+        } else if rest.starts_with("<?") {
+            skip_past("?>")
+        } else if rest.starts_with("</") {
+            on_tag(start, None);
+            skip_past(">")
+        } else {
+            let (tag, end) = scan_start_tag(canonical, start);
+            on_tag(start, Some(tag));
+            end
+        };
+    }
+}
+
+/// Scan the start tag beginning at `start`, returning it and the position right after it
+fn scan_start_tag(canonical: &str, start: usize) -> (StartTag<'_>, usize) {
+    let bytes = canonical.as_bytes();
+
+    // skip `<` and the element name
+    let mut pos = start + 1;
+    while !matches!(bytes[pos], b' ' | b'>') {
+        pos += 1;
+    }
+
+    let mut attributes = vec![];
+    while bytes[pos] == b' ' {
+        let attribute_start = pos;
+        let name_start = pos + 1;
+        let name_end = name_start + canonical[name_start..].find('=').unwrap();
+        // values are always double-quoted, with `"` escaped inside
+        let value_start = name_end + 2;
+        let value_end = value_start + canonical[value_start..].find('"').unwrap();
+        pos = value_end + 1;
+
+        attributes.push(Attribute {
+            name: &canonical[name_start..name_end],
+            range: attribute_start..pos,
+        });
+    }
+
+    let tag = StartTag {
+        attributes,
+        attributes_end: pos,
+    };
+    // skip `>`
+    (tag, pos + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan() {
+        let canonical = r#"<!-- <x> --><a xmlns="urn:a" b="1>2"><?pi <y>?><c:d xmlns:c="urn:c">&lt;e&gt;</c:d></a>"#;
+        let tags = start_tags(canonical);
+
+        assert_eq!(tags.len(), 2);
+        assert_eq!(&canonical[tags[0].attributes_end..][..2], "><");
+
+        let attributes: Vec<(&str, &str)> = tags[0]
+            .attributes
+            .iter()
+            .map(|attribute| (attribute.name, &canonical[attribute.range.clone()]))
+            .collect();
+        assert_eq!(
+            attributes,
+            [("xmlns", r#" xmlns="urn:a""#), ("b", r#" b="1>2""#)]
+        );
+
+        assert_eq!(tags[0].attributes[1].value(canonical), "1>2");
+        assert_eq!(tags[1].attributes[0].value(canonical), "urn:c");
+
+        assert_eq!(tags[0].attributes[0].declared_prefix(), Some(""));
+        assert_eq!(tags[0].attributes[1].declared_prefix(), None);
+        assert_eq!(tags[1].attributes[0].declared_prefix(), Some("c"));
+
+        assert_eq!(tag_positions(canonical), [12, 47, 77, 83]);
+        assert_eq!(element_ranges(canonical, 0), [("a", 12..87)]);
+        assert_eq!(element_ranges(canonical, 1), [("c:d", 47..83)]);
+        assert_eq!(element_ranges(canonical, 2), []);
+        assert_eq!(comment_ranges(canonical), vec![4..9]);
+    }
+}
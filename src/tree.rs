@@ -0,0 +1,847 @@
+//! Walking parsed documents
+
+use crate::ids::QName;
+use crate::output::XmlOutput;
+use libxml::bindings::{
+    xmlAddPrevSibling, xmlAttrPtr, xmlChar, xmlCopyDoc, xmlDocCopyNodeList, xmlDocPtr,
+    xmlElementType_XML_CDATA_SECTION_NODE, xmlElementType_XML_COMMENT_NODE,
+    xmlElementType_XML_DOCUMENT_NODE, xmlElementType_XML_ELEMENT_NODE,
+    xmlElementType_XML_ENTITY_DECL, xmlElementType_XML_ENTITY_REF_NODE,
+    xmlElementType_XML_TEXT_NODE, xmlEntityPtr, xmlEntityType_XML_INTERNAL_GENERAL_ENTITY,
+    xmlFreeNode, xmlFreeURI, xmlGetDocEntity, xmlGetLineNo, xmlIsID, xmlNodeAddContent,
+    xmlNodeGetSpacePreserve, xmlNodeListGetString, xmlNodePtr, xmlNsPtr, xmlParseURI,
+    xmlRemoveProp, xmlUnlinkNode,
+};
+use std::collections::{HashMap, HashSet};
+use std::ffi::CStr;
+use std::iter::successors;
+
+/// Unqualified attributes treated as IDs even without a DTD declaring them, as is common practice for XML Signature
+/// (e.g. SAML uses `ID`, WS-Security `Id`)
+const ID_ATTRIBUTE_NAMES: [&str; 3] = ["ID", "Id", "id"];
+
+/// `node`, its parent, its parent's parent, and so on
+///
+/// # Safety
+///
+/// `node` must point to a valid node (or be null)
+pub(crate) unsafe fn ancestors_or_self(node: xmlNodePtr) -> impl Iterator<Item = xmlNodePtr> {
+    successors(Some(node).filter(|node| !node.is_null()), |&node| {
+        Some((*node).parent).filter(|parent| !parent.is_null())
+    })
+}
+
+/// `root` and all elements under it, in document order
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null). The returned pointers are only valid as long as its document is.
+pub(crate) unsafe fn elements(root: xmlNodePtr) -> Vec<xmlNodePtr> {
+    let mut elements = vec![];
+    let mut stack = vec![root];
+
+    while let Some(node) = stack.pop() {
+        if node.is_null() || (*node).type_ != xmlElementType_XML_ELEMENT_NODE {
+            continue;
+        }
+        elements.push(node);
+
+        // push children in reverse, so that the first child is visited next
+        let first_child = stack.len();
+        let mut child = (*node).children;
+        while !child.is_null() {
+            stack.push(child);
+            child = (*child).next;
+        }
+        stack[first_child..].reverse();
+    }
+
+    elements
+}
+
+/// The first element in document order that is nested more than `limit` levels deep, counting `root` as level 1
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null)
+pub(crate) unsafe fn first_element_deeper_than(
+    root: xmlNodePtr,
+    limit: usize,
+) -> Option<xmlNodePtr> {
+    let mut stack = vec![(root, 1)];
+
+    while let Some((node, depth)) = stack.pop() {
+        if node.is_null() || (*node).type_ != xmlElementType_XML_ELEMENT_NODE {
+            continue;
+        }
+        if depth > limit {
+            return Some(node);
+        }
+
+        let first_child = stack.len();
+        let mut child = (*node).children;
+        while !child.is_null() {
+            stack.push((child, depth + 1));
+            child = (*child).next;
+        }
+        stack[first_child..].reverse();
+    }
+
+    None
+}
+
+/// How many levels deep elements are nested at or under `root`, counting `root` as level 1 (0 if it's null or not an
+/// element)
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null)
+pub(crate) unsafe fn depth(root: xmlNodePtr) -> usize {
+    let mut deepest = 0;
+    let mut stack = vec![(root, 1)];
+
+    while let Some((node, depth)) = stack.pop() {
+        if node.is_null() || (*node).type_ != xmlElementType_XML_ELEMENT_NODE {
+            continue;
+        }
+        deepest = deepest.max(depth);
+
+        let mut child = (*node).children;
+        while !child.is_null() {
+            stack.push((child, depth + 1));
+            child = (*child).next;
+        }
+    }
+
+    deepest
+}
+
+/// The first element at or under `root`, in document order, that has both child elements and text other than whitespace
+///
+/// Text in CDATA sections counts as text. Whitespace is what XML counts as whitespace: spaces, tabs and line breaks.
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null). The returned pointer is only valid as long as its document is.
+pub(crate) unsafe fn first_mixed_content(root: xmlNodePtr) -> Option<xmlNodePtr> {
+    elements(root).into_iter().find(|&element| {
+        let (mut has_element, mut has_text) = (false, false);
+        let mut child = (*element).children;
+        while !child.is_null() {
+            let child_type = (*child).type_;
+            if child_type == xmlElementType_XML_ELEMENT_NODE {
+                has_element = true;
+            } else if (child_type == xmlElementType_XML_TEXT_NODE
+                || child_type == xmlElementType_XML_CDATA_SECTION_NODE)
+                && !(*child).content.is_null()
+            {
+                has_text |= CStr::from_ptr((*child).content as *const _)
+                    .to_bytes()
+                    .iter()
+                    .any(|byte| !matches!(byte, b' ' | b'\t' | b'\n' | b'\r'));
+            }
+            child = (*child).next;
+        }
+        has_element && has_text
+    })
+}
+
+/// Remove every text node at or under `root` that is only whitespace and has an element sibling, except under
+/// `xml:space="preserve"`
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null) that nothing else is looking at while it's modified
+pub(crate) unsafe fn remove_whitespace_between_elements(root: xmlNodePtr) {
+    let mut whitespace = vec![];
+    for element in elements(root) {
+        if xmlNodeGetSpacePreserve(element) == 1 {
+            continue;
+        }
+        let mut has_element = false;
+        let mut text = vec![];
+        let mut child = (*element).children;
+        while !child.is_null() {
+            if (*child).type_ == xmlElementType_XML_ELEMENT_NODE {
+                has_element = true;
+            } else if (*child).type_ == xmlElementType_XML_TEXT_NODE
+                && ((*child).content.is_null()
+                    || CStr::from_ptr((*child).content as *const _)
+                        .to_bytes()
+                        .iter()
+                        .all(|byte| matches!(byte, b' ' | b'\t' | b'\n' | b'\r')))
+            {
+                text.push(child);
+            }
+            child = (*child).next;
+        }
+        if has_element {
+            whitespace.extend(text);
+        }
+    }
+
+    for text in whitespace {
+        xmlUnlinkNode(text);
+        xmlFreeNode(text);
+    }
+}
+
+/// Namespace URI (empty without a namespace) and local name of `element`
+///
+/// # Safety
+///
+/// `element` must point to a valid element
+pub(crate) unsafe fn expanded_name(element: xmlNodePtr) -> (String, String) {
+    let name = CStr::from_ptr((*element).name as *const _)
+        .to_string_lossy()
+        .into_owned();
+    let ns = (*element).ns;
+    if ns.is_null() || (*ns).href.is_null() {
+        return (String::new(), name);
+    }
+    let uri = CStr::from_ptr((*ns).href as *const _).to_string_lossy();
+    (uri.into_owned(), name)
+}
+
+/// Line `node` is on, if libxml2 knows it
+///
+/// # Safety
+///
+/// `node` must point to a valid node
+pub(crate) unsafe fn line_of(node: xmlNodePtr) -> Option<u32> {
+    u32::try_from(xmlGetLineNo(node))
+        .ok()
+        .filter(|&line| line > 0)
+}
+
+/// Qualified name of `element`, with its namespace prefix if it has one
+///
+/// # Safety
+///
+/// `element` must point to a valid element
+pub(crate) unsafe fn qualified_name(element: xmlNodePtr) -> String {
+    let name = CStr::from_ptr((*element).name as *const _).to_string_lossy();
+    let ns = (*element).ns;
+    if ns.is_null() || (*ns).prefix.is_null() {
+        return name.into_owned();
+    }
+    let prefix = CStr::from_ptr((*ns).prefix as *const _).to_string_lossy();
+    format!("{prefix}:{name}")
+}
+
+/// The first comment in document order that is `node` or under it
+///
+/// # Safety
+///
+/// `node` must point to a valid node (or be null), which may also be a document
+pub(crate) unsafe fn first_comment(node: xmlNodePtr) -> Option<xmlNodePtr> {
+    comments_until(node, true).pop()
+}
+
+/// Every comment that is `node` or under it, in document order
+///
+/// # Safety
+///
+/// `node` must point to a valid node (or be null), which may also be a document. The returned pointers are only valid
+/// as long as its document is.
+pub(crate) unsafe fn comments(node: xmlNodePtr) -> Vec<xmlNodePtr> {
+    comments_until(node, false)
+}
+
+/// Comments that are `node` or under it, in document order, stopping after the first one if `first_only`
+unsafe fn comments_until(node: xmlNodePtr, first_only: bool) -> Vec<xmlNodePtr> {
+    let mut comments = vec![];
+    let mut stack = vec![node];
+
+    while let Some(node) = stack.pop() {
+        if node.is_null() {
+            continue;
+        }
+        if (*node).type_ == xmlElementType_XML_COMMENT_NODE {
+            comments.push(node);
+            if first_only {
+                break;
+            }
+            continue;
+        }
+        // attributes and entity references can't contain comments that get canonicalized
+        let node_type = (*node).type_;
+        if node_type != xmlElementType_XML_ELEMENT_NODE
+            && node_type != xmlElementType_XML_DOCUMENT_NODE
+        {
+            continue;
+        }
+
+        let first_child = stack.len();
+        let mut child = (*node).children;
+        while !child.is_null() {
+            stack.push(child);
+            child = (*child).next;
+        }
+        stack[first_child..].reverse();
+    }
+
+    comments
+}
+
+/// The first control character (other than tab, line feed and carriage return) in text or attribute values at or under
+/// `node`, in document order, and the node it's in (the element, for attribute values)
+///
+/// # Safety
+///
+/// `node` must point to a valid node (or be null), which may also be a document
+pub(crate) unsafe fn first_control_character(node: xmlNodePtr) -> Option<(xmlNodePtr, char)> {
+    let find_in = |content: *const xmlChar| {
+        if content.is_null() {
+            return None;
+        }
+        CStr::from_ptr(content as *const _)
+            .to_string_lossy()
+            .chars()
+            .find(|&char| char.is_control() && !matches!(char, '\t' | '\n' | '\r'))
+    };
+    let mut stack = vec![node];
+
+    while let Some(node) = stack.pop() {
+        if node.is_null() {
+            continue;
+        }
+        let node_type = (*node).type_;
+        if node_type == xmlElementType_XML_TEXT_NODE
+            || node_type == xmlElementType_XML_CDATA_SECTION_NODE
+        {
+            if let Some(char) = find_in((*node).content) {
+                return Some((node, char));
+            }
+            continue;
+        }
+        if node_type != xmlElementType_XML_ELEMENT_NODE
+            && node_type != xmlElementType_XML_DOCUMENT_NODE
+        {
+            continue;
+        }
+
+        if node_type == xmlElementType_XML_ELEMENT_NODE {
+            let mut attribute = (*node).properties;
+            while !attribute.is_null() {
+                let mut value = (*attribute).children;
+                while !value.is_null() {
+                    if let Some(char) = find_in((*value).content) {
+                        return Some((node, char));
+                    }
+                    value = (*value).next;
+                }
+                attribute = (*attribute).next;
+            }
+        }
+
+        let first_child = stack.len();
+        let mut child = (*node).children;
+        while !child.is_null() {
+            stack.push(child);
+            child = (*child).next;
+        }
+        stack[first_child..].reverse();
+    }
+
+    None
+}
+
+/// Something libxml2 parses, but refuses to canonicalize, see [first_uncanonicalizable]
+pub(crate) enum Uncanonicalizable {
+    /// A namespace declared on this element has a URI without a scheme
+    RelativeNamespace(xmlNodePtr, String),
+    /// A namespace declared on this element has a URI libxml2 can't parse
+    InvalidNamespace(xmlNodePtr, String),
+    /// An entity reference that wasn't expanded when parsing
+    EntityReference(xmlNodePtr, String),
+}
+
+/// The first node at or under `root` that libxml2 refuses to canonicalize, in the order libxml2 visits them
+///
+/// Mirrors the checks of libxml2's `c14n.c`, which reports these only as a message and a generic return code.
+/// Namespace URIs are checked on every element, whether or not it ends up in the output.
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null). The returned pointers are only valid as long as its document is.
+pub(crate) unsafe fn first_uncanonicalizable(root: xmlNodePtr) -> Option<Uncanonicalizable> {
+    for element in elements(root) {
+        let mut ns: xmlNsPtr = (*element).nsDef;
+        while !ns.is_null() {
+            let href = (*ns).href;
+            if !href.is_null() && *href != 0 {
+                let uri = || {
+                    CStr::from_ptr(href as *const _)
+                        .to_string_lossy()
+                        .into_owned()
+                };
+                let parsed = xmlParseURI(href as *const _);
+                if parsed.is_null() {
+                    return Some(Uncanonicalizable::InvalidNamespace(element, uri()));
+                }
+                let relative = (*parsed).scheme.is_null() || *(*parsed).scheme == 0;
+                xmlFreeURI(parsed);
+                if relative {
+                    return Some(Uncanonicalizable::RelativeNamespace(element, uri()));
+                }
+            }
+            ns = (*ns).next;
+        }
+
+        let mut child = (*element).children;
+        while !child.is_null() {
+            if (*child).type_ == xmlElementType_XML_ENTITY_REF_NODE {
+                let name = CStr::from_ptr((*child).name as *const _)
+                    .to_string_lossy()
+                    .into_owned();
+                return Some(Uncanonicalizable::EntityReference(child, name));
+            }
+            child = (*child).next;
+        }
+    }
+    None
+}
+
+/// Replace references to internal entities under `node` with copies of the entities' content, merging the text around them
+///
+/// Only entities whose content libxml2 already parsed are expanded, nothing is ever loaded. References to external
+/// entities are left alone. libxml2 has already rejected entity loops and excessive amplification while parsing.
+///
+/// # Safety
+///
+/// `node` must point to a valid element or document, that nothing else is looking at while it's modified
+pub(crate) unsafe fn expand_internal_entities(node: xmlNodePtr) {
+    let mut stack = vec![node];
+
+    while let Some(parent) = stack.pop() {
+        let mut child = (*parent).children;
+        while !child.is_null() {
+            if (*child).type_ == xmlElementType_XML_ELEMENT_NODE {
+                stack.push(child);
+            }
+            let Some(entity) = internal_entity(child) else {
+                child = (*child).next;
+                continue;
+            };
+
+            let before = (*child).prev;
+            let mut copy = xmlDocCopyNodeList((*child).doc, (*entity).children);
+            while !copy.is_null() {
+                let following = (*copy).next;
+                xmlAddPrevSibling(child, copy);
+                copy = following;
+            }
+            xmlUnlinkNode(child);
+            xmlFreeNode(child);
+
+            // continue with the copied content, which may contain elements and further references
+            child = if before.is_null() {
+                (*parent).children
+            } else {
+                (*before).next
+            };
+        }
+
+        merge_adjacent_text((*parent).children);
+    }
+}
+
+/// Whether [expand_internal_entities] would expand anything under `node`
+///
+/// # Safety
+///
+/// `node` must point to a valid element or document
+pub(crate) unsafe fn contains_internal_entity_reference(node: xmlNodePtr) -> bool {
+    let mut stack = vec![node];
+
+    while let Some(parent) = stack.pop() {
+        let mut child = (*parent).children;
+        while !child.is_null() {
+            if (*child).type_ == xmlElementType_XML_ELEMENT_NODE {
+                stack.push(child);
+            } else if internal_entity(child).is_some() {
+                return true;
+            }
+            child = (*child).next;
+        }
+    }
+    false
+}
+
+/// The entity `node` refers to, if it is a reference to an internal entity whose content libxml2 parsed
+unsafe fn internal_entity(node: xmlNodePtr) -> Option<xmlEntityPtr> {
+    if (*node).type_ != xmlElementType_XML_ENTITY_REF_NODE {
+        return None;
+    }
+    // for entity references, libxml2 points `children` at the entity declaration
+    let entity = (*node).children as xmlEntityPtr;
+    let is_internal = !entity.is_null()
+        && (*entity).etype == xmlEntityType_XML_INTERNAL_GENERAL_ENTITY
+        && !(*entity).children.is_null();
+    is_internal.then_some(entity)
+}
+
+/// A deep copy of `document`, including the content libxml2 parsed for its internal entities, which `xmlCopyDoc` leaves
+/// out (references to them in attribute values would come out empty otherwise)
+///
+/// # Safety
+///
+/// `document` must point to a valid document. The copy, null if libxml2 failed to allocate it, must be freed by the caller.
+pub(crate) unsafe fn copy_document(document: xmlDocPtr) -> xmlDocPtr {
+    let copy = xmlCopyDoc(document, 1);
+    if copy.is_null() || (*copy).intSubset.is_null() {
+        return copy;
+    }
+
+    let mut declaration = (*(*copy).intSubset).children;
+    while !declaration.is_null() {
+        let entity = declaration as xmlEntityPtr;
+        declaration = (*declaration).next;
+        if (*entity).type_ != xmlElementType_XML_ENTITY_DECL
+            || (*entity).etype != xmlEntityType_XML_INTERNAL_GENERAL_ENTITY
+            || !(*entity).children.is_null()
+        {
+            continue;
+        }
+        let original = xmlGetDocEntity(document, (*entity).name);
+        if original.is_null() || (*original).children.is_null() {
+            continue;
+        }
+
+        // xmlFreeEntity frees the content if the entity owns it and is the parent of its first node
+        let content = xmlDocCopyNodeList(copy, (*original).children);
+        let mut node = content;
+        while !node.is_null() {
+            (*node).parent = entity as xmlNodePtr;
+            (*entity).last = node;
+            node = (*node).next;
+        }
+        (*entity).children = content;
+        (*entity).owner = 1;
+    }
+    copy
+}
+
+/// Merge every run of adjacent text nodes starting at `first` or any of its following siblings into one node
+unsafe fn merge_adjacent_text(first: xmlNodePtr) {
+    let mut node = first;
+    while !node.is_null() {
+        let next = (*node).next;
+        if !next.is_null()
+            && (*node).type_ == xmlElementType_XML_TEXT_NODE
+            && (*next).type_ == xmlElementType_XML_TEXT_NODE
+        {
+            xmlNodeAddContent(node, (*next).content);
+            xmlUnlinkNode(next);
+            xmlFreeNode(next);
+        } else {
+            node = next;
+        }
+    }
+}
+
+/// Prefixes of the namespaces declared on `element`, in document order (empty for the default namespace)
+///
+/// # Safety
+///
+/// `element` must point to a valid element
+pub(crate) unsafe fn declared_prefixes(element: xmlNodePtr) -> Vec<String> {
+    declared_namespaces(element)
+        .into_iter()
+        .map(|(prefix, _)| prefix)
+        .collect()
+}
+
+/// Prefixes and URIs of the namespaces declared on `element`, in document order (the prefix is empty for the default
+/// namespace, the URI for an undeclaration like `xmlns=""`)
+///
+/// # Safety
+///
+/// `element` must point to a valid element
+pub(crate) unsafe fn declared_namespaces(element: xmlNodePtr) -> Vec<(String, String)> {
+    let string = |value: *const xmlChar| {
+        if value.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(value as *const _)
+                .to_string_lossy()
+                .into_owned()
+        }
+    };
+
+    let mut namespaces = vec![];
+    let mut ns: xmlNsPtr = (*element).nsDef;
+    while !ns.is_null() {
+        namespaces.push((string((*ns).prefix), string((*ns).href)));
+        ns = (*ns).next;
+    }
+    namespaces
+}
+
+/// The first element at or under `root`, in document order, that binds a namespace prefix to a different URI than the
+/// nearest ancestor binding it (including ancestors of `root`), and that prefix
+///
+/// The default namespace isn't a prefix, so redefining it doesn't count.
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null). The returned pointer is only valid as long as its document is.
+pub(crate) unsafe fn first_prefix_redefinition(root: xmlNodePtr) -> Option<(xmlNodePtr, String)> {
+    elements(root).into_iter().find_map(|element| {
+        declared_namespaces(element)
+            .into_iter()
+            .filter(|(prefix, _)| !prefix.is_empty())
+            .find(|(prefix, uri)| {
+                let inherited = ancestors_or_self((*element).parent)
+                    .take_while(|&ancestor| (*ancestor).type_ == xmlElementType_XML_ELEMENT_NODE)
+                    .find_map(|ancestor| {
+                        declared_namespaces(ancestor)
+                            .into_iter()
+                            .find(|(ancestor_prefix, _)| ancestor_prefix == prefix)
+                    });
+                inherited.is_some_and(|(_, inherited_uri)| inherited_uri != *uri)
+            })
+            .map(|(prefix, _)| (element, prefix))
+    })
+}
+
+/// Whether `element` has the given local name and namespace URI (`""` for no namespace)
+///
+/// # Safety
+///
+/// `element` must point to a valid element
+pub(crate) unsafe fn has_name(element: xmlNodePtr, namespace_uri: &str, local_name: &str) -> bool {
+    name_matches((*element).name, (*element).ns, namespace_uri, local_name)
+}
+
+/// Whether a node's `name` and `ns` are the given local name and namespace URI
+unsafe fn name_matches(
+    name: *const xmlChar,
+    ns: xmlNsPtr,
+    namespace_uri: &str,
+    local_name: &str,
+) -> bool {
+    let name = CStr::from_ptr(name as *const _);
+    if name.to_bytes() != local_name.as_bytes() {
+        return false;
+    }
+
+    let uri = if ns.is_null() || (*ns).href.is_null() {
+        &[]
+    } else {
+        CStr::from_ptr((*ns).href as *const _).to_bytes()
+    };
+    uri == namespace_uri.as_bytes()
+}
+
+/// Remove every element with the given local name and namespace URI under `root` (but not `root` itself), together with
+/// everything under them, returning how many matched
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null), that nothing else is looking at while it's modified
+pub(crate) unsafe fn remove_elements(
+    root: xmlNodePtr,
+    namespace_uri: &str,
+    local_name: &str,
+) -> usize {
+    let matching: Vec<xmlNodePtr> = elements(root)
+        .into_iter()
+        .skip(1)
+        .filter(|&element| has_name(element, namespace_uri, local_name))
+        .collect();
+
+    // nested matches are freed with their ancestor, so only unlink the outermost ones
+    let matching_set: HashSet<xmlNodePtr> = matching.iter().copied().collect();
+    let outermost: Vec<xmlNodePtr> = matching
+        .iter()
+        .copied()
+        .filter(|&element| {
+            !ancestors_or_self((*element).parent).any(|ancestor| matching_set.contains(&ancestor))
+        })
+        .collect();
+    for element in outermost {
+        xmlUnlinkNode(element);
+        xmlFreeNode(element);
+    }
+
+    matching.len()
+}
+
+/// Remove every attribute with the given local name and namespace URI from `root` and all elements under it, returning
+/// how many were removed
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null), that nothing else is looking at while it's modified
+pub(crate) unsafe fn remove_attributes(
+    root: xmlNodePtr,
+    namespace_uri: &str,
+    local_name: &str,
+) -> usize {
+    let mut removed = 0;
+    for element in elements(root) {
+        let mut attribute = (*element).properties;
+        while !attribute.is_null() {
+            let next = (*attribute).next;
+            if name_matches(
+                (*attribute).name,
+                (*attribute).ns,
+                namespace_uri,
+                local_name,
+            ) {
+                xmlRemoveProp(attribute);
+                removed += 1;
+            }
+            attribute = next;
+        }
+    }
+    removed
+}
+
+/// Whether `ns` (which may be null) is the namespace `uri`
+///
+/// # Safety
+///
+/// `ns` must point to a valid namespace (or be null)
+pub(crate) unsafe fn in_namespace(ns: xmlNsPtr, uri: &str) -> bool {
+    !ns.is_null()
+        && !(*ns).href.is_null()
+        && CStr::from_ptr((*ns).href as *const _).to_bytes() == uri.as_bytes()
+}
+
+/// Declarations of the namespace `uri` on `root` and all elements under it that no element is in
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null)
+pub(crate) unsafe fn declarations_unused_by_elements(
+    root: xmlNodePtr,
+    uri: &str,
+) -> HashSet<xmlNsPtr> {
+    let elements = elements(root);
+    let used: HashSet<xmlNsPtr> = elements.iter().map(|&element| (*element).ns).collect();
+
+    let mut unused = HashSet::new();
+    for &element in &elements {
+        let mut ns = (*element).nsDef;
+        while !ns.is_null() {
+            if in_namespace(ns, uri) && !used.contains(&ns) {
+                unused.insert(ns);
+            }
+            ns = (*ns).next;
+        }
+    }
+    unused
+}
+
+/// `root` and all elements under it, by their IDs
+///
+/// An attribute is an ID as in [ids]. Several elements may claim the same ID, they're listed in document order.
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null). The returned pointers are only valid as long as its document is.
+pub(crate) unsafe fn elements_by_id(root: xmlNodePtr) -> HashMap<String, Vec<xmlNodePtr>> {
+    let mut elements_by_id: HashMap<String, Vec<xmlNodePtr>> = HashMap::new();
+    for (id, element) in ids(root) {
+        elements_by_id.entry(id).or_default().push(element);
+    }
+    elements_by_id
+}
+
+/// Every ID on `root` and all elements under it, with the element it's on, in document order (and attribute order on
+/// the same element)
+///
+/// An attribute is an ID if libxml2 knows it is (`xml:id`, or declared as one in the DTD), or if it's an unqualified
+/// attribute named like in [ID_ATTRIBUTE_NAMES].
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null). The returned pointers are only valid as long as its document is.
+pub(crate) unsafe fn ids(root: xmlNodePtr) -> Vec<(String, xmlNodePtr)> {
+    ids_including(root, &[])
+}
+
+/// Like [ids], also treating attributes named like one of `extra` as IDs
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null). The returned pointers are only valid as long as its document is.
+pub(crate) unsafe fn ids_including(root: xmlNodePtr, extra: &[QName]) -> Vec<(String, xmlNodePtr)> {
+    let mut ids = vec![];
+
+    for element in elements(root) {
+        let mut attribute = (*element).properties;
+        while !attribute.is_null() {
+            let name = CStr::from_ptr((*attribute).name as *const _).to_bytes();
+            let is_id = xmlIsID((*element).doc, element, attribute) != 0
+                || ((*attribute).ns.is_null()
+                    && ID_ATTRIBUTE_NAMES
+                        .iter()
+                        .any(|id_name| id_name.as_bytes() == name))
+                || extra.iter().any(|extra| {
+                    name_matches(
+                        (*attribute).name,
+                        (*attribute).ns,
+                        &extra.namespace_uri,
+                        &extra.local_name,
+                    )
+                });
+
+            if is_id {
+                ids.push((attribute_value(attribute), element));
+            }
+
+            attribute = (*attribute).next;
+        }
+    }
+
+    ids
+}
+
+/// Elements under `root` (including `root` itself) with an attribute named `local_name` in `namespace_uri` (`""` for
+/// unqualified attributes) whose value is `value`, in document order
+///
+/// # Safety
+///
+/// `root` must point to a valid node (or be null). The returned pointers are only valid as long as its document is.
+pub(crate) unsafe fn elements_with_attribute(
+    root: xmlNodePtr,
+    namespace_uri: &str,
+    local_name: &str,
+    value: &str,
+) -> Vec<xmlNodePtr> {
+    elements(root)
+        .into_iter()
+        .filter(|&element| {
+            let mut attribute = (*element).properties;
+            while !attribute.is_null() {
+                if name_matches(
+                    (*attribute).name,
+                    (*attribute).ns,
+                    namespace_uri,
+                    local_name,
+                ) && attribute_value(attribute) == value
+                {
+                    return true;
+                }
+                attribute = (*attribute).next;
+            }
+            false
+        })
+        .collect()
+}
+
+/// Value of `attribute`, with entity and character references replaced
+unsafe fn attribute_value(attribute: xmlAttrPtr) -> String {
+    let value = xmlNodeListGetString((*attribute).doc, (*attribute).children, 1);
+    if value.is_null() {
+        String::new()
+    } else {
+        // SAFETY: xmlNodeListGetString allocates a fresh string for us
+        let value = XmlOutput::from_raw(value);
+        String::from_utf8_lossy(value.as_bytes()).into_owned()
+    }
+}
@@ -0,0 +1,102 @@
+//! The libxml2 version linked at runtime, and what's known about it
+
+use libxml::bindings::__xmlParserVersion;
+use std::ffi::CStr;
+use std::fmt;
+
+/// Version of the libxml2 library linked at runtime, see [libxml_version]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct LibxmlVersion(u32);
+
+/// Versions (up to but not including the first one that's fine) with known problems
+const KNOWN_ISSUES: &[(u32, &str)] = &[(
+    20700,
+    "libxml2 before 2.7.0 doesn't support C14N 1.1, CanonicalizationMode::Canonical1_1 is unavailable",
+)];
+
+impl LibxmlVersion {
+    /// The version as libxml2 encodes it, e.g. `20914` for 2.9.14
+    pub fn number(self) -> u32 {
+        self.0
+    }
+
+    /// Major, minor and patch version, e.g. `(2, 9, 14)`
+    pub fn parts(self) -> (u32, u32, u32) {
+        (self.0 / 10_000, self.0 / 100 % 100, self.0 % 100)
+    }
+
+    /// If this version is known to produce output that differs from the specs (or from other versions),
+    /// a description of the problem
+    pub fn known_issue(self) -> Option<&'static str> {
+        KNOWN_ISSUES
+            .iter()
+            .find(|&&(fixed_in, _)| self.0 < fixed_in)
+            .map(|&(_, issue)| issue)
+    }
+}
+
+impl fmt::Display for LibxmlVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (major, minor, patch) = self.parts();
+        write!(f, "{major}.{minor}.{patch}")
+    }
+}
+
+/// Version of the libxml2 library linked at runtime, or [None] if it doesn't report one in the usual format
+///
+/// Canonical output is expected to be identical with every supported version, see the Readme for which ones are tested.
+/// Check [LibxmlVersion::known_issue] to warn about versions that are known not to be.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::libxml_version;
+///
+/// let version = libxml_version().unwrap();
+/// if let Some(issue) = version.known_issue() {
+///     eprintln!("warning: libxml2 {version}: {issue}");
+/// }
+/// ```
+pub fn libxml_version() -> Option<LibxmlVersion> {
+    // SAFETY: xmlParserVersion is a static string, set up before any other libxml2 function could run
+    unsafe {
+        let version = *__xmlParserVersion();
+        if version.is_null() {
+            return None;
+        }
+        parse_version(CStr::from_ptr(version).to_str().ok()?)
+    }
+}
+
+/// Parse libxml2's version string, e.g. "20914" or "21000-GITv2.10.0"
+fn parse_version(version: &str) -> Option<LibxmlVersion> {
+    let digits = version
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(version, |end| &version[..end]);
+    digits.parse().ok().map(LibxmlVersion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let version = parse_version("21000-GITv2.10.0").unwrap();
+        assert_eq!(version.number(), 21000);
+        assert_eq!(version.parts(), (2, 10, 0));
+        assert_eq!(version.to_string(), "2.10.0");
+        assert_eq!(version.known_issue(), None);
+
+        assert_eq!(parse_version("20914").unwrap().to_string(), "2.9.14");
+        assert!(parse_version("20627").unwrap().known_issue().is_some());
+        assert_eq!(parse_version("GIT"), None);
+    }
+
+    #[test]
+    fn linked_version() {
+        let version = libxml_version().unwrap();
+        assert!(version.parts().0 >= 2);
+        assert_eq!(version.known_issue(), None, "{version}");
+    }
+}
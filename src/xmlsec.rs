@@ -0,0 +1,64 @@
+//! Canonicalizing like the command-line tools of xmlsec and libxml2, for porting scripts that use them
+
+use crate::{
+    canonicalize_xml, CanonicalizationError, CanonicalizationMode, CanonicalizationOptions,
+};
+
+/// Parse specified XML document and canonicalize it the way `xmllint --c14n` does
+///
+/// `xmlsec1` has no command that only canonicalizes, so scripts built around it hash or diff what libxml2's `xmllint
+/// --c14n` prints (xmlsec canonicalizes with libxml2 as well). That is inclusive canonicalization 1.0 with comments, of
+/// the document with internal entities expanded and the attributes its DTD defaults added, which are the
+/// [CanonicalizationOptions] used here. Unlike `xmllint`, this never loads external entities, references to which fail
+/// with [CanonicalizationError::UnexpandedEntityReference].
+///
+/// Keep in mind that this isn't what XML Signature uses by default: `<ds:SignedInfo>` and references usually specify
+/// exclusive canonicalization without comments, see [canonicalize_xml].
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::canonicalize_xmlsec_compat;
+///
+/// let document = "<!DOCTYPE a [<!ENTITY e 'é'>]><a xmlns:x='urn:x'>&e;<!-- kept --></a>";
+/// let canonicalized = canonicalize_xmlsec_compat(document).unwrap();
+///
+/// assert_eq!(canonicalized, r#"<a xmlns:x="urn:x">é<!-- kept --></a>"#);
+/// ```
+pub fn canonicalize_xmlsec_compat(
+    document: impl AsRef<str>,
+) -> Result<String, CanonicalizationError> {
+    canonicalize_xml(
+        document,
+        CanonicalizationOptions {
+            mode: CanonicalizationMode::Canonical1_0,
+            keep_comments: true,
+            coalesce_text: true,
+            dtd_default_attributes: true,
+            ..Default::default()
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_xmllint() {
+        assert_eq!(
+            canonicalize_xmlsec_compat(include_str!("samples/xmlsec_compat/1_input.xml")).unwrap(),
+            include_str!("samples/xmlsec_compat/1_output.xml")
+        );
+
+        assert_eq!(
+            canonicalize_xmlsec_compat(
+                r#"<!DOCTYPE a [<!ENTITY e SYSTEM "file:///etc/hostname">]><a>&e;</a>"#
+            ),
+            Err(CanonicalizationError::UnexpandedEntityReference {
+                name: "e".to_owned(),
+                line: Some(1)
+            })
+        );
+    }
+}
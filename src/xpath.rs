@@ -0,0 +1,306 @@
+//! Evaluating XPath expressions against parsed documents
+
+use crate::diagnostics::{self, Diagnostic, DiagnosticLevel};
+use crate::{CanonicalizationError, CanonicalizationErrorCode};
+use libxml::bindings::{
+    xmlChar, xmlDocPtr, xmlElementType_XML_NAMESPACE_DECL, xmlNodePtr, xmlNsPtr, xmlValidateNCName,
+    xmlXPathContextPtr, xmlXPathCtxtCompile, xmlXPathEvalExpression, xmlXPathFreeCompExpr,
+    xmlXPathFreeContext, xmlXPathFreeObject, xmlXPathNewContext, xmlXPathNodeSetSort,
+    xmlXPathObjectType_XPATH_NODESET, xmlXPathRegisterNs,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::{c_char, CStr, CString};
+use std::ptr::null_mut;
+use std::slice;
+
+/// Check that `expression` is syntactically valid XPath, without evaluating it (so whether it selects nodes isn't known yet)
+///
+/// Fails with [CanonicalizationError::InvalidXPath] if it isn't.
+pub(crate) fn compile(expression: &str) -> Result<(), CanonicalizationError> {
+    let c_expression = CString::new(expression).map_err(|_| invalid_xpath(expression, vec![]))?;
+
+    // SAFETY: the expression is a valid null-terminated string, and the context and compiled expression are freed right away
+    let (compiled, diagnostics) = diagnostics::collect_diagnostics(|| unsafe {
+        // without a context, libxml2 prints errors instead of reporting them to the error handler
+        let context = xmlXPathNewContext(null_mut());
+        if context.is_null() {
+            return null_mut();
+        }
+        let compiled = xmlXPathCtxtCompile(context, c_expression.as_ptr() as *const xmlChar);
+        xmlXPathFreeContext(context);
+        compiled
+    });
+    if compiled.is_null() {
+        return Err(invalid_xpath(expression, diagnostics));
+    }
+    // SAFETY: compiled just above, and not used after
+    unsafe { xmlXPathFreeCompExpr(compiled) };
+    Ok(())
+}
+
+/// [CanonicalizationError::InvalidXPath] for `expression`, with the first error in what libxml2 reported
+fn invalid_xpath(expression: &str, diagnostics: Vec<Diagnostic>) -> CanonicalizationError {
+    CanonicalizationError::InvalidXPath {
+        expression: expression.to_owned(),
+        message: diagnostics
+            .into_iter()
+            .find(|diagnostic| diagnostic.level >= DiagnosticLevel::Error)
+            .map(|diagnostic| diagnostic.message),
+    }
+}
+
+/// Whether `name` is an XML name without a colon, as namespace prefixes are
+pub(crate) fn is_ncname(name: &str) -> bool {
+    let Ok(c_name) = CString::new(name) else {
+        return false;
+    };
+    // SAFETY: the name is a valid null-terminated string
+    unsafe { xmlValidateNCName(c_name.as_ptr() as *const xmlChar, 0) == 0 }
+}
+
+/// An XPath evaluation context for one document, with namespace prefixes bound, freed on drop
+///
+/// Can evaluate any number of expressions, so that prefixes only need to be registered once.
+pub(crate) struct XPathContext(xmlXPathContextPtr);
+
+impl XPathContext {
+    /// Create a context for `document`, binding each prefix in `namespaces` to its URI
+    ///
+    /// # Safety
+    ///
+    /// `document` must point to a valid document, which must outlive the context
+    pub(crate) unsafe fn new(
+        document: xmlDocPtr,
+        namespaces: &BTreeMap<String, String>,
+    ) -> Result<Self, CanonicalizationError> {
+        let context = xmlXPathNewContext(document);
+        if context.is_null() {
+            return Err(CanonicalizationErrorCode::new(-1)
+                .with_message(Some("could not create an XPath context".to_owned()))
+                .into());
+        }
+        let context = XPathContext(context);
+
+        for (prefix, uri) in namespaces {
+            let invalid = || CanonicalizationError::InvalidNamespacePrefix(prefix.clone());
+            if prefix.is_empty() {
+                // XPath 1.0 has no default namespace for element names
+                return Err(invalid());
+            }
+            let c_prefix = CString::new(prefix.as_str()).map_err(|_| invalid())?;
+            let c_uri = CString::new(uri.as_str()).map_err(|_| invalid())?;
+
+            let return_code = xmlXPathRegisterNs(
+                context.0,
+                c_prefix.as_ptr() as *const xmlChar,
+                c_uri.as_ptr() as *const xmlChar,
+            );
+            if return_code != 0 {
+                return Err(invalid());
+            }
+        }
+
+        Ok(context)
+    }
+
+    /// Evaluate `expression` and return the selected nodes in document order
+    ///
+    /// The returned pointers are only valid as long as the document is. Namespace nodes are only valid until the
+    /// evaluation result is freed, so expressions selecting them must use [select_node_set](Self::select_node_set).
+    pub(crate) fn select_nodes(
+        &self,
+        expression: &str,
+    ) -> Result<Vec<xmlNodePtr>, CanonicalizationError> {
+        self.evaluate(expression, <[xmlNodePtr]>::to_vec)
+    }
+
+    /// Evaluate `expression` and return the selected nodes as a document subset, including any namespace nodes
+    pub(crate) fn select_node_set(
+        &self,
+        expression: &str,
+    ) -> Result<NodeSet, CanonicalizationError> {
+        self.evaluate(expression, |selected| {
+            let mut node_set = NodeSet::default();
+            for &node in selected {
+                // SAFETY: the nodes are valid until the evaluation result is freed, after this returns
+                unsafe {
+                    if (*node).type_ == xmlElementType_XML_NAMESPACE_DECL {
+                        // libxml2 copies namespace nodes, pointing their `next` to the element they're on
+                        let namespace = node as xmlNsPtr;
+                        node_set
+                            .namespaces
+                            .insert(((*namespace).next as xmlNodePtr, prefix((*namespace).prefix)));
+                    } else {
+                        node_set.nodes.insert(node);
+                    }
+                }
+            }
+            node_set
+        })
+    }
+
+    /// Evaluate `expression` and pass the selected nodes to `f`, in document order
+    fn evaluate<T>(
+        &self,
+        expression: &str,
+        f: impl FnOnce(&[xmlNodePtr]) -> T,
+    ) -> Result<T, CanonicalizationError> {
+        let c_expression =
+            CString::new(expression).map_err(|_| invalid_xpath(expression, vec![]))?;
+
+        unsafe {
+            let (object, diagnostics) = diagnostics::collect_diagnostics(|| {
+                xmlXPathEvalExpression(c_expression.as_ptr() as *const xmlChar, self.0)
+            });
+            if object.is_null() {
+                return Err(invalid_xpath(expression, diagnostics));
+            }
+
+            let result = if (*object).type_ != xmlXPathObjectType_XPATH_NODESET {
+                // e.g. `count(//a)` is a valid expression, but doesn't select anything
+                Err(invalid_xpath(expression, diagnostics))
+            } else {
+                let node_set = (*object).nodesetval;
+                if node_set.is_null() || (*node_set).nodeNr == 0 {
+                    Ok(f(&[]))
+                } else {
+                    // libxml2 sorts most node sets already, but not all, e.g. not every result of a reverse axis
+                    xmlXPathNodeSetSort(node_set);
+                    let len = usize::try_from((*node_set).nodeNr).unwrap();
+                    Ok(f(slice::from_raw_parts((*node_set).nodeTab, len)))
+                }
+            };
+
+            xmlXPathFreeObject(object);
+            result
+        }
+    }
+}
+
+/// Nodes selected by an XPath expression, to canonicalize as a document subset
+///
+/// Unlike a set of subtrees, this contains exactly the selected nodes: selecting an element doesn't select its attributes,
+/// namespace nodes or children.
+#[derive(Default)]
+pub(crate) struct NodeSet {
+    nodes: HashSet<xmlNodePtr>,
+    /// Namespace nodes, as the element they're on and their prefix
+    namespaces: HashSet<(xmlNodePtr, Option<Vec<u8>>)>,
+}
+
+impl NodeSet {
+    /// Whether `node` is in the set, `parent` being the element a namespace node is on (as libxml2 passes them to
+    /// [canonicalize_with_visibility](crate::execute::canonicalize_with_visibility))
+    ///
+    /// # Safety
+    ///
+    /// `node` must be a valid node or namespace
+    pub(crate) unsafe fn contains(&self, node: xmlNodePtr, parent: xmlNodePtr) -> bool {
+        if (*node).type_ == xmlElementType_XML_NAMESPACE_DECL {
+            let namespace = node as xmlNsPtr;
+            self.namespaces
+                .contains(&(parent, prefix((*namespace).prefix)))
+        } else {
+            self.nodes.contains(&node)
+        }
+    }
+}
+
+/// Bytes of a namespace prefix, [None] for the default namespace
+unsafe fn prefix(prefix: *const xmlChar) -> Option<Vec<u8>> {
+    (!prefix.is_null()).then(|| CStr::from_ptr(prefix as *const c_char).to_bytes().to_vec())
+}
+
+impl Drop for XPathContext {
+    fn drop(&mut self) {
+        // SAFETY: we own the context
+        unsafe { xmlXPathFreeContext(self.0) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn multiple_expressions_with_prefixes() {
+        let document = parse(r#"<r xmlns:s="urn:s"><s:a/><a/><s:a/></r>"#).unwrap();
+        let root = document.root_element();
+
+        unsafe {
+            let namespaces = BTreeMap::from([("n".to_owned(), "urn:s".to_owned())]);
+            let context = XPathContext::new((*root).doc, &namespaces).unwrap();
+
+            assert_eq!(context.select_nodes("//n:a").unwrap().len(), 2);
+            assert_eq!(context.select_nodes("//a").unwrap().len(), 1);
+            assert_eq!(context.select_nodes("/*").unwrap(), [root]);
+            assert_eq!(
+                context.select_nodes("//s:a"),
+                Err(CanonicalizationError::InvalidXPath {
+                    expression: "//s:a".to_owned(),
+                    message: Some("Undefined namespace prefix".to_owned()),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn document_order() {
+        let document = parse("<r><a><b/></a><c/></r>").unwrap();
+        let root = document.root_element();
+
+        unsafe {
+            let elements = crate::tree::elements(root);
+            let context = XPathContext::new((*root).doc, &BTreeMap::new()).unwrap();
+
+            for expression in [
+                "//c | //b | //a | /r",
+                "//b/ancestor-or-self::* | //c",
+                "//c/preceding::* | //c/.. | //c",
+            ] {
+                assert_eq!(
+                    context.select_nodes(expression).unwrap(),
+                    elements,
+                    "{expression}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compile_and_names() {
+        assert_eq!(compile("//a[@b = 'c']"), Ok(()));
+        assert_eq!(compile("count(//a)"), Ok(()));
+        assert_eq!(
+            compile("//a["),
+            Err(CanonicalizationError::InvalidXPath {
+                expression: "//a[".to_owned(),
+                message: Some("Invalid expression".to_owned()),
+            })
+        );
+        assert!(matches!(
+            compile("//a\0"),
+            Err(CanonicalizationError::InvalidXPath { message: None, .. })
+        ));
+
+        assert!(is_ncname("ds"));
+        assert!(is_ncname("é_1"));
+        for name in ["", "a:b", "1a", "a b", "#default", "a\0"] {
+            assert!(!is_ncname(name), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_prefix() {
+        let document = parse("<r/>").unwrap();
+        let root = document.root_element();
+
+        let namespaces = BTreeMap::from([(String::new(), "urn:s".to_owned())]);
+        let context = unsafe { XPathContext::new((*root).doc, &namespaces) };
+        assert!(matches!(
+            context,
+            Err(CanonicalizationError::InvalidNamespacePrefix(prefix)) if prefix.is_empty()
+        ));
+    }
+}
@@ -0,0 +1,430 @@
+//! Canonicalizing the result of an XSLT transform, enabled by the `xslt` feature, which links libxslt
+
+use crate::diagnostics::{self, Diagnostic, DiagnosticLevel};
+use crate::document::Document;
+use crate::entities::with_entity_loader;
+use crate::{CanonicalizationError, CanonicalizationOptions};
+use libxml::bindings::{
+    __xmlGenericError, __xmlGenericErrorContext, xmlDocPtr, xmlErrorDomain_XML_FROM_XSLT,
+    xmlGenericErrorFunc, xmlSetGenericErrorFunc,
+};
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::ptr::{null, null_mut};
+use std::sync::Once;
+
+type StylesheetPtr = *mut c_void;
+type TransformContextPtr = *mut c_void;
+type SecurityPrefsPtr = *mut c_void;
+type SecurityCheck =
+    unsafe extern "C" fn(SecurityPrefsPtr, TransformContextPtr, *const c_char) -> c_int;
+
+/// The variant of a libxslt failure, to give what was reported once libxslt returns
+type Failure = fn(Vec<Diagnostic>) -> CanonicalizationError;
+
+/// `xsltSecurityOption`, from `XSLT_SECPREF_READ_FILE` to `XSLT_SECPREF_WRITE_NETWORK`
+const SECURITY_OPTIONS: [c_int; 5] = [1, 2, 3, 4, 5];
+
+extern "C" {
+    fn open_memstream(buffer: *mut *mut c_char, size: *mut usize) -> *mut c_void;
+    fn fclose(file: *mut c_void) -> c_int;
+    fn free(pointer: *mut c_void);
+}
+
+#[link(name = "xslt")]
+extern "C" {
+    fn xsltSetGenericErrorFunc(ctx: *mut c_void, handler: xmlGenericErrorFunc);
+    fn xsltParseStylesheetDoc(doc: xmlDocPtr) -> StylesheetPtr;
+    fn xsltFreeStylesheet(style: StylesheetPtr);
+    fn xsltNewTransformContext(style: StylesheetPtr, doc: xmlDocPtr) -> TransformContextPtr;
+    fn xsltFreeTransformContext(ctxt: TransformContextPtr);
+    fn xsltApplyStylesheetUser(
+        style: StylesheetPtr,
+        doc: xmlDocPtr,
+        params: *const *const c_char,
+        output: *const c_char,
+        profile: *mut c_void,
+        ctxt: TransformContextPtr,
+    ) -> xmlDocPtr;
+    fn xsltNewSecurityPrefs() -> SecurityPrefsPtr;
+    fn xsltFreeSecurityPrefs(sec: SecurityPrefsPtr);
+    fn xsltSetSecurityPrefs(
+        sec: SecurityPrefsPtr,
+        option: c_int,
+        func: Option<SecurityCheck>,
+    ) -> c_int;
+    fn xsltSetCtxtSecurityPrefs(sec: SecurityPrefsPtr, ctxt: TransformContextPtr) -> c_int;
+    fn xsltSecurityForbid(
+        sec: SecurityPrefsPtr,
+        ctxt: TransformContextPtr,
+        value: *const c_char,
+    ) -> c_int;
+}
+
+/// Parse specified XML document, apply the XSLT `stylesheet` to it, and canonicalize the result
+///
+/// For XML Signature references with an XSLT transform. The stylesheet can't load anything: imports and includes make
+/// it fail to compile, and `document()` makes the transform fail, whatever the URI. It can't write files (e.g. with
+/// `exsl:document`) or create directories either.
+///
+/// Returns [CanonicalizationError::InvalidStylesheet] if libxslt can't compile the stylesheet, and
+/// [CanonicalizationError::TransformFailed] if applying it fails (e.g. on `<xsl:message terminate="yes">`), both with
+/// what libxslt and libxml2 reported. libxslt's error handler is global, so the first call sets it to libxml2's default
+/// one, which prints to the stream of the thread reporting (stderr unless changed with `xmlSetGenericErrorFunc`).
+///
+/// Both documents are parsed with `options`, and the result tree is canonicalized with them as well.
+///
+/// Example:
+///
+/// ```
+/// use xml_c14n::{canonicalize_xslt, CanonicalizationOptions};
+///
+/// let stylesheet = r#"<xsl:stylesheet version="1.0" xmlns:xsl="http://www.w3.org/1999/XSL/Transform">
+///   <xsl:template match="/"><total><xsl:value-of select="sum(//price)"/></total></xsl:template>
+/// </xsl:stylesheet>"#;
+///
+/// let canonicalized = canonicalize_xslt(
+///     "<order><price>2</price><price>3</price></order>",
+///     stylesheet,
+///     CanonicalizationOptions::default(),
+/// )
+/// .unwrap();
+///
+/// assert_eq!(canonicalized, "<total>5</total>");
+/// ```
+pub fn canonicalize_xslt(
+    document: impl AsRef<str>,
+    stylesheet: &str,
+    options: CanonicalizationOptions,
+) -> Result<String, CanonicalizationError> {
+    let document = document.as_ref();
+    options.check_input_size(document.len())?;
+    options.check_input_size(stylesheet.len())?;
+    let document = Document::read_for(document.as_bytes(), None, &options)?;
+    let stylesheet = Document::read(stylesheet.as_bytes(), None, options.parse_options())?;
+
+    let (result, diagnostics) = collect_xslt_diagnostics(|| {
+        // nothing is ever loaded, on top of libxslt's own checks below, which also make it fail
+        with_entity_loader(
+            |_, _| None,
+            || {
+                // SAFETY: the stylesheet takes ownership of its document if it compiles, and everything created
+                // here is freed before returning
+                unsafe {
+                    let stylesheet_document = stylesheet.into_raw();
+                    let compiled = xsltParseStylesheetDoc(stylesheet_document);
+                    if compiled.is_null() {
+                        drop(Document::from_raw(stylesheet_document));
+                        return Err(CanonicalizationError::InvalidStylesheet as Failure);
+                    }
+
+                    let result = transform(compiled, document.as_ptr());
+                    xsltFreeStylesheet(compiled);
+                    result
+                }
+            },
+        )
+        .0
+    });
+
+    result
+        .map_err(|error| error(diagnostics))?
+        .canonicalize(options)
+}
+
+/// Apply `stylesheet` to `document`, with everything libxslt has security preferences for forbidden
+///
+/// # Safety
+///
+/// Both must be valid
+unsafe fn transform(stylesheet: StylesheetPtr, document: xmlDocPtr) -> Result<Document, Failure> {
+    let context = xsltNewTransformContext(stylesheet, document);
+    if context.is_null() {
+        return Err(CanonicalizationError::TransformFailed);
+    }
+    let preferences = xsltNewSecurityPrefs();
+    let secured = !preferences.is_null()
+        && SECURITY_OPTIONS
+            .into_iter()
+            .all(|option| xsltSetSecurityPrefs(preferences, option, Some(xsltSecurityForbid)) == 0)
+        && xsltSetCtxtSecurityPrefs(preferences, context) == 0;
+
+    let result = if secured {
+        xsltApplyStylesheetUser(stylesheet, document, null(), null(), null_mut(), context)
+    } else {
+        null_mut()
+    };
+
+    xsltFreeTransformContext(context);
+    if !preferences.is_null() {
+        xsltFreeSecurityPrefs(preferences);
+    }
+
+    if result.is_null() {
+        return Err(CanonicalizationError::TransformFailed);
+    }
+    Ok(Document::from_raw(result))
+}
+
+/// Run `f`, collecting what libxslt and libxml2 report on the current thread in the meantime
+///
+/// libxslt only prints its messages, so they're printed into memory and split into diagnostics, after those of libxml2.
+fn collect_xslt_diagnostics<T>(f: impl FnOnce() -> T) -> (T, Vec<Diagnostic>) {
+    let Some(printer) = Printer::install() else {
+        return diagnostics::collect_diagnostics(f);
+    };
+    let (result, mut diagnostics) = diagnostics::collect_diagnostics(f);
+    diagnostics.extend(printed_diagnostics(&printer.finish()));
+    (result, diagnostics)
+}
+
+/// libxml2's generic error stream of the current thread, printing into memory until dropped
+struct Printer {
+    file: *mut c_void,
+    /// Where `open_memstream` keeps the printed text and its length, boxed for they mustn't move
+    output: Box<(*mut c_char, usize)>,
+    previous_handler: xmlGenericErrorFunc,
+    previous_context: *mut c_void,
+}
+
+impl Printer {
+    fn install() -> Option<Self> {
+        static INSTALLED: Once = Once::new();
+
+        // SAFETY: the stream is closed and its output freed on drop, and the previous handler restored
+        unsafe {
+            let mut output = Box::new((null_mut(), 0));
+            let file = open_memstream(&mut output.0, &mut output.1);
+            if file.is_null() {
+                return None;
+            }
+            let printer = Printer {
+                file,
+                output,
+                previous_handler: *__xmlGenericError(),
+                previous_context: *__xmlGenericErrorContext(),
+            };
+            xmlSetGenericErrorFunc(file, None);
+            // libxml2's default handler prints to the stream of the current thread, whatever context it's given
+            let default_handler = *__xmlGenericError();
+            INSTALLED.call_once(|| xsltSetGenericErrorFunc(null_mut(), default_handler));
+            Some(printer)
+        }
+    }
+
+    /// Everything printed since [Printer::install]
+    fn finish(mut self) -> String {
+        self.close();
+        if self.output.0.is_null() {
+            return String::new();
+        }
+        // SAFETY: closing the stream NUL-terminated its output
+        unsafe { CStr::from_ptr(self.output.0) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn close(&mut self) {
+        if self.file.is_null() {
+            return;
+        }
+        // SAFETY: the stream is only closed once
+        unsafe {
+            xmlSetGenericErrorFunc(self.previous_context, self.previous_handler);
+            fclose(self.file);
+        }
+        self.file = null_mut();
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.close();
+        // SAFETY: `open_memstream` allocated the output with malloc, and nothing refers to it anymore
+        unsafe { free(self.output.0 as *mut c_void) };
+    }
+}
+
+/// The messages libxslt printed, one diagnostic for each error or warning
+///
+/// libxslt prints a line like `runtime error: file  line 2 element copy-of` before each, with the message on the lines
+/// after it. Lines printed before the first one (e.g. by `<xsl:message>`) each become an error of their own.
+fn printed_diagnostics(printed: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+    let mut in_message = false;
+    for line in printed
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+    {
+        let kind = line
+            .strip_prefix("compilation ")
+            .or_else(|| line.strip_prefix("runtime "));
+        let level_and_context = kind.and_then(|kind| {
+            [
+                ("error", DiagnosticLevel::Error),
+                ("warning", DiagnosticLevel::Warning),
+            ]
+            .into_iter()
+            .find_map(|(name, level)| Some((level, kind.strip_prefix(name)?)))
+            .filter(|(_, context)| context.is_empty() || context.starts_with(':'))
+        });
+
+        match (level_and_context, diagnostics.last_mut()) {
+            (Some((level, context)), _) => {
+                let line = context
+                    .split_once(" line ")
+                    .and_then(|(_, rest)| rest.split(' ').next()?.parse().ok())
+                    .filter(|&line| line > 0);
+                diagnostics.push(printed_diagnostic(level, String::new(), line));
+                in_message = true;
+            }
+            (None, Some(diagnostic)) if in_message => {
+                if !diagnostic.message.is_empty() {
+                    diagnostic.message.push('\n');
+                }
+                diagnostic.message.push_str(line);
+            }
+            (None, _) => {
+                diagnostics.push(printed_diagnostic(
+                    DiagnosticLevel::Error,
+                    line.to_owned(),
+                    None,
+                ));
+            }
+        }
+    }
+    diagnostics
+}
+
+fn printed_diagnostic(level: DiagnosticLevel, message: String, line: Option<u32>) -> Diagnostic {
+    Diagnostic {
+        level,
+        // libxslt's messages have no code
+        code: 0,
+        domain: xmlErrorDomain_XML_FROM_XSLT as i32,
+        message,
+        line,
+        column: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonicalize_xml;
+
+    const IDENTITY: &str = r#"<xsl:stylesheet version="1.0" xmlns:xsl="http://www.w3.org/1999/XSL/Transform">
+  <xsl:template match="@*|node()"><xsl:copy><xsl:apply-templates select="@*|node()"/></xsl:copy></xsl:template>
+</xsl:stylesheet>"#;
+
+    #[test]
+    fn identity() {
+        let input = r#"<a xmlns="urn:a" xmlns:x="urn:x" b='1' x:c="2"><!-- comment --><d>text &amp; more</d><e/></a>"#;
+
+        for options in [
+            CanonicalizationOptions::default(),
+            CanonicalizationOptions {
+                keep_comments: true,
+                ..Default::default()
+            },
+        ] {
+            assert_eq!(
+                canonicalize_xslt(input, IDENTITY, options.clone()).unwrap(),
+                canonicalize_xml(input, options).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn nothing_is_loaded() {
+        let dir = std::env::temp_dir().join(format!("xml_c14n_xslt_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let local = dir.join("local.xml");
+        std::fs::write(&local, "<secret/>").unwrap();
+        let local = local.to_str().unwrap();
+
+        let copy_of = |uri: &str| {
+            format!(
+                r#"<xsl:stylesheet version="1.0" xmlns:xsl="http://www.w3.org/1999/XSL/Transform">
+  <xsl:template match="/"><r><xsl:copy-of select="document('{uri}')"/></r></xsl:template>
+</xsl:stylesheet>"#
+            )
+        };
+        for uri in [local, "http://127.0.0.1:9/remote.xml"] {
+            let Err(CanonicalizationError::TransformFailed(diagnostics)) =
+                canonicalize_xslt("<a/>", &copy_of(uri), Default::default())
+            else {
+                panic!("{uri} was loaded");
+            };
+            assert!(
+                diagnostics
+                    .iter()
+                    .any(|diagnostic| diagnostic.message.ends_with(&format!("{uri} refused"))),
+                "{diagnostics:?}"
+            );
+        }
+
+        let include = format!(
+            r#"<xsl:stylesheet version="1.0" xmlns:xsl="http://www.w3.org/1999/XSL/Transform"><xsl:include href="{local}"/></xsl:stylesheet>"#
+        );
+        assert!(matches!(
+            canonicalize_xslt("<a/>", &include, Default::default()),
+            Err(CanonicalizationError::InvalidStylesheet(_))
+        ));
+
+        let written = dir.join("written.xml");
+        let write = format!(
+            r#"<xsl:stylesheet version="1.0" xmlns:xsl="http://www.w3.org/1999/XSL/Transform" xmlns:exsl="http://exslt.org/common" extension-element-prefixes="exsl">
+  <xsl:template match="/"><exsl:document href="{}"><leak/></exsl:document><r/></xsl:template>
+</xsl:stylesheet>"#,
+            written.to_str().unwrap()
+        );
+        assert!(canonicalize_xslt("<a/>", &write, Default::default()).is_err());
+        assert!(!written.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let Err(CanonicalizationError::InvalidStylesheet(diagnostics)) =
+            canonicalize_xslt("<a/>", "<not-a-stylesheet/>", Default::default())
+        else {
+            panic!("not a stylesheet, but compiled");
+        };
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                level: DiagnosticLevel::Error,
+                code: 0,
+                domain: 22,
+                message: "xsltParseStylesheetProcess : document is not a stylesheet".to_owned(),
+                line: Some(1),
+                column: None,
+            }]
+        );
+        assert!(matches!(
+            canonicalize_xslt("<a/>", "<xsl:stylesheet", Default::default()),
+            Err(CanonicalizationError::Parse(_))
+        ));
+
+        let terminate = r#"<xsl:stylesheet version="1.0" xmlns:xsl="http://www.w3.org/1999/XSL/Transform">
+  <xsl:template match="/"><xsl:message terminate="yes">stop</xsl:message></xsl:template>
+</xsl:stylesheet>"#;
+        let Err(CanonicalizationError::TransformFailed(diagnostics)) =
+            canonicalize_xslt("<a/>", terminate, Default::default())
+        else {
+            panic!("transform didn't terminate");
+        };
+        assert_eq!(diagnostics[0].message, "stop");
+
+        let limited = CanonicalizationOptions {
+            max_input_bytes: Some(16),
+            ..Default::default()
+        };
+        assert!(matches!(
+            canonicalize_xslt("<a/>", IDENTITY, limited),
+            Err(CanonicalizationError::InputTooLarge { .. })
+        ));
+    }
+}
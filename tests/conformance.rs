@@ -0,0 +1,91 @@
+//! Runs every conformance case listed in `src/samples/manifest.txt`.
+//!
+//! To add a case, drop the input and expected output files into `src/samples` and add a line to the manifest.
+
+use std::fs;
+use std::path::Path;
+use xml_c14n::{canonicalize_xml, CanonicalizationMode, CanonicalizationOptions};
+
+/// A single line of the manifest
+struct Case {
+    input: String,
+    expected: String,
+    options: CanonicalizationOptions,
+}
+
+fn parse_case(line: &str) -> Case {
+    let mut fields = line.split_whitespace();
+    let mut next_field = |name| {
+        fields
+            .next()
+            .unwrap_or_else(|| panic!("missing {name} in manifest line `{line}`"))
+    };
+
+    let input = next_field("input").to_owned();
+    let expected = next_field("expected output").to_owned();
+    let mode = match next_field("mode") {
+        "1.0" => CanonicalizationMode::Canonical1_0,
+        "exclusive-1.0" => CanonicalizationMode::ExclusiveCanonical1_0,
+        "1.1" => CanonicalizationMode::Canonical1_1,
+        other => panic!("unknown mode `{other}` in manifest line `{line}`"),
+    };
+    let keep_comments = match next_field("comment handling") {
+        "comments" => true,
+        "no-comments" => false,
+        other => panic!("unknown comment handling `{other}` in manifest line `{line}`"),
+    };
+    let inclusive_ns_prefixes = fields.map(str::to_owned).collect();
+
+    Case {
+        input,
+        expected,
+        options: CanonicalizationOptions {
+            mode,
+            keep_comments,
+            inclusive_ns_prefixes,
+            ..Default::default()
+        },
+    }
+}
+
+#[test]
+fn conformance_manifest() {
+    let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/samples");
+    let manifest = fs::read_to_string(samples.join("manifest.txt")).unwrap();
+
+    let cases: Vec<Case> = manifest
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_case)
+        .collect();
+    assert!(!cases.is_empty(), "manifest lists no cases");
+
+    let mut failures = vec![];
+    for case in &cases {
+        let input = fs::read_to_string(samples.join(&case.input)).unwrap();
+        let expected = fs::read_to_string(samples.join(&case.expected)).unwrap();
+        // Canonical output never ends with a newline, but fixture files often do
+        let expected = expected.trim_end_matches('\n');
+
+        match canonicalize_xml(&input, case.options.clone()) {
+            Ok(canonicalized) if canonicalized == expected => {}
+            Ok(canonicalized) => failures.push(format!(
+                "{} -> {} ({:?}): expected\n{expected}\ngot\n{canonicalized}",
+                case.input, case.expected, case.options
+            )),
+            Err(err) => failures.push(format!(
+                "{} -> {} ({:?}): {err}",
+                case.input, case.expected, case.options
+            )),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} conformance cases failed:\n\n{}",
+        failures.len(),
+        cases.len(),
+        failures.join("\n\n")
+    );
+}
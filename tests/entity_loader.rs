@@ -0,0 +1,30 @@
+//! Checks that `canonicalize_with_entity_loader` keeps loading through its closure after something else replaces
+//! libxml2's external entity loader.
+//!
+//! A binary of its own, as replacing the loader, which is global, would affect whatever tests run in parallel.
+
+use libxml::bindings::{xmlNoNetExternalEntityLoader, xmlSetExternalEntityLoader};
+use xml_c14n::canonicalize_with_entity_loader;
+
+#[test]
+fn replaced_loader() {
+    let path = std::env::temp_dir().join(format!("xml_c14n-entity-{}.txt", std::process::id()));
+    std::fs::write(&path, "secret").unwrap();
+    let document = format!(
+        r#"<!DOCTYPE a [<!ENTITY e SYSTEM "file://{}">]><a>&e;</a>"#,
+        path.display()
+    );
+    let canonicalize = || {
+        canonicalize_with_entity_loader(&document, Default::default(), |_, _| {
+            Some(b"from closure".to_vec())
+        })
+    };
+
+    assert_eq!(canonicalize().unwrap(), "<a>from closure</a>");
+    // SAFETY: setting the loader has no preconditions
+    unsafe { xmlSetExternalEntityLoader(Some(xmlNoNetExternalEntityLoader)) };
+    let canonicalized = canonicalize();
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(canonicalized.unwrap(), "<a>from closure</a>");
+}
@@ -0,0 +1,34 @@
+//! Checks that canonicalizing doesn't leak libxml2's output buffers.
+//!
+//! A binary of its own, as the resident set size it measures would also grow with whatever tests run in parallel.
+
+#![cfg(target_os = "linux")]
+
+use xml_c14n::{canonicalize_xml, canonicalize_xml_to_bytes};
+
+/// Resident set size of this process in bytes
+fn resident_bytes() -> usize {
+    let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+    let pages: usize = statm.split(' ').nth(1).unwrap().parse().unwrap();
+    pages * 4096
+}
+
+#[test]
+fn output_buffers_are_freed() {
+    let input = format!("<a>{}</a>", "<b c='d'>text</b>".repeat(16));
+    let canonicalize = || {
+        for _ in 0..5_000 {
+            canonicalize_xml_to_bytes(&input, Default::default()).unwrap();
+            canonicalize_xml(&input, Default::default()).unwrap();
+        }
+    };
+
+    canonicalize();
+    let before = resident_bytes();
+    for _ in 0..10 {
+        canonicalize();
+    }
+    // leaking the ~300 byte output of 100k canonicalizations would take over 30 MB
+    let growth = resident_bytes().saturating_sub(before);
+    assert!(growth < 8 << 20, "grew by {growth} bytes");
+}